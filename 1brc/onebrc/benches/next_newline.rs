@@ -45,6 +45,15 @@ pub fn next_newline_ptr(mut p: *const u8) -> *const u8 {
     }
 }
 
+#[inline]
+pub fn next_newline_lib(bytes: &[u8], prev: usize) -> usize {
+    let base = bytes.as_ptr();
+    unsafe {
+        let end = base.add(bytes.len());
+        onebrc::swar::scan_to_byte_bounded(base.add(prev), end, b'\n').offset_from(base) as usize
+    }
+}
+
 fn bench_fn(c: &mut Criterion) {
     let data = b"The quick brown fox jumps over the lazy dog\n                   ";
 
@@ -53,6 +62,7 @@ fn bench_fn(c: &mut Criterion) {
     assert_eq!(next_newline(data, 0), unsafe {
         next_newline_ptr(p).offset_from(p) as usize
     });
+    assert_eq!(next_newline(data, 0), next_newline_lib(data, 0));
 
     let mut g = c.benchmark_group("next_newline");
     g.bench_function("safe", |b| b.iter(|| next_newline(black_box(data), 0)));
@@ -62,6 +72,7 @@ fn bench_fn(c: &mut Criterion) {
     g.bench_function("unsafe", |b| {
         b.iter(|| next_newline_ptr(black_box(data.as_ptr())))
     });
+    g.bench_function("lib", |b| b.iter(|| next_newline_lib(black_box(data), 0)));
     g.finish();
 }
 
@@ -0,0 +1,16 @@
+//! AVX2 intrinsics throughout, so x86_64 only. On other targets the binary
+//! builds but refuses to run.
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "x86_64")]
+fn main() {
+    x86_64::main()
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn main() {
+    eprintln!("austindonisan needs an x86_64 CPU with AVX2");
+    std::process::exit(1);
+}
@@ -1,14 +1,18 @@
 #![allow(unsafe_op_in_unsafe_fn)]
 use libc::{
     CPU_COUNT, CPU_ISSET, CPU_SET, CPU_ZERO, MADV_HUGEPAGE, MADV_POPULATE_WRITE, MAP_ANONYMOUS,
-    MAP_FIXED, MAP_PRIVATE, MAP_SHARED, O_RDONLY, POLLIN, PROT_READ, PROT_WRITE, c_void, close,
-    fork, fstat, madvise, mmap, open, pipe, poll, pollfd, qsort_r, read, sched_getaffinity,
-    sched_setaffinity, stat, wait, write,
+    MAP_FIXED, MAP_PRIVATE, MAP_SHARED, POLLIN, PROT_READ, PROT_WRITE, c_void, close, fork, fstat,
+    madvise, mmap, pipe, poll, pollfd, qsort_r, read, sched_getaffinity, sched_setaffinity, stat,
+    wait, write,
 };
+use onebrc::cli::{Args, DEFAULT_INPUT};
+use onebrc::report::Format;
+use onebrc::{Report, StationStats};
 use std::arch::x86_64::*;
 use std::env;
 use std::io::{self, Write};
 use std::mem::{self, size_of};
+use std::os::fd::IntoRawFd;
 use std::ptr;
 
 const UNMAP: bool = false;
@@ -216,6 +220,9 @@ fn extract_sum(v: i64) -> i64 {
 
 const LONG_CITY_SENTINEL: i32 = 0xFACADE00u32 as i32;
 
+#[repr(align(32))]
+struct Lanes<T>(T);
+
 #[repr(align(32))]
 struct MaskedDummy([u8; 32]);
 static MASKED_DUMMY: MaskedDummy = MaskedDummy([
@@ -228,20 +235,29 @@ static MASKED_DUMMY: MaskedDummy = MaskedDummy([
 struct CityMask([i64; 8]);
 static CITY_MASK: CityMask = CityMask([-1, -1, -1, -1, 0, 0, 0, 0]);
 
-fn main() {
+pub fn main() {
+    // Built on AVX2 intrinsics throughout; see onebrc::kernel for the
+    // candidates that pick a kernel at runtime.
+    if !is_x86_feature_detected!("avx2") {
+        eprintln!("austindonisan needs a CPU with AVX2");
+        std::process::exit(1);
+    }
     unsafe {
-        let args: Vec<String> = env::args().collect();
-        if args.len() < 3 || args.len() > 4 {
-            eprintln!("Usage: 1brc file workers [warmup]");
+        // Forks its own worker tree, so --no-fork and --chunk-size do not apply.
+        let warmup = env::args().any(|a| a == "--warmup");
+        let args = Args::from_args(env::args().filter(|a| a != "--warmup"), DEFAULT_INPUT);
+        if let Err(e) = args.redirect_output() {
+            eprintln!("Error opening output: {e}");
             std::process::exit(1);
         }
 
-        let filename = &args[1];
-        let fd = open(filename.as_ptr() as *const i8, O_RDONLY);
-        if fd == -1 {
-            perror(b"Error opening file\0".as_ptr() as *const i8);
-            std::process::exit(1);
-        }
+        let fd = match args.open_input() {
+            Ok(file) => file.into_raw_fd(),
+            Err(e) => {
+                eprintln!("Error opening file: {e}");
+                std::process::exit(1);
+            }
+        };
 
         let mut file_stat: stat = mem::zeroed();
         if fstat(fd, &mut file_stat) == -1 {
@@ -250,18 +266,12 @@ fn main() {
             std::process::exit(1);
         }
 
-        let mut num_workers: i32 = args[2].parse().unwrap_or(0);
+        let mut num_workers = args.threads.min(i32::MAX as usize) as i32;
         if !(1..=256).contains(&num_workers) {
             eprintln!("workers must be between 1 and 256");
             std::process::exit(1);
         }
 
-        let warmup = if args.len() == 4 {
-            args[3].parse::<i32>().unwrap_or(0) != 0
-        } else {
-            false
-        };
-
         if (file_stat.st_size - 1) as usize / PAGE_SIZE < num_workers as usize {
             num_workers = (file_stat.st_size as usize / PAGE_SIZE + 1) as i32;
         }
@@ -295,7 +305,14 @@ fn main() {
             results as *mut c_void,
         );
 
-        print_results(results);
+        if args.format == Format::Challenge {
+            print_results(results);
+        } else {
+            let mut out = io::stdout().lock();
+            results_to_report(results)
+                .write_to(&mut out, args.format)
+                .unwrap();
+        }
     }
 }
 
@@ -449,6 +466,8 @@ unsafe fn process(
                 }
                 std::process::exit(0);
             }
+            // Only the child writes, so a child that dies hangs up the pipe.
+            close(fd_pipes[i as usize][1]);
             new_id += n;
         }
 
@@ -466,17 +485,19 @@ unsafe fn process(
                         buffer.as_mut_ptr() as *mut c_void,
                         buffer.len(),
                     );
-                    if num_bytes > 0 {
-                        children_finished += 1;
-                        merge(out, child_results[i as usize]);
-                        poll_fds[i as usize].fd = -1;
+                    if num_bytes <= 0 {
+                        eprintln!("worker exited without its results");
+                        std::process::exit(1);
                     }
+                    children_finished += 1;
+                    merge(out, child_results[i as usize]);
+                    poll_fds[i as usize].fd = -1;
                 } else if (poll_fds[i as usize].revents
                     & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL))
                     != 0
                 {
-                    children_finished += 1;
-                    poll_fds[i as usize].fd = -1;
+                    eprintln!("worker exited without its results");
+                    std::process::exit(1);
                 }
             }
         }
@@ -627,8 +648,9 @@ unsafe fn find_next_row(data: *const c_void, offset: u32) -> u32 {
 
 unsafe fn process_chunk(base: *const c_void, offsets: *const u32, hash_out: *mut Hash) {
     let mut hash = *hash_out;
-    let mut nums = [0u64; STRIDE];
-    let mut starts = [0u32; STRIDE];
+    // Loaded and stored with aligned AVX2 moves.
+    let mut nums = Lanes([0u64; STRIDE]);
+    let mut starts = Lanes([0u32; STRIDE]);
     let mut check_finished: bool;
 
     let mut starts_v = _mm256_loadu_si256(offsets as *const __m256i);
@@ -638,7 +660,7 @@ unsafe fn process_chunk(base: *const c_void, offsets: *const u32, hash_out: *mut
     let mut at_end_mask = _mm256_cmpeq_epi32(starts_v, ends_v);
     check_finished = _mm256_testz_si256(at_end_mask, at_end_mask) == 0;
 
-    _mm256_store_si256(starts.as_mut_ptr() as *mut __m256i, starts_v);
+    _mm256_store_si256(starts.0.as_mut_ptr() as *mut __m256i, starts_v);
 
     let dummy = _mm256_load_si256(MASKED_DUMMY.0.as_ptr() as *const __m256i);
     _mm256_store_si256(
@@ -662,17 +684,17 @@ unsafe fn process_chunk(base: *const c_void, offsets: *const u32, hash_out: *mut
                 _mm256_castsi256_ps(finished_v),
             ));
 
-            _mm256_maskstore_epi32(starts.as_mut_ptr() as *mut i32, finished_v, starts_v);
+            _mm256_maskstore_epi32(starts.0.as_mut_ptr() as *mut i32, finished_v, starts_v);
         }
 
-        let raw_city0 = _mm256_loadu_si256(base.add(starts[0] as usize) as *const __m256i);
-        let raw_city1 = _mm256_loadu_si256(base.add(starts[1] as usize) as *const __m256i);
-        let raw_city2 = _mm256_loadu_si256(base.add(starts[2] as usize) as *const __m256i);
-        let raw_city3 = _mm256_loadu_si256(base.add(starts[3] as usize) as *const __m256i);
-        let raw_city4 = _mm256_loadu_si256(base.add(starts[4] as usize) as *const __m256i);
-        let raw_city5 = _mm256_loadu_si256(base.add(starts[5] as usize) as *const __m256i);
-        let raw_city6 = _mm256_loadu_si256(base.add(starts[6] as usize) as *const __m256i);
-        let raw_city7 = _mm256_loadu_si256(base.add(starts[7] as usize) as *const __m256i);
+        let raw_city0 = _mm256_loadu_si256(base.add(starts.0[0] as usize) as *const __m256i);
+        let raw_city1 = _mm256_loadu_si256(base.add(starts.0[1] as usize) as *const __m256i);
+        let raw_city2 = _mm256_loadu_si256(base.add(starts.0[2] as usize) as *const __m256i);
+        let raw_city3 = _mm256_loadu_si256(base.add(starts.0[3] as usize) as *const __m256i);
+        let raw_city4 = _mm256_loadu_si256(base.add(starts.0[4] as usize) as *const __m256i);
+        let raw_city5 = _mm256_loadu_si256(base.add(starts.0[5] as usize) as *const __m256i);
+        let raw_city6 = _mm256_loadu_si256(base.add(starts.0[6] as usize) as *const __m256i);
+        let raw_city7 = _mm256_loadu_si256(base.add(starts.0[7] as usize) as *const __m256i);
 
         let semicolons = _mm256_set1_epi8(';' as i8);
         let mut sc0 =
@@ -711,35 +733,51 @@ unsafe fn process_chunk(base: *const c_void, offsets: *const u32, hash_out: *mut
 
         let masked_city0 = _mm256_and_si256(
             raw_city0,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc0 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc0 as usize) as *const __m256i
+            ),
         );
         let masked_city1 = _mm256_and_si256(
             raw_city1,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc1 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc1 as usize) as *const __m256i
+            ),
         );
         let masked_city2 = _mm256_and_si256(
             raw_city2,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc2 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc2 as usize) as *const __m256i
+            ),
         );
         let masked_city3 = _mm256_and_si256(
             raw_city3,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc3 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc3 as usize) as *const __m256i
+            ),
         );
         let masked_city4 = _mm256_and_si256(
             raw_city4,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc4 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc4 as usize) as *const __m256i
+            ),
         );
         let masked_city5 = _mm256_and_si256(
             raw_city5,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc5 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc5 as usize) as *const __m256i
+            ),
         );
         let masked_city6 = _mm256_and_si256(
             raw_city6,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc6 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc6 as usize) as *const __m256i
+            ),
         );
         let masked_city7 = _mm256_and_si256(
             raw_city7,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc7 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc7 as usize) as *const __m256i
+            ),
         );
 
         let mut masked_city0 = masked_city0;
@@ -756,35 +794,35 @@ unsafe fn process_chunk(base: *const c_void, offsets: *const u32, hash_out: *mut
 
         if _mm256_testz_si256(long_cities, long_cities) == 0 {
             if sc0 == 32 {
-                masked_city0 = process_long(base.add(starts[0] as usize), &mut hash, &mut sc0);
+                masked_city0 = process_long(base.add(starts.0[0] as usize), &mut hash, &mut sc0);
                 semicolons_v = _mm256_insert_epi32(semicolons_v, sc0, 0);
             }
             if sc1 == 32 {
-                masked_city1 = process_long(base.add(starts[1] as usize), &mut hash, &mut sc1);
+                masked_city1 = process_long(base.add(starts.0[1] as usize), &mut hash, &mut sc1);
                 semicolons_v = _mm256_insert_epi32(semicolons_v, sc1, 1);
             }
             if sc2 == 32 {
-                masked_city2 = process_long(base.add(starts[2] as usize), &mut hash, &mut sc2);
+                masked_city2 = process_long(base.add(starts.0[2] as usize), &mut hash, &mut sc2);
                 semicolons_v = _mm256_insert_epi32(semicolons_v, sc2, 2);
             }
             if sc3 == 32 {
-                masked_city3 = process_long(base.add(starts[3] as usize), &mut hash, &mut sc3);
+                masked_city3 = process_long(base.add(starts.0[3] as usize), &mut hash, &mut sc3);
                 semicolons_v = _mm256_insert_epi32(semicolons_v, sc3, 3);
             }
             if sc4 == 32 {
-                masked_city4 = process_long(base.add(starts[4] as usize), &mut hash, &mut sc4);
+                masked_city4 = process_long(base.add(starts.0[4] as usize), &mut hash, &mut sc4);
                 semicolons_v = _mm256_insert_epi32(semicolons_v, sc4, 4);
             }
             if sc5 == 32 {
-                masked_city5 = process_long(base.add(starts[5] as usize), &mut hash, &mut sc5);
+                masked_city5 = process_long(base.add(starts.0[5] as usize), &mut hash, &mut sc5);
                 semicolons_v = _mm256_insert_epi32(semicolons_v, sc5, 5);
             }
             if sc6 == 32 {
-                masked_city6 = process_long(base.add(starts[6] as usize), &mut hash, &mut sc6);
+                masked_city6 = process_long(base.add(starts.0[6] as usize), &mut hash, &mut sc6);
                 semicolons_v = _mm256_insert_epi32(semicolons_v, sc6, 6);
             }
             if sc7 == 32 {
-                masked_city7 = process_long(base.add(starts[7] as usize), &mut hash, &mut sc7);
+                masked_city7 = process_long(base.add(starts.0[7] as usize), &mut hash, &mut sc7);
                 semicolons_v = _mm256_insert_epi32(semicolons_v, sc7, 7);
             }
         }
@@ -802,17 +840,25 @@ unsafe fn process_chunk(base: *const c_void, offsets: *const u32, hash_out: *mut
 
         starts_v = _mm256_add_epi32(starts_v, semicolons_v);
 
-        nums[0] = *(base.add((starts[0] + sc0 as u32) as usize - 2) as *const u64);
-        nums[1] = *(base.add((starts[1] + sc1 as u32) as usize - 2) as *const u64);
-        nums[2] = *(base.add((starts[4] + sc4 as u32) as usize - 2) as *const u64);
-        nums[3] = *(base.add((starts[5] + sc5 as u32) as usize - 2) as *const u64);
-        nums[4] = *(base.add((starts[2] + sc2 as u32) as usize - 2) as *const u64);
-        nums[5] = *(base.add((starts[3] + sc3 as u32) as usize - 2) as *const u64);
-        nums[6] = *(base.add((starts[6] + sc6 as u32) as usize - 2) as *const u64);
-        nums[7] = *(base.add((starts[7] + sc7 as u32) as usize - 2) as *const u64);
-
-        let nums_low = _mm256_load_si256(nums.as_ptr() as *const __m256i);
-        let nums_high = _mm256_load_si256(nums.as_ptr().add(4) as *const __m256i);
+        nums.0[0] =
+            (base.add((starts.0[0] + sc0 as u32) as usize - 2) as *const u64).read_unaligned();
+        nums.0[1] =
+            (base.add((starts.0[1] + sc1 as u32) as usize - 2) as *const u64).read_unaligned();
+        nums.0[2] =
+            (base.add((starts.0[4] + sc4 as u32) as usize - 2) as *const u64).read_unaligned();
+        nums.0[3] =
+            (base.add((starts.0[5] + sc5 as u32) as usize - 2) as *const u64).read_unaligned();
+        nums.0[4] =
+            (base.add((starts.0[2] + sc2 as u32) as usize - 2) as *const u64).read_unaligned();
+        nums.0[5] =
+            (base.add((starts.0[3] + sc3 as u32) as usize - 2) as *const u64).read_unaligned();
+        nums.0[6] =
+            (base.add((starts.0[6] + sc6 as u32) as usize - 2) as *const u64).read_unaligned();
+        nums.0[7] =
+            (base.add((starts.0[7] + sc7 as u32) as usize - 2) as *const u64).read_unaligned();
+
+        let nums_low = _mm256_load_si256(nums.0.as_ptr() as *const __m256i);
+        let nums_high = _mm256_load_si256(nums.0.as_ptr().add(4) as *const __m256i);
 
         let low_words = _mm256_castps_si256(_mm256_shuffle_ps(
             _mm256_castsi256_ps(nums_low),
@@ -856,7 +902,7 @@ unsafe fn process_chunk(base: *const c_void, offsets: *const u32, hash_out: *mut
 
         starts_v = _mm256_add_epi32(starts_v, minus_mask_shift);
         starts_v = _mm256_sub_epi32(starts_v, newline_mask_shift);
-        _mm256_store_si256(starts.as_mut_ptr() as *mut __m256i, starts_v);
+        _mm256_store_si256(starts.0.as_mut_ptr() as *mut __m256i, starts_v);
 
         at_end_mask = _mm256_cmpeq_epi32(starts_v, ends_v);
         check_finished = _mm256_testz_si256(at_end_mask, at_end_mask) == 0;
@@ -1015,27 +1061,36 @@ unsafe fn process_long(start: *const c_void, h: *mut Hash, sc_out: *mut i32) ->
     let sc2 = _mm256_movemask_epi8(_mm256_cmpeq_epi8(seg2, semicolons)).trailing_zeros() as i32;
     let sc3 = _mm256_movemask_epi8(_mm256_cmpeq_epi8(seg3, semicolons)).trailing_zeros() as i32;
 
-    let mut hash_val = hash_long(*(start as *const i64), *(start.add(8) as *const i64));
+    let mut hash_val = hash_long(
+        (start as *const i64).read_unaligned(),
+        (start.add(8) as *const i64).read_unaligned(),
+    );
 
     if sc1 < 32 {
         *sc_out = 32 + sc1;
         seg1 = _mm256_and_si256(
             seg1,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc1 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc1 as usize) as *const __m256i
+            ),
         );
         hash_val = insert_city_long1(h, hash_val, seg0, seg1);
     } else if sc2 < 32 {
         *sc_out = 64 + sc2;
         seg2 = _mm256_and_si256(
             seg2,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc2 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc2 as usize) as *const __m256i
+            ),
         );
         hash_val = insert_city_long2(h, hash_val, seg0, seg1, seg2);
     } else {
         *sc_out = 96 + sc3;
         seg3 = _mm256_and_si256(
             seg3,
-            _mm256_loadu_si256(CITY_MASK.0.as_ptr().add(32 - sc3 as usize) as *const __m256i),
+            _mm256_loadu_si256(
+                (CITY_MASK.0.as_ptr() as *const u8).add(32 - sc3 as usize) as *const __m256i
+            ),
         );
         hash_val = insert_city_long3(h, hash_val, seg0, seg1, seg2, seg3);
     }
@@ -1445,6 +1500,38 @@ unsafe fn convert_hash_to_results(hash: *mut Hash, out: *mut Results) {
     }
 }
 
+unsafe fn city_name(results: *mut Results, row: &ResultsRow) -> String {
+    let bytes = if city_is_long(row.city) {
+        (*(*results).long_cities.add(row.city.long_ref.index as usize))
+            .bytes
+            .as_ptr()
+    } else {
+        row.city.short_city.bytes.as_ptr()
+    };
+    std::ffi::CStr::from_ptr(bytes as *const i8)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The results as a library [`Report`], for the structured output formats.
+unsafe fn results_to_report(results: *mut Results) -> Report {
+    let mut report = Report::default();
+    for i in 0..(*results).num_cities {
+        let ref_val = *(*results).refs.add(i as usize);
+        let row = &*(*results)
+            .rows
+            .add(ref_val.offset as usize / SHORT_CITY_LENGTH);
+        let stats = StationStats {
+            min: row.min,
+            max: row.max,
+            count: row.count as usize,
+            total: row.sum,
+        };
+        report.merge_station(&city_name(results, row), &stats);
+    }
+    report
+}
+
 unsafe fn print_results(results: *mut Results) {
     let mut buffer = vec![0u8; MAX_CITIES * 150];
     let mut pos = 0;
@@ -1457,17 +1544,9 @@ unsafe fn print_results(results: *mut Results) {
             .rows
             .add(ref_val.offset as usize / SHORT_CITY_LENGTH);
 
-        let bytes = if city_is_long(row.city) {
-            (*(*results).long_cities.add(row.city.long_ref.index as usize))
-                .bytes
-                .as_ptr()
-        } else {
-            row.city.short_city.bytes.as_ptr()
-        };
-
         let s = format!(
             "{}={:.1}/{:.1}/{:.1}",
-            std::ffi::CStr::from_ptr(bytes as *const i8).to_string_lossy(),
+            city_name(results, row),
             row.min as f32 * 0.1,
             (row.sum as f32 * 0.1 / row.count as f32 * 10.0).round() * 0.1,
             row.max as f32 * 0.1
@@ -1,11 +1,10 @@
 use memchr::memchr;
 use memmap2::Mmap;
+use onebrc::cli::{Args, DEFAULT_INPUT};
 use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{self, BufRead, Write};
-use std::process::{Command, Stdio};
+use std::io::{self, Write};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::{env, thread};
+use std::thread;
 
 const CHUNK_SIZE: usize = 1 << 20;
 
@@ -15,35 +14,20 @@ const CHUNK_SIZE: usize = 1 << 20;
 /// 4. get rid of the complicated string comparison
 
 fn main() -> io::Result<()> {
-    let is_worker = std::env::args().any(|a| a == "--worker");
-    if is_worker {
-        run_worker()
+    let args = Args::from_env(DEFAULT_INPUT);
+    args.redirect_output()?;
+    if args.is_worker() {
+        run_worker(&args)
     } else {
-        run_parent()
+        args.run_parent()
     }
 }
 
-fn run_parent() -> io::Result<()> {
-    let exe = std::env::current_exe()?;
-    let child = Command::new(exe)
-        .arg("--worker")
-        .stdout(Stdio::piped())
-        .spawn()?;
-
-    let out = child.stdout.expect("expected stdout to be present");
-    let mut line = String::new();
-    let mut reader = io::BufReader::new(out);
-    reader.read_line(&mut line)?;
-    print!("{line}");
-    // std::process::exit(0);
-    Ok(())
-}
-
-fn run_worker() -> io::Result<()> {
-    let file = File::open("../java-orig/measurements.txt")?;
+fn run_worker(args: &Args) -> io::Result<()> {
+    let file = args.open_input()?;
     let data = unsafe { Mmap::map(&file)? };
 
-    println!("{}", total_lines(&data));
+    println!("{}", total_lines(&data, args));
     io::stdout().flush().expect("expected flush to work");
     assert_eq!(b'\n', data[data.len() - 1]);
     // std::process::exit(0);
@@ -317,7 +301,7 @@ fn snap_to_newline(data: &[u8], end: usize) -> usize {
     }
 }
 
-fn claim_chunk(data: &[u8], next: &AtomicUsize) -> Option<(usize, usize)> {
+fn claim_chunk(data: &[u8], next: &AtomicUsize, chunk_size: usize) -> Option<(usize, usize)> {
     let len = data.len();
     loop {
         let start = next.load(Ordering::Relaxed);
@@ -325,7 +309,7 @@ fn claim_chunk(data: &[u8], next: &AtomicUsize) -> Option<(usize, usize)> {
             return None;
         }
 
-        let end = usize::min(start + CHUNK_SIZE, len);
+        let end = usize::min(start + chunk_size, len);
         let end = snap_to_newline(data, end);
 
         assert_eq!(data[end - 1], b'\n');
@@ -339,11 +323,9 @@ fn claim_chunk(data: &[u8], next: &AtomicUsize) -> Option<(usize, usize)> {
     }
 }
 
-fn total_lines(data: &[u8]) -> String {
-    let num_threads = match env::var("NUM_THREADS") {
-        Ok(v) => v.parse::<usize>().unwrap(),
-        Err(_) => thread::available_parallelism().map_or(1, |n| n.get()),
-    };
+fn total_lines(data: &[u8], args: &Args) -> String {
+    let num_threads = args.threads;
+    let chunk_size = args.chunk_size.unwrap_or(CHUNK_SIZE);
     eprintln!("total threads: {}", num_threads);
 
     let next = AtomicUsize::new(0);
@@ -354,7 +336,7 @@ fn total_lines(data: &[u8]) -> String {
         for _ in 0..num_threads {
             handles.push(s.spawn(|| {
                 let mut statistics = NameTable::with_capacity(data, 10000);
-                while let Some((start, end)) = claim_chunk(data, &next) {
+                while let Some((start, end)) = claim_chunk(data, &next, chunk_size) {
                     assert_eq!(data[end - 1], b'\n');
                     // eprintln!("{} - {}", start, end);
                     chunk_statistics(data, start, end, &mut statistics);
@@ -1,6 +1,6 @@
 use memmap2::MmapOptions;
+use onebrc::cli::Args;
 use std::collections::BTreeMap;
-use std::fs::File;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 
@@ -88,12 +88,12 @@ impl<'a> Scanner<'a> {
 
     fn get_long_at(&self, pos: usize) -> u64 {
         if pos + 8 <= self.data.len() {
-            u64::from_ne_bytes(self.data[pos..pos + 8].try_into().unwrap())
+            u64::from_le_bytes(self.data[pos..pos + 8].try_into().unwrap())
         } else if pos < self.data.len() {
             let mut bytes = [0u8; 8];
             let len = self.data.len() - pos;
             bytes[..len].copy_from_slice(&self.data[pos..]);
-            u64::from_ne_bytes(bytes)
+            u64::from_le_bytes(bytes)
         } else {
             0
         }
@@ -106,7 +106,7 @@ impl<'a> Scanner<'a> {
 
 fn next_new_line(data: &[u8], mut prev: usize) -> usize {
     while prev + 8 <= data.len() {
-        let current_word = u64::from_ne_bytes(data[prev..prev + 8].try_into().unwrap());
+        let current_word = u64::from_le_bytes(data[prev..prev + 8].try_into().unwrap());
         let input = current_word ^ 0x0A0A0A0A0A0A0A0Au64;
         let pos = (input.wrapping_sub(0x0101010101010101u64)) & !input & 0x8080808080808080u64;
         if pos != 0 {
@@ -138,6 +138,9 @@ fn hash_to_index(hash: u64, size: usize) -> usize {
     (hash_as_int as usize) & (size - 1)
 }
 
+/// The temperature in tenths. `number_word` is the little-endian word that
+/// starts after the ';' (first character in the low byte), and
+/// `decimal_sep_pos` the bit index of the '.' in it.
 fn convert_into_number(decimal_sep_pos: u32, number_word: u64) -> i64 {
     let shift = 28 - decimal_sep_pos;
     let signed = !((number_word << 59) as i64) >> 63;
@@ -268,17 +271,18 @@ fn record(existing_result: &mut ResultEntry, number: i64) {
 
 fn parse_loop(
     counter: &AtomicUsize,
+    segment_size: usize,
     data: &[u8],
     collected_results: &mut Vec<usize>,
     hash_table: &mut [Option<Box<ResultEntry>>],
 ) {
     loop {
-        let current_offset = counter.fetch_add(SEGMENT_SIZE, Ordering::SeqCst);
+        let current_offset = counter.fetch_add(segment_size, Ordering::SeqCst);
         if current_offset >= data.len() {
             return;
         }
 
-        let raw_segment_end = (current_offset + SEGMENT_SIZE).min(data.len().saturating_sub(1));
+        let raw_segment_end = (current_offset + segment_size).min(data.len().saturating_sub(1));
         let segment_end = next_new_line(data, raw_segment_end);
 
         let segment_start = if current_offset == 0 {
@@ -445,18 +449,20 @@ fn parse_loop(
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 || args[1] != "--worker" {
-        spawn_worker();
+    let args = Args::from_env(FILE_PATH);
+    args.redirect_output().expect("Could not redirect output");
+    if !args.is_worker() {
+        args.run_parent().expect("failed to execute child");
         return;
     }
 
-    let file = File::open(FILE_PATH).expect("Could not open file");
+    let file = args.open_input().expect("Could not open file");
     let mmap = unsafe { MmapOptions::new().map(&file).expect("Could not map file") };
     let data = &mmap[..];
     let cursor = AtomicUsize::new(0);
 
-    let num_workers = thread::available_parallelism().unwrap().get();
+    let num_workers = args.threads;
+    let segment_size = args.chunk_size.unwrap_or(SEGMENT_SIZE);
 
     thread::scope(|s| {
         let mut handles = vec![];
@@ -466,7 +472,7 @@ fn main() {
                 let mut hash_table = (0..HASH_TABLE_SIZE)
                     .map(|_| None)
                     .collect::<Vec<Option<Box<ResultEntry>>>>();
-                parse_loop(&cursor, data, &mut collected_results, &mut hash_table);
+                parse_loop(&cursor, segment_size, data, &mut collected_results, &mut hash_table);
                 (collected_results, hash_table)
             }));
         }
@@ -504,12 +510,3 @@ fn main() {
         println!("}}");
     });
 }
-
-fn spawn_worker() {
-    let mut child = std::process::Command::new(std::env::current_exe().unwrap())
-        .arg("--worker")
-        .stdout(std::process::Stdio::inherit())
-        .spawn()
-        .expect("failed to execute child");
-    child.wait().expect("failed to wait on child");
-}
@@ -1,6 +1,5 @@
+use onebrc::cli::{Args, DEFAULT_INPUT};
 use std::{
-    env::args,
-    fs::File,
     sync::{Arc, Mutex},
     thread::{self},
 };
@@ -29,6 +28,7 @@ impl<T> HashTable<T> {
     pub fn insert_or_update(
         &mut self,
         key: impl Hash,
+        same: impl Fn(&T) -> bool,
         modify: impl FnOnce(&mut T),
         provide: impl FnOnce() -> T,
     ) {
@@ -36,9 +36,10 @@ impl<T> HashTable<T> {
             self.resize();
         }
         let index = key.hash() as usize % self.buckets.len();
+        // Different names can share a hash, so only the name itself decides.
         if let Some(record) = self.buckets[index]
             .iter_mut()
-            .find(|(k, _)| k.hash() == key.hash())
+            .find(|(k, v)| k.hash() == key.hash() && same(v))
             .map(|(_, v)| v)
         {
             modify(record);
@@ -153,7 +154,7 @@ impl Result {
         let min = self.min as f64 / 10.0;
         let max = self.max as f64 / 10.0;
         let mean = self.mean as f64 / self.count as f64 / 10.0;
-        format!("{}={:.1}/{:.1}/{:.1}", self.name, min, max, mean)
+        format!("{}={:.1}/{:.1}/{:.1}", self.name, min, mean, max)
     }
 }
 
@@ -185,8 +186,8 @@ impl Chunk {
 
     #[inline(always)]
     fn parse_line(&mut self) -> bool {
-        // Find next semicolon, skipped 3 bytes because town is at least 3 bytes
-        let split_pos = find_next(&self.data, self.position + 3, b';');
+        // Find next semicolon, skipped 1 byte because town is at least 1 byte
+        let split_pos = find_next(&self.data, self.position + 1, b';');
         let name = &self.data[self.position..split_pos];
         // Find next newline, skipped 3 bytes because temp at lest 3 bytes (x.x)
         self.position = find_next(&self.data, split_pos + 3, b'\n') + 1;
@@ -194,14 +195,18 @@ impl Chunk {
         // Simple hash function, stolen from java
         let mut key: u64 = 0;
         for i in 0..name.len() {
-            key *= 31;
-            key += name[i] as u64;
+            key = key.wrapping_mul(31).wrapping_add(name[i] as u64);
         }
         // Update or insert new result
         self.result.insert_or_update(
             key,
+            |r: &Result| r.name.as_bytes() == name,
             |fu: &mut Result| fu.update(value),
-            || Result::new(name),
+            || {
+                let mut fu = Result::new(name);
+                fu.update(value);
+                fu
+            },
         );
         self.position < self.end
     }
@@ -222,21 +227,18 @@ impl Chunk {
 }
 
 fn main() {
-    let file_name = args().nth(1);
-    if file_name.is_none() {
-        eprintln!("Usage: {} <file>", args().next().unwrap());
-        return;
-    }
-    let file_name = file_name.unwrap();
-    let file = File::open(file_name).unwrap();
+    // Always runs in-process; --no-fork is implied.
+    let args = Args::from_env(DEFAULT_INPUT);
+    args.redirect_output().unwrap();
+    let file = args.open_input().unwrap();
 
     // Map file to memory
     let mmaped = unsafe { memmap::Mmap::map(&file).unwrap() };
     let mmaped = Arc::new(mmaped);
 
     // Calculate chunk size for each thread
-    let max_threads: usize = thread::available_parallelism().unwrap().into();
-    let chunk_size = mmaped.len() / max_threads;
+    let max_threads = args.threads;
+    let chunk_size = args.chunk_size.unwrap_or(mmaped.len() / max_threads);
 
     // Split file into chunks by finding newlines at the end of each chunk
     let mut chunks = Vec::new();
@@ -263,6 +265,7 @@ fn main() {
             for (key, value) in chunk.result.key_set() {
                 result.insert_or_update(
                     *key,
+                    |r: &Result| r.name == value.name,
                     |fu: &mut Result| fu.merge(value),
                     || value.clone(),
                 );
@@ -278,9 +281,11 @@ fn main() {
     }
 
     let result = result.lock().unwrap();
+    let mut result = result.key_set().map(|(_, value)| value).collect::<Vec<_>>();
+    result.sort_unstable_by(|a, b| a.name.cmp(&b.name));
     let result = result
-        .key_set()
-        .map(|(_, value)| value.to_string())
+        .into_iter()
+        .map(|value| value.to_string())
         .collect::<Vec<String>>();
     println!("{{{}}}", result.join(", "));
 }
@@ -0,0 +1,93 @@
+use onebrc::generate;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+use std::{env, process, thread};
+
+// Rust port of upstream create_measurements.sh:
+//
+//     onebrc-gen 1000000000 --seed 42 --output /media/measurements/measurements.txt --expected expected.txt
+//
+// The same seed and row count always produce the same file.
+
+const USAGE: &str = "\
+usage: onebrc-gen ROWS [OPTIONS]
+
+  ROWS                 number of rows, e.g. 1000000000 or 1_000_000_000
+  -s, --seed N         random seed (default: 0)
+  -o, --output PATH    measurements file, or - for stdout (default: measurements.txt)
+  -e, --expected PATH  also write the {Abha=...} answer for the generated file
+  -t, --threads N      generator threads (default: all cores)";
+
+struct GenArgs {
+    rows: u64,
+    seed: u64,
+    output: String,
+    expected: Option<String>,
+    threads: usize,
+}
+
+fn parse_args() -> Result<GenArgs, String> {
+    let mut rows = None;
+    let mut seed = 0;
+    let mut output = "measurements.txt".to_string();
+    let mut expected = None;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = |name: &str| argv.next().ok_or_else(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            "-s" | "--seed" => seed = parse_number(&value("--seed")?)?,
+            "-o" | "--output" => output = value("--output")?,
+            "-e" | "--expected" => expected = Some(value("--expected")?),
+            "-t" | "--threads" => threads = parse_number(&value("--threads")?)? as usize,
+            a if a.starts_with('-') => return Err(format!("unknown option {a}")),
+            a if rows.is_none() => rows = Some(parse_number(a)?),
+            a => return Err(format!("unexpected argument {a}")),
+        }
+    }
+
+    Ok(GenArgs {
+        rows: rows.ok_or("missing ROWS")?,
+        seed,
+        output,
+        expected,
+        threads,
+    })
+}
+
+fn parse_number(v: &str) -> Result<u64, String> {
+    v.replace('_', "")
+        .parse()
+        .map_err(|_| format!("invalid number {v:?}"))
+}
+
+fn main() -> io::Result<()> {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("onebrc-gen: {e}\n{USAGE}");
+        process::exit(2);
+    });
+
+    let start = Instant::now();
+    let report = if args.output == "-" {
+        let mut out = io::stdout().lock();
+        generate::write_rows(&mut out, args.rows, args.seed, args.threads)?
+    } else {
+        let mut out = BufWriter::with_capacity(1 << 20, File::create(&args.output)?);
+        let report = generate::write_rows(&mut out, args.rows, args.seed, args.threads)?;
+        out.flush()?;
+        report
+    };
+    eprintln!("wrote {} rows in {:?}", args.rows, start.elapsed());
+
+    if let Some(path) = &args.expected {
+        let mut f = File::create(path)?;
+        writeln!(f, "{report}")?;
+    }
+    Ok(())
+}
@@ -1,10 +1,9 @@
 use memmap2::Mmap;
+use onebrc::cli::Args;
 use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write, stdout};
-use std::process::{Command, Stdio};
+use std::io::{Write, stdout};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::{env, io, process};
+use std::{io, process};
 use std::{sync::Arc, thread};
 
 const FILE: &str = "./measurements.txt";
@@ -19,17 +18,17 @@ unsafe fn madvise(ptr: *const u8, len: usize, advice: i32) {
 }
 
 fn main() -> io::Result<()> {
-    let is_worker = env::args().any(|a| a == "--worker");
-    if !is_worker {
-        return spawn_worker();
+    let args = Args::from_env(FILE);
+    args.redirect_output()?;
+    if !args.is_worker() {
+        args.run_parent()?;
+        process::exit(0);
     }
 
-    let number_of_workers = env::var("NUM_THREADS").map_or(
-        thread::available_parallelism().map_or(1, |n| n.get()),
-        |n| n.parse::<usize>().unwrap(),
-    );
+    let number_of_workers = args.threads;
+    let segment_size = args.chunk_size.unwrap_or(SEGMENT_SIZE as usize);
 
-    let file = File::open(FILE)?;
+    let file = args.open_input()?;
     let mmap = unsafe { Mmap::map(&file)? };
     unsafe {
         madvise(mmap.as_ptr(), mmap.len(), libc::MADV_SEQUENTIAL);
@@ -41,7 +40,7 @@ fn main() -> io::Result<()> {
         let mut handles = Vec::with_capacity(number_of_workers);
         for _ in 0..number_of_workers {
             let cursor = Arc::clone(&cursor);
-            handles.push(s.spawn(move || parse_loop(data, &cursor)));
+            handles.push(s.spawn(move || parse_loop(data, &cursor, segment_size)));
         }
         let mut all_results = Vec::with_capacity(number_of_workers);
         for h in handles {
@@ -70,17 +69,6 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
-fn spawn_worker() -> io::Result<()> {
-    let exe = env::current_exe()?;
-    let mut cmd = Command::new(exe);
-    let mut child = cmd.arg("--worker").stdout(Stdio::piped()).spawn()?;
-    let pipe = child.stdout.take().expect("stdout was piped");
-    let mut result = String::new();
-    BufReader::new(pipe).read_line(&mut result)?;
-    println!("{result}");
-    process::exit(0);
-}
-
 pub struct Result {
     pub first_name_word: u64,
     pub second_name_word: u64,
@@ -138,7 +126,7 @@ impl Result {
     }
 }
 
-fn parse_loop(data: &[u8], cursor: &AtomicUsize) -> Vec<Result> {
+fn parse_loop(data: &[u8], cursor: &AtomicUsize, segment_size: usize) -> Vec<Result> {
     let mut table: Vec<u32> = vec![0; HASH_TABLE_SIZE as usize];
 
     let mut results: Vec<Result> = Vec::with_capacity(MAX_CITIES as usize);
@@ -152,7 +140,7 @@ fn parse_loop(data: &[u8], cursor: &AtomicUsize) -> Vec<Result> {
     }
 
     loop {
-        let current = cursor.fetch_add(SEGMENT_SIZE as usize, Ordering::Relaxed);
+        let current = cursor.fetch_add(segment_size, Ordering::Relaxed);
         if current >= data.len() {
             return results;
         }
@@ -165,7 +153,7 @@ fn parse_loop(data: &[u8], cursor: &AtomicUsize) -> Vec<Result> {
 
         let segment_end_nl = next_newline(
             data,
-            usize::min(data.len() - 1, current + SEGMENT_SIZE as usize),
+            usize::min(data.len() - 1, current + segment_size),
         );
         let segment_end = segment_end_nl + 1;
 
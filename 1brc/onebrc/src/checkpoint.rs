@@ -0,0 +1,153 @@
+//! Checkpoint/resume for long runs over a mmapped file.
+//!
+//! The input is processed in segments of [`Checkpoint::every`] bytes. Within a
+//! segment the threads claim chunks as usual; once every chunk below the
+//! segment end is done, the merged stats and that offset are written to the
+//! checkpoint file (via a temporary file and a rename, so a kill never leaves
+//! a torn one behind). A run that finds a checkpoint for the same input
+//! starts at its offset, and the file is removed once the run completes.
+//!
+//! The stats are stored as a [`partial`] result. The file is tied to its
+//! input by the input length and a hash of the bytes around the offset, and
+//! to the [`Stats`] it was written with.
+
+use crate::chunk::{snap_to_newline, split_tail};
+use crate::metrics::Metrics;
+use crate::partial;
+use crate::report::Report;
+use crate::stats::{Stats, take};
+use crate::{Options, aggregate_range, aggregate_tail};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::PathBuf;
+use xxhash_rust::xxh3::xxh3_64;
+
+const MAGIC: &[u8; 8] = b"1brc-ckp";
+const VERSION: u32 = 3;
+
+/// Default for [`Checkpoint::every`].
+pub const CHECKPOINT_EVERY: usize = 1 << 30;
+
+/// Bytes hashed on either side of the offset to recognise the input.
+const FINGERPRINT_BYTES: usize = 4096;
+
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    /// Bytes of input between two checkpoints.
+    pub every: usize,
+}
+
+/// Like [`aggregate_with`](crate::aggregate_with), resuming from and writing
+/// to `checkpoint`.
+pub fn aggregate_checkpointed<S: Stats + Send>(
+    data: &[u8],
+    options: Options,
+    checkpoint: &Checkpoint,
+) -> io::Result<Report<S>> {
+    let body = split_tail(data);
+    let (mut offset, mut report) = load(checkpoint, data)?.unwrap_or_default();
+    if offset > body {
+        return Err(mismatch(checkpoint, "offset is past the end of the input"));
+    }
+
+    let metrics = Metrics::new("thread");
+    while offset < body {
+        let end = snap_to_newline(&data[..body], (offset + checkpoint.every.max(1)).min(body));
+        report.merge(&aggregate_range(data, offset..end, options, &metrics)?);
+        offset = end;
+        save(checkpoint, data, offset, &report)?;
+    }
+    metrics.log();
+
+    report.merge(&aggregate_tail(data, body..data.len(), options)?);
+    match fs::remove_file(&checkpoint.path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(report),
+    }
+}
+
+/// Read the checkpoint for `data`, if there is one: the offset below which
+/// everything is aggregated, and the stats of `data[..offset]`.
+pub fn load<S: Stats>(
+    checkpoint: &Checkpoint,
+    data: &[u8],
+) -> io::Result<Option<(usize, Report<S>)>> {
+    let bytes = match fs::read(&checkpoint.path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let (offset, report, input_len, hash) = decode(&bytes).ok_or_else(|| {
+        mismatch(
+            checkpoint,
+            "not a checkpoint file of this version and stats mode",
+        )
+    })?;
+
+    if input_len != data.len() as u64 {
+        return Err(mismatch(
+            checkpoint,
+            "written for an input of a different size",
+        ));
+    }
+    let offset = offset as usize;
+    if offset > data.len()
+        || (offset > 0 && data[offset - 1] != b'\n')
+        || fingerprint(data, offset) != hash
+    {
+        return Err(mismatch(checkpoint, "written for a different input"));
+    }
+    Ok(Some((offset, report)))
+}
+
+/// Record that `report` holds the stats of `data[..offset]`.
+pub fn save<S: Stats>(
+    checkpoint: &Checkpoint,
+    data: &[u8],
+    offset: usize,
+    report: &Report<S>,
+) -> io::Result<()> {
+    let mut out = Vec::new();
+    out.extend(MAGIC);
+    out.extend(VERSION.to_le_bytes());
+    out.extend((data.len() as u64).to_le_bytes());
+    out.extend(fingerprint(data, offset).to_le_bytes());
+    out.extend((offset as u64).to_le_bytes());
+    partial::encode(report, &mut out);
+
+    let mut tmp = checkpoint.path.clone().into_os_string();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&out)?;
+    file.sync_all()?;
+    fs::rename(&tmp, &checkpoint.path)
+}
+
+/// `(offset, report, input length, fingerprint)`.
+fn decode<S: Stats>(mut input: &[u8]) -> Option<(u64, Report<S>, u64, u64)> {
+    let input = &mut input;
+    if &take::<8>(input)? != MAGIC || u32::from_le_bytes(take(input)?) != VERSION {
+        return None;
+    }
+    let input_len = u64::from_le_bytes(take(input)?);
+    let hash = u64::from_le_bytes(take(input)?);
+    let offset = u64::from_le_bytes(take(input)?);
+    let report = partial::decode_from(input)?;
+    input
+        .is_empty()
+        .then_some((offset, report, input_len, hash))
+}
+
+fn fingerprint(data: &[u8], offset: usize) -> u64 {
+    let start = offset.saturating_sub(FINGERPRINT_BYTES);
+    let end = (offset + FINGERPRINT_BYTES).min(data.len());
+    xxh3_64(&data[start..end])
+}
+
+fn mismatch(checkpoint: &Checkpoint, why: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("checkpoint {}: {why}", checkpoint.path.display()),
+    )
+}
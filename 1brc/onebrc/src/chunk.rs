@@ -0,0 +1,351 @@
+//! Work distribution and the per-chunk hot loop.
+//!
+//! Threads claim newline-aligned `[start, end)` ranges from a shared cursor,
+//! large ones first and smaller ones towards the end ([`Guided`]), and
+//! process each range with three interleaved cursors (Java winner-style) to
+//! increase ILP and hide probe latency.
+//!
+//! The hot loop does unaligned 8-byte loads that may extend up to 7 bytes past
+//! the end of a chunk. Every chunk must therefore be followed by at least
+//! [`PADDING`] readable bytes; [`split_tail`] and [`padded_tail`] arrange that
+//! for the end of the input.
+//!
+//! [`chunk_statistics`] picks between that loop and a validating line-by-line
+//! one for each chunk, according to the [`InputFormat`], and runs the former
+//! with the [`Kernel`] it is given.
+
+use crate::kernel::{self, Kernel};
+use crate::parse::{
+    InputFormat, InvalidRecord, Values, is_fixed_shape, parse_general, split_record,
+};
+use crate::swar::{parse_temp_branchless, scan_to_byte_bounded};
+use crate::stats::Stats;
+use crate::table::NameTable;
+use memchr::{memchr, memrchr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Readable bytes required after the end of a chunk. Every load starts inside
+/// the chunk and is at most 8 bytes wide.
+pub const PADDING: usize = 8;
+
+#[inline(always)]
+unsafe fn snap_to_next_nl(pos: *const u8, end: *const u8) -> *const u8 { unsafe {
+    // return pointer to '\n' at/after pos (assumes there is a '\n' before end)
+    scan_to_byte_bounded(pos, end, b'\n')
+}}
+
+/// How the hot loop finds the separator and the `'\n'` of a record; one
+/// implementation per [`Kernel`].
+pub(crate) trait Scan {
+    /// Pointers to the first `separator` of the record at `p` and to the
+    /// `'\n'` after it. On a malformed line with no separator both may be
+    /// `end`.
+    ///
+    /// # Safety
+    /// `p < end`, there is a `'\n'` in `[p, end)`, and `[p, limit)` is
+    /// readable with `limit >= end + PADDING`.
+    unsafe fn scan(p: *const u8, end: *const u8, limit: *const u8, separator: u8) -> (*const u8, *const u8);
+}
+
+/// Eight bytes at a time in a `u64`; runs everywhere.
+pub(crate) struct Swar;
+
+impl Scan for Swar {
+    #[inline(always)]
+    unsafe fn scan(p: *const u8, end: *const u8, _limit: *const u8, separator: u8) -> (*const u8, *const u8) { unsafe {
+        let semi = scan_to_byte_bounded(p, end, separator);
+        (semi, scan_to_byte_bounded(semi.add(1), end, b'\n'))
+    }}
+}
+
+#[inline(always)]
+unsafe fn process_one<S: Stats, K: Scan>(
+    base: *const u8,
+    p: *const u8,
+    end: *const u8,
+    limit: *const u8,
+    statistics: &mut NameTable<S>,
+    separator: u8,
+) -> *const u8 { unsafe {
+    // Parse one record: <name>;<temp>\n
+    // Preconditions: p < end, and there is a '\n' before end.
+    let (semi, nl) = K::scan(p, end, limit, separator);
+
+    let name_off = (p as usize - base as usize) as u64;
+    let name_len = (semi as usize - p as usize) as u16;
+
+    // A line without a separator gives `semi == end`; the load must stay
+    // within the PADDING bytes after `end`.
+    let temp = parse_temp_branchless(semi.add(1).min(end));
+
+    statistics.get_or_insert_stats(name_off, name_len).record(temp);
+
+    nl.add(1)
+}}
+
+/// Aggregate the records in `data[chunk_start..chunk_end]` into `statistics`.
+///
+/// The range must start at a record and end right after a `'\n'`, and
+/// `data` must extend at least [`PADDING`] bytes beyond `chunk_end`.
+pub fn chunk_statistics_3cursors<S: Stats>(
+    data: &[u8],
+    chunk_start: usize,
+    chunk_end: usize,
+    statistics: &mut NameTable<S>,
+) {
+    chunk_statistics_3cursors_with(data, chunk_start, chunk_end, statistics, b';')
+}
+
+/// [`chunk_statistics_3cursors`] for records whose name ends at `separator`
+/// instead of `';'`.
+#[inline(always)]
+pub fn chunk_statistics_3cursors_with<S: Stats>(
+    data: &[u8],
+    chunk_start: usize,
+    chunk_end: usize,
+    statistics: &mut NameTable<S>,
+    separator: u8,
+) {
+    // SAFETY: Swar needs no target features.
+    unsafe { three_cursors::<S, Swar>(data, chunk_start, chunk_end, statistics, separator) }
+}
+
+/// The loop of [`chunk_statistics_3cursors_with`], scanning with `K`.
+///
+/// # Safety
+/// The CPU must support whatever instructions `K` uses.
+#[inline(always)]
+pub(crate) unsafe fn three_cursors<S: Stats, K: Scan>(
+    data: &[u8],
+    chunk_start: usize,
+    chunk_end: usize,
+    statistics: &mut NameTable<S>,
+    separator: u8,
+) {
+    assert!(chunk_start < chunk_end);
+    assert!(chunk_end + PADDING <= data.len());
+    assert_eq!(data[chunk_end - 1], b'\n');
+
+    unsafe {
+        let base = data.as_ptr();
+        let start = base.add(chunk_start);
+        let end = base.add(chunk_end);
+        let limit = base.add(data.len());
+
+        let len = chunk_end - chunk_start;
+        let dist = len / 3;
+
+        // Midpoints snapped to newline boundaries.
+        let m1_nl = snap_to_next_nl(start.add(dist), end);
+        let m2_nl = snap_to_next_nl(start.add(dist + dist), end);
+
+        // Subranges are [s1,e1), [s2,e2), [s3,e3) where each ends at '\n'+1.
+        let s1 = start;
+        let e1 = m1_nl.add(1);
+
+        let s2 = e1;
+        let e2 = m2_nl.add(1);
+
+        let s3 = e2;
+        let e3 = end;
+
+        let mut p1 = s1;
+        let mut p2 = s2;
+        let mut p3 = s3;
+
+        // Lockstep loop (unrolled 3x) while all three have work.
+        while p1 < e1 && p2 < e2 && p3 < e3 {
+            p1 = process_one::<S, K>(base, p1, e1, limit, statistics, separator);
+            p2 = process_one::<S, K>(base, p2, e2, limit, statistics, separator);
+            p3 = process_one::<S, K>(base, p3, e3, limit, statistics, separator);
+        }
+
+        // Drain tails (usually tiny).
+        while p1 < e1 {
+            p1 = process_one::<S, K>(base, p1, e1, limit, statistics, separator);
+        }
+        while p2 < e2 {
+            p2 = process_one::<S, K>(base, p2, e2, limit, statistics, separator);
+        }
+        while p3 < e3 {
+            p3 = process_one::<S, K>(base, p3, e3, limit, statistics, separator);
+        }
+    }
+}
+
+/// Aggregate `data[chunk_start..chunk_end]` as [`chunk_statistics_3cursors`]
+/// does, for any [`InputFormat`], scanning with `kernel`. Chunks the fast
+/// path cannot handle (other columns, or values not in the 1BRC shape) fall
+/// back to [`chunk_statistics_general`].
+///
+/// # Panics
+/// If the CPU does not support `kernel`.
+#[inline]
+pub fn chunk_statistics<S: Stats>(
+    data: &[u8],
+    chunk_start: usize,
+    chunk_end: usize,
+    statistics: &mut NameTable<S>,
+    format: InputFormat,
+    kernel: Kernel,
+) -> Result<(), InvalidRecord> {
+    let fast = format.is_two_column()
+        && (format.values == Values::Fixed
+            || is_fixed_shape(&data[chunk_start..chunk_end], &format));
+    if !fast {
+        return chunk_statistics_general(data, chunk_start, chunk_end, statistics, &format);
+    }
+    if kernel != Kernel::Swar {
+        kernel::chunk_statistics_simd(kernel, data, chunk_start, chunk_end, statistics, format.separator);
+        return Ok(());
+    }
+    // Keep the default separator a constant in the hot loop.
+    match format.separator {
+        b';' => chunk_statistics_3cursors(data, chunk_start, chunk_end, statistics),
+        separator => {
+            chunk_statistics_3cursors_with(data, chunk_start, chunk_end, statistics, separator)
+        }
+    }
+    Ok(())
+}
+
+/// The slow path: one line at a time, split into columns with
+/// [`split_record`] and every value validated with [`parse_general`]. Stops
+/// at the first invalid record; its offset is relative to `data`.
+pub fn chunk_statistics_general<S: Stats>(
+    data: &[u8],
+    chunk_start: usize,
+    chunk_end: usize,
+    statistics: &mut NameTable<S>,
+    format: &InputFormat,
+) -> Result<(), InvalidRecord> {
+    assert!(chunk_end + PADDING <= data.len());
+    assert_eq!(data[chunk_end - 1], b'\n');
+
+    let mut start = chunk_start;
+    for nl in memchr::memchr_iter(b'\n', &data[chunk_start..chunk_end]) {
+        let nl = chunk_start + nl;
+        let line = &data[start..nl];
+        let invalid = |reason| InvalidRecord {
+            offset: start as u64,
+            reason,
+        };
+        let (key, value) = split_record(line, format).map_err(invalid)?;
+        let name_len = u16::try_from(key.len()).map_err(|_| invalid("station name is too long"))?;
+        let temp = parse_general(&line[value]).map_err(invalid)?;
+        // `data` extends PADDING bytes beyond `chunk_end`, see above.
+        unsafe { statistics.get_or_insert_stats((start + key.start) as u64, name_len) }
+            .record(temp);
+        start = nl + 1;
+    }
+    Ok(())
+}
+
+/// Move `end` to just past the next `'\n'` at or after it.
+#[inline]
+pub fn snap_to_newline(data: &[u8], end: usize) -> usize {
+    if end < data.len() {
+        end + 1 + memchr(b'\n', &data[end..]).unwrap()
+    } else {
+        end
+    }
+}
+
+/// Claim the next newline-aligned range of roughly `chunk_size` bytes.
+pub fn claim_chunk(data: &[u8], next: &AtomicUsize, chunk_size: usize) -> Option<(usize, usize)> {
+    claim_sized(data, next, |_| chunk_size)
+}
+
+/// Claim the next newline-aligned range of roughly
+/// `guided.chunk_size(remaining)` bytes.
+pub fn claim_guided(data: &[u8], next: &AtomicUsize, guided: Guided) -> Option<(usize, usize)> {
+    claim_sized(data, next, |start| guided.chunk_size(data.len() - start))
+}
+
+/// Claim the range at the cursor, `size(start)` bytes before snapping.
+#[inline]
+fn claim_sized(data: &[u8], next: &AtomicUsize, size: impl Fn(usize) -> usize) -> Option<(usize, usize)> {
+    let len = data.len();
+    loop {
+        let start = next.load(Ordering::Relaxed);
+        if start >= len {
+            return None;
+        }
+
+        let end = usize::min(start.saturating_add(size(start)), len);
+        let end = snap_to_newline(data, end);
+
+        assert_eq!(data[end - 1], b'\n');
+
+        if next
+            .compare_exchange_weak(start, end, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            return Some((start, end));
+        }
+    }
+}
+
+/// A [`Guided`] claim takes `1 / (GUIDED_FACTOR * workers)` of what is left.
+pub const GUIDED_FACTOR: usize = 2;
+
+/// Chunk sizes for guided self-scheduling: every claim takes a share of the
+/// bytes that are left, but at least `min`. The first chunks are large, so
+/// there are few claims, and the last ones are `min`, so the workers run out
+/// of work at about the same time instead of one finishing a big chunk alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guided {
+    /// The smallest chunk, before snapping to a newline.
+    pub min: usize,
+    /// A claim takes `1 / divisor` of the remaining bytes when that is more
+    /// than `min`.
+    pub divisor: usize,
+}
+
+impl Guided {
+    /// `workers` workers claiming at least `min` bytes at a time.
+    pub fn new(min: usize, workers: usize) -> Guided {
+        Guided {
+            min: min.max(1),
+            divisor: GUIDED_FACTOR * workers.max(1),
+        }
+    }
+
+    /// The same schedule for a worker that claims `min` where the others
+    /// claim `self.min`, e.g. on a slower core: its share of what is left
+    /// shrinks in proportion.
+    pub fn scaled_to(self, min: usize) -> Guided {
+        let min = min.max(1);
+        Guided {
+            min,
+            divisor: self.divisor.saturating_mul(self.min).div_ceil(min),
+        }
+    }
+
+    /// Bytes to claim when `remaining` are left.
+    #[inline]
+    pub fn chunk_size(&self, remaining: usize) -> usize {
+        (remaining / self.divisor).max(self.min)
+    }
+}
+
+/// Length of the prefix of `data` that can be processed in place: it ends
+/// right after a `'\n'` and leaves at least [`PADDING`] bytes behind it.
+pub fn split_tail(data: &[u8]) -> usize {
+    let limit = data.len().saturating_sub(PADDING);
+    memrchr(b'\n', &data[..limit]).map_or(0, |i| i + 1)
+}
+
+/// Copy the tail left over by [`split_tail`] into a buffer with a terminating
+/// `'\n'` and [`PADDING`] zero bytes. Returns the buffer and the length of the
+/// records in it.
+pub fn padded_tail(tail: &[u8]) -> (Vec<u8>, usize) {
+    let mut buf = Vec::with_capacity(tail.len() + 1 + PADDING);
+    buf.extend_from_slice(tail);
+    if buf.last().is_some_and(|&b| b != b'\n') {
+        buf.push(b'\n');
+    }
+    let len = buf.len();
+    buf.resize(len + PADDING, 0);
+    (buf, len)
+}
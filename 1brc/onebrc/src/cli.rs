@@ -0,0 +1,635 @@
+//! Command line handling shared by the candidate binaries.
+//!
+//! ```text
+//! candidate_22 [INPUT] [--threads N] [--chunk-size BYTES] [--no-fork] [--output PATH]
+//!              [--format FORMAT] [--stats MODE] [--input-format FORMAT] [--stream]
+//!              [--io-uring] [--kernel KERNEL] [--pin] [--cpus LIST] [--prefer-pcores]
+//!              [--normalize STEPS] [--invalid-utf8 POLICY] [--group-by GROUPING]
+//!              [--include NAME]... [--include-from PATH]...
+//!              [--exclude NAME]... [--exclude-from PATH]...
+//!              [--checkpoint PATH [--checkpoint-every BYTES]]
+//!              [--follow [--interval DURATION]] [--processes K]
+//!              [--validate [--max-errors N]] [--skip-invalid]
+//! candidate_22 merge PARTIAL... [--format FORMAT] [--output PATH]
+//! ```
+//!
+//! `INPUT` is a file path or `-` for stdin. Stdin that is not a regular file is
+//! copied into a memfd first, so candidates that mmap or seek keep working.
+//! Binaries that can read from a pipe directly use [`Args::streams`] and
+//! [`Args::open_reader`] instead.
+//! The parent process re-executes itself with `--worker` (so it can print and
+//! exit without waiting for munmap) unless `--no-fork` is given. With
+//! `--processes`, that process coordinates `K` more started with
+//! `--coordinated` (see [`crate::coordinator`]).
+
+use crate::checkpoint::{CHECKPOINT_EVERY, Checkpoint};
+use crate::filter::{self, Filter};
+use crate::group::GroupBy;
+use crate::kernel::Kernel;
+use crate::keys::{InvalidUtf8, Keys};
+use crate::parse::InputFormat;
+use crate::placement::{self, Placement, Topology};
+use crate::report::Format;
+use crate::stats::StatsMode;
+use crate::validate::MAX_ERRORS;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Where the binaries have historically looked for their input.
+pub const DEFAULT_INPUT: &str = "../java-orig/measurements.txt";
+
+/// Default for `--interval`.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "\
+usage: {} [INPUT] [OPTIONS]
+       {} merge PARTIAL... [OPTIONS]
+
+  INPUT                  measurements file, or - for stdin
+  -t, --threads N        worker threads (default: $NUM_THREADS or all cores)
+  -c, --chunk-size SIZE  smallest claimed chunk, accepts K/M/G suffixes
+      --no-fork          aggregate in this process instead of a --worker child
+  -o, --output PATH      write the result to PATH instead of stdout
+  -f, --format FORMAT    challenge (default), json, csv, ndjson or partial
+      --stats MODE       basic (default) or extended: adds stddev and p50/p90/p99
+      --input-format FORMAT
+                         1brc (default): values are -99.9..99.9 with one decimal;
+                         general: any number such as 101.25, 7 or 1e1, validated;
+                         followed by any of ,sep=SEP (tab, comma, pipe, ... or
+                         the character), ,key=N,value=N (columns from 0) and
+                         ,crlf, e.g. general,sep=comma,key=1,value=2,crlf
+      --normalize STEPS  merge station names that differ only in form: any of
+                         nfc, casefold and trim, comma-separated (default: none)
+      --invalid-utf8 POLICY
+                         for station names that are not UTF-8: error (default),
+                         lossy (U+FFFD) or escape (\\xHH)
+      --group-by GROUPING
+                         report groups of stations instead: prefix:N (the first
+                         N characters), regex:PATTERN (its first capture group)
+                         or map:PATH (a CSV file of station,group lines);
+                         stations without a group are reported as unknown
+      --include NAME     aggregate only this station (repeat for more)
+      --include-from PATH
+                         aggregate only the stations in PATH, one per line
+      --exclude NAME     leave out this station (repeat for more)
+      --exclude-from PATH
+                         leave out the stations in PATH, one per line
+      --kernel KERNEL    how chunks are scanned: auto (default: the widest this
+                         CPU supports), swar, neon, avx2 or avx512
+      --pin              pin each worker thread to one CPU
+      --cpus LIST        run the workers on these CPUs only, e.g. 0-7 or 0,2,4
+                         (default --threads: one per CPU)
+      --prefer-pcores    pin the workers to performance cores first; workers
+                         on efficiency cores claim smaller chunks
+      --stream           read through fixed buffers instead of mmap (the default
+                         for pipes and FIFOs where supported)
+      --io-uring         read the file with io_uring and O_DIRECT instead of
+                         mmap (pread where that is not available)
+      --checkpoint PATH  save progress to PATH and resume from it if it exists
+      --checkpoint-every SIZE
+                         input bytes between checkpoints (default: 1G)
+      --follow           keep reading lines appended to INPUT; print the report
+                         every --interval and on SIGUSR1
+      --interval DURATION
+                         e.g. 500ms, 10s or 5m; 0 for SIGUSR1 only (default: 10s)
+      --processes K      aggregate in K worker processes that claim chunks from
+                         this one; --threads is split between them
+      --validate         check every line; report the first --max-errors
+                         invalid ones with their line number and fail
+      --max-errors N     how many invalid lines --validate reports (default: 10)
+      --skip-invalid     check every line, leave out the invalid ones and print
+                         how many there were
+  -h, --help             print this message";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    Path(PathBuf),
+    Stdin,
+}
+
+impl Input {
+    /// Read the whole input into memory.
+    pub fn read_all(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Input::Path(path) => std::fs::read(path),
+            Input::Stdin => {
+                let mut bytes = Vec::new();
+                io::stdin().lock().read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub input: Input,
+    pub threads: usize,
+    /// `None` means the binary's own default chunk size.
+    pub chunk_size: Option<usize>,
+    pub no_fork: bool,
+    pub output: Option<PathBuf>,
+    /// Only binaries that print through [`crate::Report::write_to`] honour this.
+    pub format: Format,
+    /// Likewise only honoured by binaries built on [`crate::aggregate_with`].
+    pub stats: StatsMode,
+    /// Likewise only honoured by binaries built on [`crate::aggregate_with`].
+    pub input_format: InputFormat,
+    /// `--normalize` and `--invalid-utf8`; likewise.
+    pub keys: Keys,
+    /// `--group-by` (`candidate_22` only), applied to the merged report.
+    pub group_by: Option<GroupBy>,
+    /// `--include` and `--exclude`, if any were given; likewise only
+    /// honoured by binaries built on [`crate::aggregate_with`].
+    pub filter: Option<Filter>,
+    /// The `--include` and `--exclude` arguments, to hand to the workers of
+    /// `--processes`.
+    pub filter_args: Vec<String>,
+    /// `--kernel`, or the one [`Kernel::detect`] picks; likewise.
+    pub kernel: Kernel,
+    /// `--pin`, `--cpus` and `--prefer-pcores`, if any were given; likewise.
+    pub placement: Option<Placement>,
+    /// Read with [`crate::stream`] even if the input could be mmapped.
+    pub stream: bool,
+    /// Read the input file with [`crate::uring`] instead of mmapping it.
+    pub io_uring: bool,
+    /// `--checkpoint`, with `--checkpoint-every` or its default.
+    pub checkpoint: Option<Checkpoint>,
+    pub follow: bool,
+    /// How often `--follow` prints the report; `None` for only on SIGUSR1.
+    pub interval: Option<Duration>,
+    /// `merge PARTIAL...`: combine `--format partial` results instead of
+    /// aggregating `input`.
+    pub merge: Option<Vec<Input>>,
+    /// `--processes`: how many worker processes to coordinate.
+    pub processes: Option<usize>,
+    /// Check every line with [`crate::validate`] and list the invalid ones.
+    pub validate: bool,
+    /// How many invalid lines `--validate` lists.
+    pub max_errors: usize,
+    /// Check every line and aggregate only the valid ones.
+    pub skip_invalid: bool,
+    /// Set in the re-executed child.
+    pub worker: bool,
+    /// Set in the worker processes of `--processes`, which get the input on
+    /// stdin and the coordinator on [`crate::coordinator::WORKER_FD`].
+    pub coordinated: bool,
+    /// Arguments to hand to the `--worker` child (everything but `--output`,
+    /// which the parent handles).
+    worker_args: Vec<String>,
+}
+
+impl Args {
+    /// Parse `env::args()`, printing usage and exiting on error.
+    pub fn from_env(default_input: &str) -> Self {
+        Self::from_args(env::args(), default_input)
+    }
+
+    /// Like [`Args::from_env`], for binaries that strip their own extra flags
+    /// from `env::args()` first. `argv` includes the program name.
+    pub fn from_args<I>(argv: I, default_input: &str) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut argv = argv.into_iter();
+        let program = argv.next().unwrap_or_default();
+        match Self::parse_from(argv, default_input) {
+            Ok(Some(args)) => args,
+            Ok(None) => {
+                println!("{}", USAGE.replace("{}", &program));
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("{program}: {e}");
+                eprintln!("{}", USAGE.replace("{}", &program));
+                process::exit(2);
+            }
+        }
+    }
+
+    /// Parse arguments (without the program name). Returns `Ok(None)` for `--help`.
+    pub fn parse_from<I>(argv: I, default_input: &str) -> Result<Option<Self>, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut input = None;
+        let mut threads = None;
+        let mut chunk_size = None;
+        let mut no_fork = false;
+        let mut output = None;
+        let mut format = Format::default();
+        let mut stats = StatsMode::default();
+        let mut input_format = InputFormat::default();
+        let mut normalize = Keys::default();
+        let mut invalid_utf8 = InvalidUtf8::default();
+        let mut group_by = None;
+        let mut filter = Filter::default();
+        let mut filter_args = Vec::new();
+        let mut kernel = None;
+        let mut pin = false;
+        let mut cpus = None;
+        let mut prefer_pcores = false;
+        let mut stream = false;
+        let mut io_uring = false;
+        let mut checkpoint = None;
+        let mut checkpoint_every = CHECKPOINT_EVERY;
+        let mut follow = false;
+        let mut interval = Some(DEFAULT_INTERVAL);
+        let mut processes = None;
+        let mut validate = false;
+        let mut max_errors = MAX_ERRORS;
+        let mut skip_invalid = false;
+        let mut worker = false;
+        let mut coordinated = false;
+        let mut worker_args = Vec::new();
+
+        let mut argv = argv.into_iter().peekable();
+        let mut merge = None;
+        if argv.next_if(|a| a == "merge").is_some() {
+            merge = Some(Vec::new());
+        }
+        while let Some(arg) = argv.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                inline
+                    .clone()
+                    .or_else(|| argv.next())
+                    .ok_or_else(|| format!("{name} needs a value"))
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--worker" => {
+                    worker = true;
+                    continue;
+                }
+                "--coordinated" => coordinated = true,
+                "--no-fork" => no_fork = true,
+                "--stream" => stream = true,
+                "--io-uring" => io_uring = true,
+                "--pin" => pin = true,
+                "--prefer-pcores" => prefer_pcores = true,
+                "--follow" => follow = true,
+                "--validate" => validate = true,
+                "--skip-invalid" => skip_invalid = true,
+                "--max-errors" => {
+                    let v = value("--max-errors")?;
+                    max_errors = v
+                        .parse()
+                        .map_err(|_| format!("invalid error count {v:?}"))?;
+                    worker_args.extend(["--max-errors".to_string(), v]);
+                    continue;
+                }
+                "--interval" => {
+                    let v = value("--interval")?;
+                    interval = Some(parse_duration(&v)?).filter(|d| !d.is_zero());
+                    worker_args.extend(["--interval".to_string(), v]);
+                    continue;
+                }
+                "--processes" => {
+                    let v = value("--processes")?;
+                    processes = Some(parse_count("process", &v)?);
+                    worker_args.extend(["--processes".to_string(), v]);
+                    continue;
+                }
+                "-t" | "--threads" => {
+                    let v = value("--threads")?;
+                    threads = Some(parse_count("thread", &v)?);
+                    worker_args.extend(["--threads".to_string(), v]);
+                    continue;
+                }
+                "-c" | "--chunk-size" => {
+                    let v = value("--chunk-size")?;
+                    chunk_size = Some(parse_size(&v)?);
+                    worker_args.extend(["--chunk-size".to_string(), v]);
+                    continue;
+                }
+                "-o" | "--output" => {
+                    output = Some(PathBuf::from(value("--output")?));
+                    continue;
+                }
+                "-f" | "--format" => {
+                    let v = value("--format")?;
+                    format = v.parse()?;
+                    worker_args.extend(["--format".to_string(), v]);
+                    continue;
+                }
+                "--checkpoint" => {
+                    let v = value("--checkpoint")?;
+                    checkpoint = Some(PathBuf::from(&v));
+                    worker_args.extend(["--checkpoint".to_string(), v]);
+                    continue;
+                }
+                "--checkpoint-every" => {
+                    let v = value("--checkpoint-every")?;
+                    checkpoint_every = parse_size(&v)?;
+                    worker_args.extend(["--checkpoint-every".to_string(), v]);
+                    continue;
+                }
+                "--input-format" => {
+                    let v = value("--input-format")?;
+                    input_format = v.parse()?;
+                    worker_args.extend(["--input-format".to_string(), v]);
+                    continue;
+                }
+                "--normalize" => {
+                    let v = value("--normalize")?;
+                    normalize = v.parse()?;
+                    worker_args.extend(["--normalize".to_string(), v]);
+                    continue;
+                }
+                "--invalid-utf8" => {
+                    let v = value("--invalid-utf8")?;
+                    invalid_utf8 = v.parse()?;
+                    worker_args.extend(["--invalid-utf8".to_string(), v]);
+                    continue;
+                }
+                "--group-by" => {
+                    let v = value("--group-by")?;
+                    group_by = Some(v.parse()?);
+                    worker_args.extend(["--group-by".to_string(), v]);
+                    continue;
+                }
+                "--include" | "--include-from" | "--exclude" | "--exclude-from" => {
+                    let v = value(&flag)?;
+                    let names = match flag.as_str() {
+                        "--include-from" | "--exclude-from" => filter::read_names(Path::new(&v))?,
+                        _ => vec![v.clone().into_bytes()],
+                    };
+                    if flag.starts_with("--include") {
+                        // An empty file still restricts the run (to nothing).
+                        filter.include.get_or_insert_default();
+                        names.iter().for_each(|name| filter.include(name));
+                    } else {
+                        names.iter().for_each(|name| filter.exclude(name));
+                    }
+                    worker_args.extend([flag.clone(), v.clone()]);
+                    filter_args.extend([flag, v]);
+                    continue;
+                }
+                "--kernel" => {
+                    let v = value("--kernel")?;
+                    kernel = Some(v.parse()?);
+                    worker_args.extend(["--kernel".to_string(), v]);
+                    continue;
+                }
+                "--cpus" => {
+                    let v = value("--cpus")?;
+                    cpus = Some(placement::parse_cpu_list(&v)?);
+                    worker_args.extend(["--cpus".to_string(), v]);
+                    continue;
+                }
+                "--stats" => {
+                    let v = value("--stats")?;
+                    stats = v.parse()?;
+                    worker_args.extend(["--stats".to_string(), v]);
+                    continue;
+                }
+                "-" if merge.is_some() => {
+                    merge.as_mut().unwrap().push(Input::Stdin);
+                }
+                "-" => {
+                    set_input(&mut input, Input::Stdin)?;
+                }
+                f if f.starts_with('-') => return Err(format!("unknown option {f}")),
+                _ if merge.is_some() => {
+                    merge
+                        .as_mut()
+                        .unwrap()
+                        .push(Input::Path(PathBuf::from(&arg)));
+                }
+                _ => {
+                    set_input(&mut input, Input::Path(PathBuf::from(&arg)))?;
+                }
+            }
+            worker_args.push(arg);
+        }
+
+        if merge.as_ref().is_some_and(Vec::is_empty) {
+            return Err("merge needs at least one partial result".to_string());
+        }
+        let input = match input {
+            Some(input) => input,
+            None => {
+                worker_args.push(default_input.to_string());
+                Input::Path(PathBuf::from(default_input))
+            }
+        };
+        let placement = match pin || cpus.is_some() || prefer_pcores {
+            true => {
+                let topology = Topology::detect().map_err(|e| format!("CPU topology: {e}"))?;
+                let placement = Placement::new(&topology, cpus.as_deref(), pin, prefer_pcores)?;
+                Some(placement)
+            }
+            false => None,
+        };
+        let threads = match threads {
+            Some(n) => n,
+            None => match env::var("NUM_THREADS") {
+                Ok(v) => parse_count("thread", &v)?,
+                Err(_) if cpus.is_some() => placement.as_ref().unwrap().cpus.len(),
+                Err(_) => thread::available_parallelism().map_or(1, |n| n.get()),
+            },
+        };
+
+        Ok(Some(Self {
+            input,
+            threads,
+            chunk_size,
+            no_fork,
+            output,
+            format,
+            stats,
+            input_format,
+            keys: Keys {
+                invalid_utf8,
+                ..normalize
+            },
+            group_by,
+            filter: (!filter.is_empty()).then_some(filter),
+            filter_args,
+            kernel: kernel.unwrap_or_else(Kernel::detect),
+            placement,
+            stream,
+            io_uring,
+            checkpoint: checkpoint.map(|path| Checkpoint {
+                path,
+                every: checkpoint_every,
+            }),
+            follow,
+            interval,
+            merge,
+            processes,
+            validate,
+            max_errors,
+            skip_invalid,
+            worker,
+            coordinated,
+            worker_args,
+        }))
+    }
+
+    /// True in the process that should do the actual aggregation. `--follow`
+    /// never forks, so that SIGUSR1 reaches the process doing the work.
+    pub fn is_worker(&self) -> bool {
+        self.worker || self.no_fork || self.follow
+    }
+
+    /// Open the input as a `File` that can be mmapped and seeked.
+    pub fn open_input(&self) -> io::Result<File> {
+        match &self.input {
+            Input::Path(path) => File::open(path),
+            Input::Stdin => stdin_as_file(),
+        }
+    }
+
+    /// Whether to read the input with [`crate::stream`]: `--stream` was
+    /// given, or the input is a pipe, FIFO or anything else that is not a
+    /// regular file.
+    pub fn streams(&self) -> io::Result<bool> {
+        if self.stream {
+            return Ok(true);
+        }
+        let metadata = match &self.input {
+            Input::Path(path) => std::fs::metadata(path)?,
+            Input::Stdin => File::from(io::stdin().as_fd().try_clone_to_owned()?).metadata()?,
+        };
+        Ok(!metadata.is_file())
+    }
+
+    /// Open the input for sequential reading, without spilling stdin.
+    pub fn open_reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        match &self.input {
+            Input::Path(path) => Ok(Box::new(File::open(path)?)),
+            Input::Stdin => Ok(Box::new(io::stdin())),
+        }
+    }
+
+    /// Point stdout (fd 1) at `--output`, if given. Call this once, early in
+    /// `main`, so that every later `println!` ends up in the file.
+    pub fn redirect_output(&self) -> io::Result<()> {
+        if let Some(path) = &self.output {
+            let file = File::create(path)?;
+            if unsafe { libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-execute the current binary with `--worker` and relay its output:
+    /// the first line for the challenge format, everything up to EOF for the
+    /// multi-line formats (workers should close stdout once they are done).
+    ///
+    /// A worker that fails writes nothing to stdout; then this waits for it
+    /// and exits with its code. One that wrote its result is not waited for,
+    /// as it may still be unmapping its input.
+    pub fn run_parent(&self) -> io::Result<()> {
+        let exe = env::current_exe()?;
+        let mut child = Command::new(exe)
+            .arg("--worker")
+            .args(&self.worker_args)
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let out = child.stdout.take().expect("expected stdout to be present");
+        let mut reader = io::BufReader::new(out);
+        let relayed = if self.format != Format::Challenge {
+            io::copy(&mut reader, &mut io::stdout().lock())? > 0
+        } else {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            print!("{line}");
+            line.ends_with('\n')
+        };
+        if !relayed {
+            let status = child.wait()?;
+            if !status.success() {
+                process::exit(status.code().unwrap_or(1));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Close stdout so that a parent in [`Args::run_parent`] sees EOF without
+/// waiting for this process to exit (and unmap its input).
+pub fn close_stdout() {
+    unsafe { libc::close(libc::STDOUT_FILENO) };
+}
+
+fn set_input(input: &mut Option<Input>, value: Input) -> Result<(), String> {
+    if input.is_some() {
+        return Err("more than one input given".to_string());
+    }
+    *input = Some(value);
+    Ok(())
+}
+
+fn parse_count(what: &str, v: &str) -> Result<usize, String> {
+    match v.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid {what} count {v:?}")),
+    }
+}
+
+/// Parse a byte count such as `1048576`, `512K`, `1M` or `2G`.
+pub fn parse_size(v: &str) -> Result<usize, String> {
+    let (digits, shift) = match v.as_bytes().last() {
+        Some(b'k' | b'K') => (&v[..v.len() - 1], 10),
+        Some(b'm' | b'M') => (&v[..v.len() - 1], 20),
+        Some(b'g' | b'G') => (&v[..v.len() - 1], 30),
+        _ => (v, 0),
+    };
+    match digits.parse::<usize>() {
+        Ok(n) if n > 0 => n
+            .checked_mul(1 << shift)
+            .ok_or_else(|| format!("size {v:?} is too large")),
+        _ => Err(format!("invalid size {v:?}")),
+    }
+}
+
+/// Parse a duration such as `10`, `10s`, `500ms` or `5m`. Plain numbers are seconds.
+pub fn parse_duration(v: &str) -> Result<Duration, String> {
+    let (digits, unit) = if let Some(d) = v.strip_suffix("ms") {
+        (d, Duration::from_millis(1))
+    } else if let Some(d) = v.strip_suffix('s') {
+        (d, Duration::from_secs(1))
+    } else if let Some(d) = v.strip_suffix('m') {
+        (d, Duration::from_secs(60))
+    } else {
+        (v, Duration::from_secs(1))
+    };
+    digits
+        .parse::<u32>()
+        .ok()
+        .and_then(|n| unit.checked_mul(n))
+        .ok_or_else(|| format!("invalid duration {v:?}"))
+}
+
+/// Stdin as a regular file. Redirected files (`< measurements.txt`) are used
+/// directly; pipes are drained into an anonymous memfd.
+fn stdin_as_file() -> io::Result<File> {
+    let stdin = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+    if stdin.metadata()?.is_file() {
+        return Ok(stdin);
+    }
+
+    let fd = unsafe { libc::memfd_create(c"onebrc-stdin".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    io::copy(&mut io::stdin().lock(), &mut file)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
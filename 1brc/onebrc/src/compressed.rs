@@ -0,0 +1,209 @@
+//! gzip and zstd input, recognised by their magic bytes.
+//!
+//! Both are decompressed on the fly into [`crate::stream`], so nothing is
+//! inflated to disk first. A zstd file made of several independent frames
+//! (`zstd -T0 --block-size`, `pzstd`, or plain concatenation) is decompressed
+//! one frame per thread instead: lines that straddle a frame boundary are
+//! stitched back together and aggregated at the end. gzip has no such
+//! boundaries, so it is always decompressed on one thread.
+
+use crate::parse::InvalidRecord;
+use crate::report::Report;
+use crate::stats::Stats;
+use crate::stream::{aggregate_lines, aggregate_reader};
+use crate::{Options, try_aggregate_with};
+use memchr::memchr;
+use std::io::{self, Cursor, ErrorKind, Read};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Look at the first (up to) four bytes of the input.
+    pub fn detect(head: &[u8]) -> Self {
+        match head {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            // Skippable frame, e.g. pzstd's header.
+            [m, 0x2a, 0x4d, 0x18, ..] if m & 0xf0 == 0x50 => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Aggregate `data`, decompressing it first if it is gzip or zstd.
+pub fn aggregate<S: Stats + Send>(data: &[u8], options: Options) -> io::Result<Report<S>> {
+    match Compression::detect(data) {
+        Compression::None => Ok(try_aggregate_with(data, options)?),
+        Compression::Gzip => aggregate_reader(flate2::bufread::MultiGzDecoder::new(data), options),
+        Compression::Zstd => {
+            let frames = zstd_frames(data)?;
+            if frames.len() > 1 && options.threads > 1 {
+                match aggregate_frames(&frames, options) {
+                    // Offsets within a frame are not offsets in the input:
+                    // find the record again in one pass.
+                    Err(e) if e.get_ref().is_some_and(|e| e.is::<InvalidRecord>()) => {}
+                    result => return result,
+                }
+            }
+            aggregate_reader(zstd::Decoder::with_buffer(data)?, options)
+        }
+    }
+}
+
+/// Wrap `reader` in a decompressor if it starts with a gzip or zstd magic.
+pub fn decompress<'a, R: Read + Send + 'a>(mut reader: R) -> io::Result<Box<dyn Read + Send + 'a>> {
+    let mut head = [0; 4];
+    let mut len = 0;
+    while len < head.len() {
+        match reader.read(&mut head[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let reader = Cursor::new(head).take(len as u64).chain(reader);
+    Ok(match Compression::detect(&head[..len]) {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+    })
+}
+
+/// Split a zstd file into its frames (skippable frames included).
+pub fn zstd_frames(data: &[u8]) -> io::Result<Vec<&[u8]>> {
+    let mut frames = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let len = zstd::zstd_safe::find_frame_compressed_size(rest).map_err(|code| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "zstd frame at byte {}: {}",
+                    data.len() - rest.len(),
+                    zstd::zstd_safe::get_error_name(code)
+                ),
+            )
+        })?;
+        let (frame, tail) = rest.split_at(len);
+        frames.push(frame);
+        rest = tail;
+    }
+    Ok(frames)
+}
+
+/// What a frame leaves for its neighbours: the bytes up to and including its
+/// first `'\n'` finish the previous frame's last line, and its own unfinished
+/// last line continues in the next frame.
+#[derive(Default)]
+struct Edges {
+    head: Vec<u8>,
+    /// False if the frame has no `'\n'` at all; then `head` is the whole frame.
+    has_newline: bool,
+    tail: Vec<u8>,
+}
+
+fn aggregate_frames<S: Stats + Send>(frames: &[&[u8]], options: Options) -> io::Result<Report<S>> {
+    let next = AtomicUsize::new(0);
+    let edges = Mutex::new(
+        (0..frames.len())
+            .map(|_| Edges::default())
+            .collect::<Vec<_>>(),
+    );
+
+    // Each thread decompresses one frame at a time and hands the lines to a
+    // single parsing thread of its own, the same pipeline as `aggregate_reader`.
+    let per_frame = Options {
+        threads: 1,
+        ..options
+    };
+    let mut report = thread::scope(|s| {
+        let handles: Vec<_> = (0..options.threads.min(frames.len()))
+            .map(|_| {
+                s.spawn(|| -> io::Result<Report<S>> {
+                    let mut report = Report::default();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(frame) = frames.get(i) else {
+                            return Ok(report);
+                        };
+                        let (part, frame_edges) = aggregate_frame(frame, i == 0, per_frame)?;
+                        report.merge(&part);
+                        edges.lock().unwrap()[i] = frame_edges;
+                    }
+                })
+            })
+            .collect();
+
+        let mut report = Report::default();
+        for handle in handles {
+            report.merge(&handle.join().unwrap()?);
+        }
+        Ok::<_, io::Error>(report)
+    })?;
+
+    let mut stitched = Vec::new();
+    let mut pending = Vec::new();
+    for frame in edges.into_inner().unwrap() {
+        if frame.has_newline {
+            stitched.append(&mut pending);
+            stitched.extend_from_slice(&frame.head);
+            pending = frame.tail;
+        } else {
+            pending.extend_from_slice(&frame.head);
+        }
+    }
+    stitched.append(&mut pending);
+    report.merge(&try_aggregate_with(&stitched, options)?);
+    Ok(report)
+}
+
+fn aggregate_frame<S: Stats + Send>(
+    frame: &[u8],
+    first: bool,
+    options: Options,
+) -> io::Result<(Report<S>, Edges)> {
+    let mut decoder = zstd::Decoder::with_buffer(frame)?.single_frame();
+    let mut edges = Edges {
+        has_newline: true,
+        ..Edges::default()
+    };
+
+    // Every frame but the first starts in the middle of a line.
+    let mut rest = Vec::new();
+    if !first {
+        let mut buf = vec![0; 64 << 10];
+        loop {
+            let n = match decoder.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                edges.has_newline = false;
+                return Ok((Report::default(), edges));
+            }
+            if let Some(nl) = memchr(b'\n', &buf[..n]) {
+                edges.head.extend_from_slice(&buf[..=nl]);
+                rest.extend_from_slice(&buf[nl + 1..n]);
+                break;
+            }
+            edges.head.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    let report = aggregate_lines(
+        Cursor::new(rest).chain(decoder),
+        options,
+        Some(&mut edges.tail),
+    )?;
+    Ok((report, edges))
+}
@@ -0,0 +1,255 @@
+//! Aggregation across worker processes (`--processes K`).
+//!
+//! The coordinator maps the input and hands out newline-aligned ranges with
+//! [`claim_guided`], one request at a time, over a Unix socket per worker.
+//! The ranges shrink as if every thread of every worker were claiming them.
+//! Each worker maps the same file, aggregates the ranges it is given on its
+//! own threads, and sends its stats back as a [`partial`] result.
+//!
+//! A worker that dies, sends garbage or goes quiet for [`WORKER_TIMEOUT`]
+//! only loses its own ranges: the coordinator remembers which ranges went
+//! where and aggregates those of a failed worker itself, together with
+//! anything never claimed and the tail that [`split_tail`] leaves over.
+//!
+//! Workers are ordinary child processes started by [`spawn`], so they can be
+//! moved into their own cgroups (e.g. by PID into a cpuset) like any other.
+//!
+//! Protocol, all integers little-endian:
+//!
+//! ```text
+//! coordinator -> worker  input length: u64
+//! worker -> coordinator  b'C'                          claim a range
+//! coordinator -> worker  start: u64 | end: u64         0, 0 when there is none
+//! worker -> coordinator  b'R' | length: u64 | partial  result, then hang up
+//! ```
+
+use crate::chunk::{Guided, chunk_statistics, claim_guided, split_tail};
+use crate::metrics::{self, Metrics};
+use crate::report::Report;
+use crate::stats::Stats;
+use crate::table::NameTable;
+use crate::{Options, TABLE_CAPACITY, aggregate_range, aggregate_tail, partial};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// The fd a worker started by [`spawn`] finds its socket on.
+pub const WORKER_FD: RawFd = 3;
+
+/// How long a worker may be silent, i.e. busy with one range, before it
+/// counts as failed. Streams that already have a read timeout keep theirs.
+pub const WORKER_TIMEOUT: Duration = Duration::from_secs(60);
+
+const CLAIM: u8 = b'C';
+const RESULT: u8 = b'R';
+
+/// Start `command` as a worker: `input` on its stdin (so it maps the very
+/// same file, even an unlinked one) and its end of the socket on
+/// [`WORKER_FD`]. Returns the child and the coordinator's end.
+pub fn spawn(mut command: Command, input: &File) -> io::Result<(Child, UnixStream)> {
+    let (ours, theirs) = UnixStream::pair()?;
+    let fd = theirs.as_raw_fd();
+    unsafe {
+        command.pre_exec(move || {
+            // dup2 clears close-on-exec, except when there is nothing to dup.
+            let ok = if fd == WORKER_FD {
+                libc::fcntl(fd, libc::F_SETFD, 0) != -1
+            } else {
+                libc::dup2(fd, WORKER_FD) != -1
+            };
+            if ok {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+    let child = command
+        .stdin(Stdio::from(input.try_clone()?))
+        .stdout(Stdio::null())
+        .spawn()?;
+    Ok((child, ours))
+}
+
+/// Serve `workers` until each has sent its result or gone away, and return
+/// the report for all of `data`. `options.threads` is used for whatever the
+/// coordinator has to aggregate itself.
+pub fn coordinate<S: Stats + Send>(
+    data: &[u8],
+    options: Options,
+    workers: Vec<UnixStream>,
+) -> io::Result<Report<S>> {
+    let body = split_tail(data);
+    let next = AtomicUsize::new(0);
+    let guided = Guided::new(options.chunk_size, options.threads.max(workers.len()));
+    let metrics = &Metrics::new("process");
+
+    let outcomes: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = workers
+            .into_iter()
+            .enumerate()
+            .map(|(index, stream)| {
+                let next = &next;
+                let worker = metrics.worker(index);
+                s.spawn(move || serve::<S>(&data[..body], next, guided, stream, worker))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    metrics.log();
+
+    let mut report = Report::default();
+    let mut redo = Claimed::new();
+    for (i, outcome) in outcomes.into_iter().enumerate() {
+        match outcome {
+            Ok(partial) => report.merge(&partial),
+            Err((e, claimed)) => {
+                eprintln!(
+                    "worker {i} failed ({e}); redoing its {} ranges",
+                    claimed.len()
+                );
+                redo.extend(claimed);
+            }
+        }
+    }
+    // Whatever no worker got to. In input order, so that the first invalid
+    // record is the one reported.
+    redo.push((next.load(Ordering::Relaxed), body));
+    redo.sort_unstable();
+    let metrics = Metrics::new("thread");
+    for (start, end) in redo {
+        report.merge(&aggregate_range(data, start..end, options, &metrics)?);
+    }
+    metrics.log();
+    report.merge(&aggregate_tail(data, body..data.len(), options)?);
+    Ok(report)
+}
+
+/// The ranges handed to a worker, kept so they can be redone if it fails.
+type Claimed = Vec<(usize, usize)>;
+
+fn serve<S: Stats>(
+    body: &[u8],
+    next: &AtomicUsize,
+    guided: Guided,
+    mut stream: UnixStream,
+    mut worker: metrics::Worker<'_>,
+) -> Result<Report<S>, (io::Error, Claimed)> {
+    let mut claimed = Claimed::new();
+    let result = (|| {
+        if stream.read_timeout()?.is_none() {
+            stream.set_read_timeout(Some(WORKER_TIMEOUT))?;
+        }
+        stream.write_all(&(body.len() as u64).to_le_bytes())?;
+        loop {
+            let mut tag = [0];
+            stream.read_exact(&mut tag)?;
+            match tag[0] {
+                CLAIM => {
+                    let (start, end) = claim_guided(body, next, guided).unwrap_or((0, 0));
+                    if end > start {
+                        claimed.push((start, end));
+                        worker.chunk(start, end);
+                    }
+                    let mut reply = [0; 16];
+                    reply[..8].copy_from_slice(&(start as u64).to_le_bytes());
+                    reply[8..].copy_from_slice(&(end as u64).to_le_bytes());
+                    stream.write_all(&reply)?;
+                }
+                RESULT => {
+                    let mut len = [0; 8];
+                    stream.read_exact(&mut len)?;
+                    let len = u64::from_le_bytes(len);
+                    // Only as much as is actually sent, whatever the length says.
+                    let mut bytes = Vec::new();
+                    (&mut stream).take(len).read_to_end(&mut bytes)?;
+                    if bytes.len() as u64 != len {
+                        let message = format!("result cut short at {} bytes", bytes.len());
+                        return Err(invalid(message));
+                    }
+                    return partial::decode::<S>(&bytes);
+                }
+                other => return Err(invalid(format!("unexpected message {other:#x}"))),
+            }
+        }
+    })();
+    result.map_err(|e| (e, claimed))
+}
+
+/// The worker side: aggregate the ranges the coordinator hands out over
+/// `stream` on `options.threads` threads, then send back the result. A
+/// worker that finds an invalid record just fails; the coordinator then finds
+/// it again when it redoes the worker's ranges.
+pub fn work<S: Stats + Send>(data: &[u8], options: Options, stream: UnixStream) -> io::Result<()> {
+    let mut stream = stream;
+    let mut len = [0; 8];
+    stream.read_exact(&mut len)?;
+    let body = u64::from_le_bytes(len) as usize;
+    if body != split_tail(data) {
+        return Err(invalid("the coordinator has a different input".to_string()));
+    }
+
+    let stream = Mutex::new(stream);
+    let tables = thread::scope(|s| {
+        let handles: Vec<_> = (0..options.threads.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut statistics = NameTable::<S>::with_capacity(data, TABLE_CAPACITY)
+                        .with_filter(options.filter);
+                    while let Some((start, end)) = claim(&stream, &data[..body])? {
+                        chunk_statistics(data, start, end, &mut statistics, options.input, options.kernel)?;
+                    }
+                    io::Result::Ok(statistics)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+
+    let mut report = Report::<S>::default();
+    for table in &tables {
+        report.merge_table_keyed(table, &options.keys)?;
+    }
+    let mut bytes = Vec::new();
+    partial::encode(&report, &mut bytes);
+
+    let mut stream = stream.into_inner().unwrap();
+    stream.write_all(&[RESULT])?;
+    stream.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    stream.write_all(&bytes)?;
+    stream.flush()
+}
+
+/// Ask the coordinator for the next range; `None` once there are no more.
+fn claim(stream: &Mutex<UnixStream>, body: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let mut reply = [0; 16];
+    {
+        let mut stream = stream.lock().unwrap();
+        stream.write_all(&[CLAIM])?;
+        stream.read_exact(&mut reply)?;
+    }
+    let start = u64::from_le_bytes(reply[..8].try_into().unwrap()) as usize;
+    let end = u64::from_le_bytes(reply[8..].try_into().unwrap()) as usize;
+    if start == end {
+        return Ok(None);
+    }
+    // The fast path reads past `end`, so only trust ranges that are valid here.
+    if start > end || end > body.len() || body[end - 1] != b'\n' {
+        return Err(invalid(format!("bad range {start}..{end}")));
+    }
+    Ok(Some((start, end)))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
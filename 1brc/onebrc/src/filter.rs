@@ -0,0 +1,60 @@
+//! Restricting a run to some stations (`--include`, `--exclude`).
+//!
+//! A [`Filter`] is consulted when a [`NameTable`](crate::NameTable) sees a
+//! name for the first time, on its cold insert path; a name it rejects gets
+//! an ignored entry. Later rows with that name land in the ignored entry like
+//! any other, so the scanning loop has no extra branch, and the table leaves
+//! ignored entries out when it is merged. Names are compared as raw bytes,
+//! before [`Keys`](crate::Keys) are applied.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Which station names to aggregate. The default keeps all of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// If set, only these names are kept.
+    pub include: Option<HashSet<Vec<u8>>>,
+    /// These names are dropped, even if they are in `include`.
+    pub exclude: HashSet<Vec<u8>>,
+}
+
+impl Filter {
+    /// Whether rows with the station name `name` are left out.
+    pub fn ignores(&self, name: &[u8]) -> bool {
+        self.include
+            .as_ref()
+            .is_some_and(|names| !names.contains(name))
+            || self.exclude.contains(name)
+    }
+
+    /// Whether the filter keeps every name.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_empty()
+    }
+
+    /// Add `name` to the names to keep.
+    pub fn include(&mut self, name: &[u8]) {
+        self.include
+            .get_or_insert_with(HashSet::new)
+            .insert(name.to_vec());
+    }
+
+    /// Add `name` to the names to drop.
+    pub fn exclude(&mut self, name: &[u8]) {
+        self.exclude.insert(name.to_vec());
+    }
+}
+
+/// The station names in the file at `path`, one per line. A `\r` before the
+/// `\n` and empty lines are skipped.
+pub fn read_names(path: &Path) -> Result<Vec<Vec<u8>>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(bytes
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect())
+}
@@ -0,0 +1,321 @@
+//! `--follow`: keep aggregating a file that is being appended to.
+//!
+//! A [`Follower`] remembers how far into the file it has aggregated. Each
+//! [`Follower::catch_up`] reads what was appended since into a buffer, at
+//! most [`READ_SIZE`] at a time, and runs the usual chunked workers over its
+//! complete lines; a half-written last line is left alone until its `'\n'`
+//! arrives. The stats so far live in a [`Report`], so nothing is ever read
+//! twice. The file is read rather than mapped: one truncated in place while
+//! mapped would kill the process with `SIGBUS` on the next access.
+//!
+//! [`follow`] wakes up on inotify events (falling back to polling the size),
+//! and hands the report to a callback on an interval and on `SIGUSR1`.
+//! Like `tail -F`, a file that shrinks, is replaced, or no longer has the
+//! last line read at the same place (truncated and written again, as with
+//! logrotate's `copytruncate`) is read again from its start, keeping the
+//! stats gathered so far.
+
+use crate::chunk::split_tail;
+use crate::metrics::Metrics;
+use crate::report::Report;
+use crate::stats::{StationStats, Stats};
+use crate::{Options, aggregate_range, aggregate_tail};
+use memchr::memrchr;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::ops::ControlFlow;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// How often the file size is checked when inotify has nothing to say.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How much of the appended data is read and aggregated at a time; more for
+/// a line that does not fit.
+pub const READ_SIZE: usize = 64 << 20;
+
+/// How much of the last line read is kept to check that it is still there.
+const LAST_LINE: usize = 64;
+
+pub struct Follower<'a, S = StationStats> {
+    path: PathBuf,
+    options: Options<'a>,
+    file: File,
+    inode: u64,
+    /// Everything before this offset is in `report`; it is always just past
+    /// a `'\n'` (or 0).
+    done: usize,
+    /// The end of the last line before `done`, as it was read.
+    last: Vec<u8>,
+    buffer: Vec<u8>,
+    report: Report<S>,
+    /// The threads of every catch-up, logged when [`follow`] stops.
+    metrics: Metrics,
+}
+
+impl<'a, S: Stats + Send> Follower<'a, S> {
+    /// Start following `path` from its beginning. Nothing is read until the
+    /// first [`Follower::catch_up`].
+    pub fn new(path: impl Into<PathBuf>, options: Options<'a>) -> io::Result<Self> {
+        let path = path.into();
+        let file = File::open(&path)?;
+        let inode = file.metadata()?.ino();
+        Ok(Self {
+            path,
+            options,
+            file,
+            inode,
+            done: 0,
+            last: Vec::new(),
+            buffer: Vec::new(),
+            report: Report::default(),
+            metrics: Metrics::new("thread"),
+        })
+    }
+
+    /// The stats of every complete line seen so far.
+    pub fn report(&self) -> &Report<S> {
+        &self.report
+    }
+
+    /// Bytes of the current file that are in [`Follower::report`].
+    pub fn offset(&self) -> usize {
+        self.done
+    }
+
+    /// Aggregate the complete lines appended since the last call. Returns
+    /// whether there were any.
+    pub fn catch_up(&mut self) -> io::Result<bool> {
+        let mut progress = self.read_new_lines()?;
+
+        // Replaced (log rotation): finish the old file, then start the new one.
+        if let Ok(metadata) = fs::metadata(&self.path)
+            && metadata.ino() != self.inode
+        {
+            self.file = File::open(&self.path)?;
+            self.inode = self.file.metadata()?.ino();
+            self.start_over();
+            progress |= self.read_new_lines()?;
+        }
+        Ok(progress)
+    }
+
+    fn start_over(&mut self) {
+        self.done = 0;
+        self.last.clear();
+    }
+
+    fn read_new_lines(&mut self) -> io::Result<bool> {
+        let len = self.file.metadata()?.len() as usize;
+        if len < self.done || !self.last_line_is_there()? {
+            // Truncated, and maybe written again since.
+            self.start_over();
+        }
+
+        let mut progress = false;
+        let mut size = READ_SIZE;
+        while self.done < len {
+            let want = (len - self.done).min(size);
+            self.buffer.clear();
+            self.buffer.resize(want, 0);
+            let read = read_at(&self.file, &mut self.buffer, self.done)?;
+            let Some(nl) = memrchr(b'\n', &self.buffer[..read]) else {
+                if read == want && want < len - self.done {
+                    // A line longer than the buffer.
+                    size *= 2;
+                    continue;
+                }
+                break;
+            };
+            let end = nl + 1;
+
+            // In place up to where the fast path still has padding, then a copy.
+            let data = &self.buffer[..];
+            let body = split_tail(&data[..end]);
+            let range = aggregate_range(data, 0..body, self.options, &self.metrics)?;
+            let tail = aggregate_tail(data, body..end, self.options)?;
+            self.report.merge(&range);
+            self.report.merge(&tail);
+            self.done += end;
+            self.last.clear();
+            self.last
+                .extend_from_slice(&data[end.saturating_sub(LAST_LINE)..end]);
+            progress = true;
+            if read < want {
+                // Shrunk while reading; the next call starts over.
+                break;
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Whether the file still has the end of the last line read before
+    /// `done`.
+    fn last_line_is_there(&self) -> io::Result<bool> {
+        let mut now = vec![0; self.last.len()];
+        let read = read_at(&self.file, &mut now, self.done - self.last.len())?;
+        Ok(read == now.len() && now == self.last)
+    }
+}
+
+/// Read `buf.len()` bytes at `offset`, or up to the end of the file. Returns
+/// how many were read.
+fn read_at(file: &File, buf: &mut [u8], offset: usize) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read_at(&mut buf[read..], (offset + read) as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Follow `path` until `emit` breaks: call `emit` with the report once at
+/// the start, then every `interval` (if given) and on every `SIGUSR1`.
+pub fn follow<S, F>(
+    path: &Path,
+    options: Options,
+    interval: Option<Duration>,
+    mut emit: F,
+) -> io::Result<()>
+where
+    S: Stats + Send,
+    F: FnMut(&Report<S>) -> io::Result<ControlFlow<()>>,
+{
+    let signals = usr1_pipe()?;
+    let mut follower = Follower::<S>::new(path, options)?;
+    let mut watch = Inotify::watch(path).ok();
+    let mut inode = follower.inode;
+
+    follower.catch_up()?;
+    if emit(follower.report())?.is_break() {
+        follower.metrics.log();
+        return Ok(());
+    }
+
+    let mut next = interval.map(|i| Instant::now() + i);
+    loop {
+        let timeout = match next {
+            Some(next) => next
+                .saturating_duration_since(Instant::now())
+                .min(POLL_INTERVAL),
+            None => POLL_INTERVAL,
+        };
+        let mut fds = vec![libc::pollfd {
+            fd: signals,
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        if let Some(watch) = &watch {
+            fds.push(libc::pollfd {
+                fd: watch.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            });
+        }
+        let ready = unsafe {
+            libc::poll(
+                fds.as_mut_ptr(),
+                fds.len() as libc::nfds_t,
+                timeout.as_millis() as libc::c_int,
+            )
+        };
+        if ready == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+        let usr1 = drain(signals);
+        if let Some(watch) = &watch {
+            drain(watch.fd.as_raw_fd());
+        }
+
+        follower.catch_up()?;
+        if follower.inode != inode {
+            // The watch is on the old file.
+            inode = follower.inode;
+            watch = Inotify::watch(path).ok();
+        }
+
+        let due = next.is_some_and(|next| Instant::now() >= next);
+        if due {
+            next = interval.map(|i| Instant::now() + i);
+        }
+        if (usr1 || due) && emit(follower.report())?.is_break() {
+            follower.metrics.log();
+            return Ok(());
+        }
+    }
+}
+
+/// Read everything available from a non-blocking fd; true if there was any.
+fn drain(fd: RawFd) -> bool {
+    let mut buf = [0u8; 4096];
+    let mut any = false;
+    while unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } > 0 {
+        any = true;
+    }
+    any
+}
+
+struct Inotify {
+    fd: OwnedFd,
+}
+
+impl Inotify {
+    fn watch(path: &Path) -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let mask = libc::IN_MODIFY | libc::IN_ATTRIB | libc::IN_MOVE_SELF | libc::IN_DELETE_SELF;
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd })
+    }
+}
+
+/// Write end of the `SIGUSR1` self-pipe, for the handler.
+static USR1_WRITE: OnceLock<RawFd> = OnceLock::new();
+
+extern "C" fn on_usr1(_: libc::c_int) {
+    if let Some(&fd) = USR1_WRITE.get() {
+        unsafe { libc::write(fd, [1u8].as_ptr().cast(), 1) };
+    }
+}
+
+/// Install the `SIGUSR1` handler (once per process) and return the read end
+/// of the pipe it writes to.
+fn usr1_pipe() -> io::Result<RawFd> {
+    static READ: OnceLock<RawFd> = OnceLock::new();
+    if let Some(&fd) = READ.get() {
+        return Ok(fd);
+    }
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let _ = USR1_WRITE.set(fds[1]);
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_usr1 as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        if libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(*READ.get_or_init(|| fds[0]))
+}
@@ -0,0 +1,176 @@
+//! Measurement file generator, a port of upstream `create_measurements.sh`.
+//!
+//! Every row picks a station uniformly from [`STATIONS`] and draws its
+//! temperature from a normal distribution around the station mean (sd 10),
+//! rounded to tenths. Rows are produced in blocks of [`BLOCK_ROWS`]; block `i`
+//! gets its own generator seeded from `(seed, i)`, so the output depends only
+//! on the seed and the row count, not on the number of threads.
+
+use crate::report::Report;
+use crate::stations::STATIONS;
+use crate::stats::StationStats;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+
+pub const BLOCK_ROWS: u64 = 1 << 16;
+
+const MIN_TEMP: i16 = -999;
+const MAX_TEMP: i16 = 999;
+
+/// SplitMix64. Small, fast and stable across crate upgrades, which matters
+/// because a seed should keep producing the same file.
+#[derive(Clone, Debug)]
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    #[inline(always)]
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    #[inline(always)]
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    /// Uniform in `[0, n)`.
+    #[inline(always)]
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    /// Standard normal (Box-Muller).
+    #[inline(always)]
+    pub fn gaussian(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+    }
+}
+
+/// One block of generated rows and the stats of its rows, indexed like [`STATIONS`].
+pub struct Block {
+    pub bytes: Vec<u8>,
+    pub stats: Vec<StationStats>,
+}
+
+/// Temperature in tenths, written as `-12.3`.
+#[inline(always)]
+fn push_temp(out: &mut Vec<u8>, tenths: i16) {
+    if tenths < 0 {
+        out.push(b'-');
+    }
+    let abs = tenths.unsigned_abs();
+    if abs >= 100 {
+        out.push(b'0' + (abs / 100) as u8);
+    }
+    out.push(b'0' + (abs / 10 % 10) as u8);
+    out.push(b'.');
+    out.push(b'0' + (abs % 10) as u8);
+}
+
+/// Generate rows `[block * BLOCK_ROWS, block * BLOCK_ROWS + rows)`.
+pub fn generate_block(seed: u64, block: u64, rows: u64) -> Block {
+    let mut rng = SplitMix64::new(SplitMix64::new(seed ^ block.rotate_left(32)).next_u64());
+    let mut bytes = Vec::with_capacity(rows as usize * 16);
+    let mut stats = vec![StationStats::default(); STATIONS.len()];
+
+    for _ in 0..rows {
+        let i = rng.below(STATIONS.len());
+        let (name, mean) = STATIONS[i];
+        let temp = ((mean + 10.0 * rng.gaussian()) * 10.0).round() as i16;
+        let temp = temp.clamp(MIN_TEMP, MAX_TEMP);
+
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.push(b';');
+        push_temp(&mut bytes, temp);
+        bytes.push(b'\n');
+        stats[i].record(temp);
+    }
+
+    Block { bytes, stats }
+}
+
+/// Write `rows` rows to `out` using `threads` generator threads and return the
+/// aggregate of everything written (the `expected.txt` answer).
+pub fn write_rows<W: Write>(out: &mut W, rows: u64, seed: u64, threads: usize) -> io::Result<Report> {
+    let blocks = rows.div_ceil(BLOCK_ROWS);
+    let threads = threads.max(1);
+    let next = AtomicU64::new(0);
+    let mut stats = vec![StationStats::default(); STATIONS.len()];
+
+    // At most `window` blocks are generated but not yet written, so that
+    // generators cannot run arbitrarily far ahead of the writer. A generator
+    // takes a slot before it claims a block and the writer gives it back once
+    // that block is written; the lowest unwritten block always holds one, so
+    // the writer never waits for nothing.
+    let window = threads * 2;
+    let (slot_tx, slot_rx) = mpsc::sync_channel::<()>(window);
+    for _ in 0..window {
+        slot_tx.send(()).unwrap();
+    }
+    let slot_rx = Mutex::new(slot_rx);
+
+    thread::scope(|s| {
+        // Dropped if the writer fails, which stops the generators.
+        let slot_tx = slot_tx;
+        let (tx, rx) = mpsc::channel::<(u64, Block)>();
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let (next, slot_rx) = (&next, &slot_rx);
+            s.spawn(move || {
+                loop {
+                    if slot_rx.lock().unwrap().recv().is_err() {
+                        break;
+                    }
+                    let block = next.fetch_add(1, Ordering::Relaxed);
+                    if block >= blocks {
+                        break;
+                    }
+                    let n = BLOCK_ROWS.min(rows - block * BLOCK_ROWS);
+                    if tx.send((block, generate_block(seed, block, n))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Blocks arrive out of order; write them in order.
+        let mut pending = BTreeMap::new();
+        let mut written = 0;
+        for (block, data) in rx {
+            pending.insert(block, data);
+            while let Some(data) = pending.remove(&written) {
+                out.write_all(&data.bytes)?;
+                for (total, s) in stats.iter_mut().zip(&data.stats) {
+                    total.merge(s);
+                }
+                written += 1;
+                // Never blocks: there are at most `window` slots out.
+                let _ = slot_tx.send(());
+            }
+        }
+        Ok::<_, io::Error>(())
+    })?;
+
+    let mut report = Report::default();
+    for ((name, _), s) in STATIONS.iter().zip(&stats) {
+        if s.count > 0 {
+            report.merge_station(name, s);
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,139 @@
+//! Grouping stations by a key derived from their name (`--group-by`).
+//!
+//! Grouping happens on the finished [`Report`](crate::Report): every station
+//! is mapped to its group once and its stats are merged into that group's,
+//! so the hot loop and the per-thread tables are unchanged. Stations without
+//! a group end up in [`UNKNOWN`].
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The group of stations that [`GroupBy`] finds no group for.
+pub const UNKNOWN: &str = "unknown";
+
+/// How to derive a group from a station name.
+#[derive(Clone, Debug)]
+pub enum GroupBy {
+    /// The first `n` characters; shorter names have no group.
+    Prefix(usize),
+    /// The first capture group of the leftmost match, or the whole match if
+    /// the pattern has no groups. Names it does not match have no group.
+    Regex(Regex),
+    /// Station name to group, from a two-column CSV file. Names that are not
+    /// in it have no group.
+    Map(HashMap<String, String>),
+}
+
+impl GroupBy {
+    /// The group of `station`, or `None` for [`UNKNOWN`].
+    pub fn group<'a>(&'a self, station: &'a str) -> Option<&'a str> {
+        match self {
+            GroupBy::Prefix(n) => match station.char_indices().nth(*n) {
+                Some((end, _)) => Some(&station[..end]),
+                None if station.chars().count() == *n => Some(station),
+                None => None,
+            },
+            GroupBy::Regex(regex) => {
+                let captures = regex.captures(station)?;
+                let m = match captures.len() {
+                    1 => captures.get(0),
+                    _ => captures.get(1),
+                };
+                m.map(|m| m.as_str())
+            }
+            GroupBy::Map(map) => map.get(station).map(String::as_str),
+        }
+    }
+
+    /// Read a mapping from a CSV file of `station,group` lines, see
+    /// [`parse_map`].
+    pub fn load_map(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        parse_map(&text)
+            .map(GroupBy::Map)
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    /// `prefix:N`, `regex:PATTERN` or `map:PATH` (which reads the file).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("prefix", n)) => match n.parse() {
+                Ok(n) if n > 0 => Ok(GroupBy::Prefix(n)),
+                _ => Err(format!("invalid prefix length {n:?}")),
+            },
+            Some(("regex", pattern)) => Regex::new(pattern)
+                .map(GroupBy::Regex)
+                .map_err(|e| format!("invalid regex {pattern:?}: {e}")),
+            Some(("map", path)) => GroupBy::load_map(Path::new(path)),
+            _ => Err(format!(
+                "unknown grouping {s:?} (expected prefix:N, regex:PATTERN or map:PATH)"
+            )),
+        }
+    }
+}
+
+/// Parse `station,group` lines, quoted as in RFC 4180 where a field holds a
+/// comma or quote (as in the `--format csv` output). Blank lines are
+/// skipped, and so is a first line of `station,group`.
+pub fn parse_map(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut map = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() || (i == 0 && line == "station,group") {
+            continue;
+        }
+        let fields = csv_fields(line).map_err(|e| format!("line {}: {e}", i + 1))?;
+        let [station, group] = <[String; 2]>::try_from(fields)
+            .map_err(|f| format!("line {}: expected 2 fields, found {}", i + 1, f.len()))?;
+        if map.contains_key(&station) {
+            return Err(format!("line {}: {station:?} is mapped twice", i + 1));
+        }
+        map.insert(station, group);
+    }
+    Ok(map)
+}
+
+/// The fields of one CSV line.
+fn csv_fields(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    loop {
+        let field;
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) if quoted[i + 1..].starts_with('"') => {
+                        value.push('"');
+                        chars.next();
+                    }
+                    Some((i, '"')) => break i + 1,
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated quote"),
+                }
+            };
+            field = value;
+            rest = &quoted[end..];
+            if !rest.is_empty() && !rest.starts_with(',') {
+                return Err("text after a closing quote");
+            }
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            field = rest[..end].to_string();
+            rest = &rest[end..];
+        }
+        fields.push(field);
+        match rest.strip_prefix(',') {
+            Some(r) => rest = r,
+            None => return Ok(fields),
+        }
+    }
+}
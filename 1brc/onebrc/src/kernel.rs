@@ -0,0 +1,341 @@
+//! Chunk kernels: how the hot loop finds the separator and the `'\n'` of a
+//! record (`--kernel`).
+//!
+//! All kernels run the same three-cursor loop and the same branchless
+//! temperature parse; they differ in the scan only. [`Kernel::Swar`] looks
+//! at 8 bytes per step and runs everywhere. [`Kernel::Neon`], on aarch64,
+//! compares 32 bytes as two 16-byte vectors and narrows each result to four
+//! bits per byte, since NEON has no byte movemask. [`Kernel::Avx2`] finds the
+//! separator and the `'\n'` in one 32-byte compare each. [`Kernel::Avx512`]
+//! compares straight into mask registers, 32 bytes and then 64 for longer
+//! names, and uses masked loads at the end of the buffer. A record that does
+//! not fit (a name of about 30 or 60 bytes or more) is scanned with SWAR.
+//!
+//! [`Kernel::detect`] picks the widest one the CPU supports, once, at
+//! startup. The x86 loops are compiled with `#[target_feature]`, so the
+//! binary still runs on CPUs without them; NEON is part of the aarch64
+//! baseline.
+
+use crate::chunk::{Scan, Swar, three_cursors};
+use crate::stats::Stats;
+use crate::table::NameTable;
+use std::fmt;
+use std::str::FromStr;
+
+/// A chunk kernel, from the most portable to the widest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kernel {
+    Swar,
+    Neon,
+    Avx2,
+    Avx512,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 4] = [Kernel::Swar, Kernel::Neon, Kernel::Avx2, Kernel::Avx512];
+
+    /// The widest kernel this CPU supports.
+    pub fn detect() -> Kernel {
+        let mut best = Kernel::Swar;
+        for kernel in Kernel::ALL {
+            if kernel.is_supported() {
+                best = kernel;
+            }
+        }
+        best
+    }
+
+    /// Whether this CPU has the instructions the kernel needs.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Kernel::Swar => true,
+            Kernel::Neon => cfg!(target_arch = "aarch64"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => {
+                is_x86_feature_detected!("avx512f")
+                    && is_x86_feature_detected!("avx512bw")
+                    && is_x86_feature_detected!("avx512vl")
+            }
+            #[cfg(not(target_arch = "x86_64"))]
+            Kernel::Avx2 | Kernel::Avx512 => false,
+        }
+    }
+}
+
+impl Default for Kernel {
+    /// [`Kernel::detect`].
+    fn default() -> Self {
+        Kernel::detect()
+    }
+}
+
+impl FromStr for Kernel {
+    type Err = String;
+
+    /// `swar`, `neon`, `avx2` or `avx512`, or `auto` for [`Kernel::detect`]. Only
+    /// kernels this CPU supports are accepted.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kernel = match s {
+            "auto" => return Ok(Kernel::detect()),
+            "swar" => Kernel::Swar,
+            "neon" => Kernel::Neon,
+            "avx2" => Kernel::Avx2,
+            "avx512" => Kernel::Avx512,
+            _ => {
+                return Err(format!(
+                    "unknown kernel {s:?} (expected auto, swar, neon, avx2 or avx512)"
+                ));
+            }
+        };
+        if !kernel.is_supported() {
+            return Err(format!("this CPU does not support the {kernel} kernel"));
+        }
+        Ok(kernel)
+    }
+}
+
+impl fmt::Display for Kernel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kernel::Swar => "swar",
+            Kernel::Neon => "neon",
+            Kernel::Avx2 => "avx2",
+            Kernel::Avx512 => "avx512",
+        })
+    }
+}
+
+/// [`crate::chunk::chunk_statistics_3cursors_with`] with a SIMD `kernel`.
+///
+/// # Panics
+/// If the CPU does not support `kernel`.
+pub(crate) fn chunk_statistics_simd<S: Stats>(
+    kernel: Kernel,
+    data: &[u8],
+    chunk_start: usize,
+    chunk_end: usize,
+    statistics: &mut NameTable<S>,
+    separator: u8,
+) {
+    assert!(
+        kernel.is_supported(),
+        "this CPU does not support the {kernel} kernel"
+    );
+    match kernel {
+        Kernel::Swar => unsafe {
+            three_cursors::<S, Swar>(data, chunk_start, chunk_end, statistics, separator)
+        },
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon => unsafe {
+            three_cursors::<S, arm::Neon>(data, chunk_start, chunk_end, statistics, separator)
+        },
+        #[cfg(not(target_arch = "aarch64"))]
+        Kernel::Neon => unreachable!(),
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 => unsafe {
+            x86::chunk_avx2(data, chunk_start, chunk_end, statistics, separator)
+        },
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx512 => unsafe {
+            x86::chunk_avx512(data, chunk_start, chunk_end, statistics, separator)
+        },
+        #[cfg(not(target_arch = "x86_64"))]
+        Kernel::Avx2 | Kernel::Avx512 => unreachable!(),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::*;
+    use std::arch::x86_64::*;
+
+    struct Avx2;
+    struct Avx512;
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn chunk_avx2<S: Stats>(
+        data: &[u8],
+        chunk_start: usize,
+        chunk_end: usize,
+        statistics: &mut NameTable<S>,
+        separator: u8,
+    ) {
+        unsafe { three_cursors::<S, Avx2>(data, chunk_start, chunk_end, statistics, separator) }
+    }
+
+    #[target_feature(enable = "avx512f,avx512bw,avx512vl")]
+    pub(super) unsafe fn chunk_avx512<S: Stats>(
+        data: &[u8],
+        chunk_start: usize,
+        chunk_end: usize,
+        statistics: &mut NameTable<S>,
+        separator: u8,
+    ) {
+        unsafe { three_cursors::<S, Avx512>(data, chunk_start, chunk_end, statistics, separator) }
+    }
+
+    impl Scan for Avx2 {
+        #[inline(always)]
+        unsafe fn scan(
+            p: *const u8,
+            end: *const u8,
+            limit: *const u8,
+            separator: u8,
+        ) -> (*const u8, *const u8) {
+            unsafe { scan_avx2(p, end, limit, separator) }
+        }
+    }
+
+    impl Scan for Avx512 {
+        #[inline(always)]
+        unsafe fn scan(
+            p: *const u8,
+            end: *const u8,
+            limit: *const u8,
+            separator: u8,
+        ) -> (*const u8, *const u8) {
+            unsafe { scan_avx512(p, end, limit, separator) }
+        }
+    }
+
+    /// The first separator and the `'\n'` after it, from bit masks of the
+    /// bytes from `p` that match, if both are there and before `end`.
+    #[inline(always)]
+    unsafe fn from_masks(
+        p: *const u8,
+        end: *const u8,
+        separators: u64,
+        newlines: u64,
+    ) -> Option<(*const u8, *const u8)> {
+        if separators == 0 {
+            return None;
+        }
+        let semi = separators.trailing_zeros();
+        let newlines = newlines >> semi;
+        if newlines == 0 {
+            return None;
+        }
+        let nl = unsafe { p.add((semi + newlines.trailing_zeros()) as usize) };
+        (nl < end).then(|| (unsafe { p.add(semi as usize) }, nl))
+    }
+
+    /// One unaligned 32-byte load, which may read up to `limit`.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn scan_avx2(
+        p: *const u8,
+        end: *const u8,
+        limit: *const u8,
+        separator: u8,
+    ) -> (*const u8, *const u8) {
+        unsafe {
+            if limit as usize - p as usize >= 32 {
+                let v = _mm256_loadu_si256(p as *const __m256i);
+                let eq = |b: u8| {
+                    let m = _mm256_cmpeq_epi8(v, _mm256_set1_epi8(b as i8));
+                    _mm256_movemask_epi8(m) as u32 as u64
+                };
+                if let Some(found) = from_masks(p, end, eq(separator), eq(b'\n')) {
+                    return found;
+                }
+            }
+            Swar::scan(p, end, limit, separator)
+        }
+    }
+
+    /// A 32-byte load first, since most records fit, and a 64-byte one for
+    /// longer names. Both are masked to the bytes before `limit` near the end
+    /// of the buffer, where a full load could fault.
+    #[inline]
+    #[target_feature(enable = "avx512f,avx512bw,avx512vl")]
+    unsafe fn scan_avx512(
+        p: *const u8,
+        end: *const u8,
+        limit: *const u8,
+        separator: u8,
+    ) -> (*const u8, *const u8) {
+        unsafe {
+            let len = limit as usize - p as usize;
+            let v = if len >= 32 {
+                _mm256_loadu_si256(p as *const __m256i)
+            } else {
+                _mm256_maskz_loadu_epi8((1 << len) - 1, p as *const i8)
+            };
+            let eq = |b: u8| _mm256_cmpeq_epi8_mask(v, _mm256_set1_epi8(b as i8)) as u64;
+            if let Some(found) = from_masks(p, end, eq(separator), eq(b'\n')) {
+                return found;
+            }
+            let v = if len >= 64 {
+                _mm512_loadu_si512(p as *const __m512i)
+            } else {
+                _mm512_maskz_loadu_epi8((1 << len) - 1, p as *const i8)
+            };
+            let eq = |b: u8| _mm512_cmpeq_epi8_mask(v, _mm512_set1_epi8(b as i8));
+            match from_masks(p, end, eq(separator), eq(b'\n')) {
+                Some(found) => found,
+                None => Swar::scan(p, end, limit, separator),
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use super::*;
+    use std::arch::aarch64::*;
+
+    pub(super) struct Neon;
+
+    impl Scan for Neon {
+        #[inline(always)]
+        unsafe fn scan(
+            p: *const u8,
+            end: *const u8,
+            limit: *const u8,
+            separator: u8,
+        ) -> (*const u8, *const u8) {
+            unsafe { scan_neon(p, end, limit, separator) }
+        }
+    }
+
+    /// Four bits per byte of `v`, set where it equals `b`: `vshrn` keeps the
+    /// middle byte of each pair, which takes half of each compare result.
+    #[inline(always)]
+    unsafe fn nibbles(v: uint8x16_t, b: u8) -> u64 {
+        unsafe {
+            let eq = vceqq_u8(v, vdupq_n_u8(b));
+            let narrowed = vshrn_n_u16::<4>(vreinterpretq_u16_u8(eq));
+            vget_lane_u64::<0>(vreinterpret_u64_u8(narrowed))
+        }
+    }
+
+    /// Two 16-byte loads, which may read up to `limit`.
+    #[inline(always)]
+    unsafe fn scan_neon(
+        p: *const u8,
+        end: *const u8,
+        limit: *const u8,
+        separator: u8,
+    ) -> (*const u8, *const u8) {
+        unsafe {
+            if limit as usize - p as usize >= 32 {
+                let (lo, hi) = (vld1q_u8(p), vld1q_u8(p.add(16)));
+                let eq = |b: u8| nibbles(lo, b) as u128 | ((nibbles(hi, b) as u128) << 64);
+                let separators = eq(separator);
+                if separators != 0 {
+                    let semi = separators.trailing_zeros() / 4;
+                    let newlines = eq(b'\n') >> (semi * 4);
+                    if newlines != 0 {
+                        let nl = p.add((semi + newlines.trailing_zeros() / 4) as usize);
+                        if nl < end {
+                            return (p.add(semi as usize), nl);
+                        }
+                    }
+                }
+            }
+            Swar::scan(p, end, limit, separator)
+        }
+    }
+}
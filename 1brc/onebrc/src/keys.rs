@@ -0,0 +1,152 @@
+//! Turning the raw name bytes of a [`NameTable`](crate::NameTable) into
+//! report keys (`--normalize`, `--invalid-utf8`).
+//!
+//! The table compares names as raw bytes, so `Zürich` in NFC and in NFD are
+//! two stations there. [`Keys`] is applied when a table is merged into a
+//! [`Report`](crate::Report), once per distinct name and table (once per
+//! distinct name and thread in [`stream`](crate::stream) and
+//! [`uring`](crate::uring), which recycle a table per chunk), so the hot loop
+//! is unchanged: names that normalize to the same key are merged there.
+//!
+//! The steps, in order: decode the bytes as UTF-8 (see [`InvalidUtf8`]),
+//! trim surrounding whitespace, fold case, and compose to NFC.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfc_quick};
+
+/// How station names become report keys. The default keeps the bytes as
+/// they are and rejects names that are not UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keys {
+    /// Compose to Unicode normalization form C.
+    pub nfc: bool,
+    /// Unicode default case folding (`Straße` and `STRASSE` become `strasse`).
+    pub fold_case: bool,
+    /// Strip leading and trailing whitespace.
+    pub trim: bool,
+    pub invalid_utf8: InvalidUtf8,
+}
+
+/// What to do with a name that is not UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Fail with the offset of a record with that name (not necessarily the
+    /// first one: tables are filled out of order).
+    #[default]
+    Error,
+    /// Replace invalid sequences with U+FFFD.
+    Lossy,
+    /// Write each invalid byte as `\xHH`.
+    Escape,
+}
+
+impl Keys {
+    /// The report key for the station name `name`.
+    pub fn key<'a>(&self, name: &'a [u8]) -> Result<Cow<'a, str>, &'static str> {
+        let mut key = match std::str::from_utf8(name) {
+            Ok(name) => Cow::Borrowed(name),
+            Err(_) => match self.invalid_utf8 {
+                InvalidUtf8::Error => return Err("station name is not UTF-8"),
+                InvalidUtf8::Lossy => String::from_utf8_lossy(name),
+                InvalidUtf8::Escape => Cow::Owned(escape(name)),
+            },
+        };
+        if self.trim {
+            key = match key {
+                Cow::Borrowed(k) => Cow::Borrowed(k.trim()),
+                Cow::Owned(k) => Cow::Owned(k.trim().to_string()),
+            };
+        }
+        if self.fold_case {
+            key = Cow::Owned(caseless::default_case_fold_str(&key));
+        }
+        if self.nfc && is_nfc_quick(key.chars()) != IsNormalized::Yes {
+            key = Cow::Owned(key.nfc().collect());
+        }
+        Ok(key)
+    }
+}
+
+/// `name` with every byte that is not part of valid UTF-8 written as `\xHH`.
+fn escape(name: &[u8]) -> String {
+    let mut escaped = String::with_capacity(name.len() * 2);
+    for chunk in name.utf8_chunks() {
+        escaped.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            write!(escaped, "\\x{b:02X}").unwrap();
+        }
+    }
+    escaped
+}
+
+impl FromStr for Keys {
+    type Err = String;
+
+    /// `none`, or a comma-separated list of `nfc`, `casefold` and `trim`.
+    /// Leaves [`Keys::invalid_utf8`] at its default.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys = Keys::default();
+        if s == "none" {
+            return Ok(keys);
+        }
+        for item in s.split(',') {
+            match item {
+                "nfc" => keys.nfc = true,
+                "casefold" => keys.fold_case = true,
+                "trim" => keys.trim = true,
+                _ => {
+                    return Err(format!(
+                        "unknown normalization {item:?} (expected none, or any of \
+                         nfc, casefold and trim)"
+                    ));
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// The inverse of [`Keys::from_str`] (so without the UTF-8 policy).
+impl fmt::Display for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps = [
+            (self.nfc, "nfc"),
+            (self.fold_case, "casefold"),
+            (self.trim, "trim"),
+        ];
+        let steps: Vec<_> = steps.iter().filter(|s| s.0).map(|s| s.1).collect();
+        if steps.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&steps.join(","))
+        }
+    }
+}
+
+impl FromStr for InvalidUtf8 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(InvalidUtf8::Error),
+            "lossy" => Ok(InvalidUtf8::Lossy),
+            "escape" => Ok(InvalidUtf8::Escape),
+            _ => Err(format!(
+                "unknown invalid UTF-8 policy {s:?} (expected error, lossy or escape)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InvalidUtf8::Error => "error",
+            InvalidUtf8::Lossy => "lossy",
+            InvalidUtf8::Escape => "escape",
+        })
+    }
+}
@@ -0,0 +1,224 @@
+//! The candidate_22 engine as a library.
+//!
+//! The input is a byte slice of `Station name;-12.3\n` records, usually a
+//! mmapped file. It is split into newline-aligned chunks which worker threads
+//! claim dynamically; every thread aggregates into its own [`NameTable`] and
+//! the tables are merged into a [`Report`] at the end.
+//!
+//! ```no_run
+//! use memmap2::Mmap;
+//! use std::fs::File;
+//!
+//! let file = File::open("measurements.txt").unwrap();
+//! let data = unsafe { Mmap::map(&file).unwrap() };
+//! let report = onebrc::aggregate(&data, onebrc::Options::default());
+//! println!("{report}");
+//! ```
+
+pub mod checkpoint;
+pub mod chunk;
+pub mod cli;
+pub mod compressed;
+pub mod coordinator;
+pub mod filter;
+pub mod follow;
+pub mod generate;
+pub mod group;
+pub mod kernel;
+pub mod keys;
+pub mod metrics;
+pub mod parse;
+pub mod partial;
+pub mod placement;
+pub mod report;
+pub mod stations;
+pub mod stats;
+pub mod stream;
+pub mod swar;
+pub mod table;
+pub mod uring;
+pub mod validate;
+
+use chunk::Guided;
+use metrics::Metrics;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+pub use filter::Filter;
+pub use kernel::Kernel;
+pub use keys::Keys;
+pub use parse::{InputFormat, InvalidRecord};
+pub use placement::Placement;
+pub use report::Report;
+pub use stats::{ExtendedStats, StationStats, Stats};
+pub use table::NameTable;
+
+/// Smallest chunk a worker claims (before snapping to a newline); earlier
+/// claims take a share of what is left, see [`chunk::Guided`].
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+/// Expected number of distinct stations, used as the initial size of each
+/// [`NameTable`]; tables with more names grow.
+pub const TABLE_CAPACITY: usize = 10000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options<'a> {
+    /// Number of worker threads.
+    pub threads: usize,
+    /// Size of the last, smallest chunks the workers claim; the readers of
+    /// [`stream`] and [`uring`] read chunks of this size.
+    pub chunk_size: usize,
+    /// What the records look like; the 1BRC layout by default.
+    pub input: InputFormat,
+    /// How station names become report keys; as they are by default.
+    pub keys: Keys,
+    /// Which stations to aggregate; all of them by default.
+    pub filter: Option<&'a Filter>,
+    /// How the hot loop scans; the widest this CPU supports by default.
+    pub kernel: Kernel,
+    /// Which CPUs the workers run on; wherever the scheduler puts them by
+    /// default.
+    pub placement: Option<&'a Placement>,
+}
+
+impl Default for Options<'_> {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            chunk_size: CHUNK_SIZE,
+            input: InputFormat::default(),
+            keys: Keys::default(),
+            filter: None,
+            kernel: Kernel::detect(),
+            placement: None,
+        }
+    }
+}
+
+impl Options<'_> {
+    /// Move the calling thread to where worker `index` runs, and return the
+    /// chunk sizes it should claim.
+    pub(crate) fn enter_worker(&self, index: usize) -> Guided {
+        let guided = Guided::new(self.chunk_size, self.threads);
+        match self.placement {
+            Some(placement) => guided.scaled_to(placement.enter(index, self.chunk_size)),
+            None => guided,
+        }
+    }
+}
+
+/// Aggregate all records in `data`. The last line does not need a `'\n'`.
+///
+/// The fast path never reads outside `data`: the last few lines are copied
+/// into a padded buffer (see [`chunk::split_tail`]) and processed separately.
+pub fn aggregate(data: &[u8], options: Options) -> Report {
+    aggregate_with(data, options)
+}
+
+/// [`aggregate`] into any [`Stats`], e.g. `aggregate_with::<ExtendedStats>`
+/// for standard deviation and percentiles.
+///
+/// # Panics
+/// If `options.input` validates values and one is invalid, or a station name
+/// is not UTF-8 and `options.keys` says that is an error; see
+/// [`try_aggregate_with`].
+pub fn aggregate_with<S: Stats + Send>(data: &[u8], options: Options) -> Report<S> {
+    try_aggregate_with(data, options).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// [`aggregate_with`], returning the first record that `options.input` or
+/// `options.keys` rejects instead of panicking.
+pub fn try_aggregate_with<S: Stats + Send>(
+    data: &[u8],
+    options: Options,
+) -> Result<Report<S>, InvalidRecord> {
+    let body = chunk::split_tail(data);
+    let metrics = Metrics::new("thread");
+    let mut report = aggregate_range(data, 0..body, options, &metrics)?;
+    metrics.log();
+    report.merge(&aggregate_tail(data, body..data.len(), options)?);
+    Ok(report)
+}
+
+/// Aggregate the records in `data[range]` on `options.threads` threads.
+/// `range.end` must be right after a `'\n'` and at most
+/// [`chunk::split_tail`]`(data)`, so that the fast path can read past it.
+/// On invalid records, returns the one with the lowest offset that was seen.
+/// The threads are counted in `metrics`, which the caller logs.
+pub(crate) fn aggregate_range<S: Stats + Send>(
+    data: &[u8],
+    range: Range<usize>,
+    options: Options,
+    metrics: &Metrics,
+) -> Result<Report<S>, InvalidRecord> {
+    let next = &AtomicUsize::new(range.start);
+    let threads = options.threads.max(1);
+    let body = &data[..range.end];
+
+    thread::scope(|s| {
+        let mut handles = Vec::with_capacity(threads);
+
+        for index in 0..threads {
+            handles.push(s.spawn(move || {
+                let guided = options.enter_worker(index);
+                let mut worker = metrics.worker(index);
+                let mut statistics = NameTable::<S>::with_capacity(data, TABLE_CAPACITY)
+                    .with_filter(options.filter);
+                while let Some((start, end)) = chunk::claim_guided(body, next, guided) {
+                    worker.chunk(start, end);
+                    if let Err(e) = chunk::chunk_statistics(
+                        data,
+                        start,
+                        end,
+                        &mut statistics,
+                        options.input,
+                        options.kernel,
+                    ) {
+                        // Let the other threads stop too.
+                        next.store(usize::MAX, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                }
+                Ok(statistics)
+            }));
+        }
+
+        let mut report = Report::default();
+        let mut invalid = Vec::new();
+        for handle in handles {
+            let merged = handle
+                .join()
+                .unwrap()
+                .and_then(|statistics| report.merge_table_keyed(&statistics, &options.keys));
+            if let Err(e) = merged {
+                invalid.push(e);
+            }
+        }
+        match invalid.into_iter().min_by_key(|e| e.offset) {
+            Some(e) => Err(e),
+            None => Ok(report),
+        }
+    })
+}
+
+/// Aggregate the few lines left over by [`chunk::split_tail`] at the end of
+/// `data[range]` from a padded copy.
+pub(crate) fn aggregate_tail<S: Stats>(
+    data: &[u8],
+    range: Range<usize>,
+    options: Options,
+) -> Result<Report<S>, InvalidRecord> {
+    let mut report = Report::default();
+    let base = range.start;
+    let (tail, tail_len) = chunk::padded_tail(&data[range]);
+    if tail_len > 0 {
+        let mut statistics = NameTable::<S>::with_capacity(&tail, 16).with_filter(options.filter);
+        chunk::chunk_statistics(&tail, 0, tail_len, &mut statistics, options.input, options.kernel)
+            .map_err(|e| e.shifted(base))?;
+        report
+            .merge_table_keyed(&statistics, &options.keys)
+            .map_err(|e| e.shifted(base))?;
+    }
+    Ok(report)
+}
@@ -1,3 +1,4 @@
+use onebrc::cli::Args;
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
@@ -73,11 +74,21 @@ impl ResultEntry {
 struct Scanner<'a> {
     data: &'a [u8],
     pos: usize,
+    /// Every segment read so far, which the entries' names point into.
+    all_data: &'a [u8],
+    /// Where `data` starts in `all_data`.
+    base: usize,
 }
 
 impl<'a> Scanner<'a> {
-    fn new(data: &'a [u8]) -> Self {
-        Self { data, pos: 0 }
+    /// Scans the first `len` bytes of `all_data` from `base`.
+    fn new(all_data: &'a [u8], base: usize, len: usize) -> Self {
+        Self {
+            data: &all_data[base..base + len],
+            pos: 0,
+            all_data,
+            base,
+        }
     }
 
     fn has_next(&self) -> bool {
@@ -89,16 +100,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn get_long_at(&self, pos: usize) -> u64 {
-        if pos + 8 <= self.data.len() {
-            u64::from_ne_bytes(self.data[pos..pos + 8].try_into().unwrap())
-        } else if pos < self.data.len() {
-            let mut bytes = [0u8; 8];
-            let len = self.data.len() - pos;
-            bytes[..len].copy_from_slice(&self.data[pos..]);
-            u64::from_ne_bytes(bytes)
-        } else {
-            0
-        }
+        long_at(self.data, pos)
     }
 
     fn add(&mut self, delta: usize) {
@@ -106,9 +108,24 @@ impl<'a> Scanner<'a> {
     }
 }
 
+/// Eight bytes at `pos` as a little-endian word, zero-padded past the end of
+/// `data`.
+fn long_at(data: &[u8], pos: usize) -> u64 {
+    if pos + 8 <= data.len() {
+        u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+    } else if pos < data.len() {
+        let mut bytes = [0u8; 8];
+        let len = data.len() - pos;
+        bytes[..len].copy_from_slice(&data[pos..]);
+        u64::from_le_bytes(bytes)
+    } else {
+        0
+    }
+}
+
 fn next_new_line(data: &[u8], mut prev: usize) -> usize {
     while prev + 8 <= data.len() {
-        let current_word = u64::from_ne_bytes(data[prev..prev + 8].try_into().unwrap());
+        let current_word = u64::from_le_bytes(data[prev..prev + 8].try_into().unwrap());
         let input = current_word ^ 0x0A0A0A0A0A0A0A0Au64;
         let pos = (input.wrapping_sub(0x0101010101010101u64)) & !input & 0x8080808080808080u64;
         if pos != 0 {
@@ -140,6 +157,9 @@ fn hash_to_index(hash: u64, size: usize) -> usize {
     (hash_as_int as usize) & (size - 1)
 }
 
+/// The temperature in tenths. `number_word` is the little-endian word that
+/// starts after the ';' (first character in the low byte), and
+/// `decimal_sep_pos` the bit index of the '.' in it.
 fn convert_into_number(decimal_sep_pos: u32, number_word: u64) -> i64 {
     let shift = 28 - decimal_sep_pos;
     let signed = !((number_word << 59) as i64) >> 63;
@@ -214,7 +234,8 @@ fn find_result(
 
     loop {
         if results[table_index].is_none() {
-            let mut r = Box::new(ResultEntry::new(name_offset));
+            // Entries outlive the segment, so they point into all_data.
+            let mut r = Box::new(ResultEntry::new(scanner.base + name_offset));
             let total_length = name_length + 1;
             r.first_name_word = scanner.get_long_at(name_offset);
             r.second_name_word = scanner.get_long_at(name_offset + 8);
@@ -234,7 +255,8 @@ fn find_result(
         let mut i = 0;
         let mut collision = false;
         while i + 8 <= name_length + 1 {
-            if scanner.get_long_at(existing.name_offset + i) != scanner.get_long_at(name_offset + i)
+            if long_at(scanner.all_data, existing.name_offset + i)
+                != scanner.get_long_at(name_offset + i)
             {
                 collision = true;
                 break;
@@ -244,7 +266,7 @@ fn find_result(
 
         if !collision {
             let remaining_shift = 64 - (((name_length + 1 - i) << 3) & 63);
-            if ((scanner.get_long_at(existing.name_offset + i)
+            if ((long_at(scanner.all_data, existing.name_offset + i)
                 ^ scanner.get_long_at(name_offset + i))
                 << remaining_shift)
                 == 0
@@ -270,15 +292,16 @@ fn record(existing_result: &mut ResultEntry, number: i64) {
 
 fn parse_loop(
     counter: &AtomicUsize,
+    segment_size: usize,
     file: &File,
     file_size: usize,
     collected_results: &mut Vec<usize>,
     hash_table: &mut [Option<Box<ResultEntry>>],
     all_data: &mut Vec<u8>,
 ) {
-    let mut buffer = vec![0u8; SEGMENT_SIZE + 1024]; // Extra space for overflow line
+    let mut buffer = vec![0u8; segment_size + 1024]; // Extra space for overflow line
     loop {
-        let current_offset = counter.fetch_add(SEGMENT_SIZE, Ordering::SeqCst);
+        let current_offset = counter.fetch_add(segment_size, Ordering::SeqCst);
         if current_offset >= file_size {
             return;
         }
@@ -289,7 +312,7 @@ fn parse_loop(
             .seek(SeekFrom::Start(read_start as u64))
             .unwrap();
 
-        let bytes_to_read = (SEGMENT_SIZE + 1024).min(file_size - read_start);
+        let bytes_to_read = (segment_size + 1024).min(file_size - read_start);
         actual_handle
             .read_exact(&mut buffer[..bytes_to_read])
             .unwrap_or_default();
@@ -305,10 +328,10 @@ fn parse_loop(
             p + 1
         };
 
-        let segment_end = if read_start + SEGMENT_SIZE >= file_size {
+        let segment_end = if read_start + segment_size >= file_size {
             data_in_buffer.len()
         } else {
-            let mut p = SEGMENT_SIZE;
+            let mut p = segment_size;
             while p < data_in_buffer.len() && data_in_buffer[p] != b'\n' {
                 p += 1;
             }
@@ -324,15 +347,16 @@ fn parse_loop(
         all_data.extend_from_slice(segment_data);
 
         // We use the recently extended part of all_data
-        let data = &all_data[offset_in_all_data..];
+        let all = &all_data[..];
+        let data = &all[offset_in_all_data..];
         let dist = data.len() / 3;
         let mid_point1 = next_new_line(data, dist);
         let mid_point2 = next_new_line(data, dist * 2);
 
-        let mut scanner1 = Scanner::new(&data[..mid_point1]);
-        let mut scanner2 = Scanner::new(&data[..mid_point2]);
+        let mut scanner1 = Scanner::new(all, offset_in_all_data, mid_point1);
+        let mut scanner2 = Scanner::new(all, offset_in_all_data, mid_point2);
         scanner2.add(mid_point1 + 1);
-        let mut scanner3 = Scanner::new(data);
+        let mut scanner3 = Scanner::new(all, offset_in_all_data, data.len());
         scanner3.add(mid_point2 + 1);
 
         while scanner1.has_next() && scanner2.has_next() && scanner3.has_next() {
@@ -368,7 +392,6 @@ fn parse_loop(
                 (input.wrapping_sub(0x0101010101010101u64)) & !input & 0x8080808080808080u64
             };
 
-            let s1_pos_before = scanner1.pos;
             let res_idx1 = find_result(
                 word1,
                 dm1,
@@ -378,13 +401,7 @@ fn parse_loop(
                 hash_table,
                 collected_results,
             );
-            // Adjust name_offset for new results
-            if let Some(ref mut r) = hash_table[res_idx1]
-                && r.name_offset == s1_pos_before {
-                    r.name_offset += offset_in_all_data;
-                }
 
-            let s2_pos_before = scanner2.pos;
             let res_idx2 = find_result(
                 word2,
                 dm2,
@@ -394,12 +411,7 @@ fn parse_loop(
                 hash_table,
                 collected_results,
             );
-            if let Some(ref mut r) = hash_table[res_idx2]
-                && r.name_offset == s2_pos_before {
-                    r.name_offset += offset_in_all_data;
-                }
 
-            let s3_pos_before = scanner3.pos;
             let res_idx3 = find_result(
                 word3,
                 dm3,
@@ -409,10 +421,6 @@ fn parse_loop(
                 hash_table,
                 collected_results,
             );
-            if let Some(ref mut r) = hash_table[res_idx3]
-                && r.name_offset == s3_pos_before {
-                    r.name_offset += offset_in_all_data;
-                }
 
             let num1 = scan_number(&mut scanner1);
             let num2 = scan_number(&mut scanner2);
@@ -434,7 +442,6 @@ fn parse_loop(
                 let input = word_b ^ 0x3B3B3B3B3B3B3B3Bu64;
                 (input.wrapping_sub(0x0101010101010101u64)) & !input & 0x8080808080808080u64
             };
-            let s_pos_before = scanner1.pos;
             let res_idx = find_result(
                 word,
                 dm,
@@ -444,10 +451,6 @@ fn parse_loop(
                 hash_table,
                 collected_results,
             );
-            if let Some(ref mut r) = hash_table[res_idx]
-                && r.name_offset == s_pos_before {
-                    r.name_offset += offset_in_all_data;
-                }
             let num = scan_number(&mut scanner1);
             record(hash_table[res_idx].as_mut().unwrap(), num);
         }
@@ -462,7 +465,6 @@ fn parse_loop(
                 let input = word_b ^ 0x3B3B3B3B3B3B3B3Bu64;
                 (input.wrapping_sub(0x0101010101010101u64)) & !input & 0x8080808080808080u64
             };
-            let s_pos_before = scanner2.pos;
             let res_idx = find_result(
                 word,
                 dm,
@@ -472,10 +474,6 @@ fn parse_loop(
                 hash_table,
                 collected_results,
             );
-            if let Some(ref mut r) = hash_table[res_idx]
-                && r.name_offset == s_pos_before {
-                    r.name_offset += offset_in_all_data;
-                }
             let num = scan_number(&mut scanner2);
             record(hash_table[res_idx].as_mut().unwrap(), num);
         }
@@ -490,7 +488,6 @@ fn parse_loop(
                 let input = word_b ^ 0x3B3B3B3B3B3B3B3Bu64;
                 (input.wrapping_sub(0x0101010101010101u64)) & !input & 0x8080808080808080u64
             };
-            let s_pos_before = scanner3.pos;
             let res_idx = find_result(
                 word,
                 dm,
@@ -500,10 +497,6 @@ fn parse_loop(
                 hash_table,
                 collected_results,
             );
-            if let Some(ref mut r) = hash_table[res_idx]
-                && r.name_offset == s_pos_before {
-                    r.name_offset += offset_in_all_data;
-                }
             let num = scan_number(&mut scanner3);
             record(hash_table[res_idx].as_mut().unwrap(), num);
         }
@@ -511,17 +504,19 @@ fn parse_loop(
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 || args[1] != "--worker" {
-        spawn_worker();
+    let args = Args::from_env(FILE_PATH);
+    args.redirect_output().expect("Could not redirect output");
+    if !args.is_worker() {
+        args.run_parent().expect("failed to execute child");
         return;
     }
 
-    let file = File::open(FILE_PATH).expect("Could not open file");
+    let file = args.open_input().expect("Could not open file");
     let file_size = file.metadata().unwrap().len() as usize;
     let cursor = AtomicUsize::new(0);
 
-    let num_workers = thread::available_parallelism().unwrap().get();
+    let num_workers = args.threads;
+    let segment_size = args.chunk_size.unwrap_or(SEGMENT_SIZE);
 
     thread::scope(|s| {
         let mut handles = vec![];
@@ -536,6 +531,7 @@ fn main() {
                 let mut all_data = Vec::new();
                 parse_loop(
                     cursor_ref,
+                    segment_size,
                     file_ref,
                     file_size,
                     &mut collected_results,
@@ -581,12 +577,3 @@ fn main() {
         println!("}}");
     });
 }
-
-fn spawn_worker() {
-    let mut child = std::process::Command::new(std::env::current_exe().unwrap())
-        .arg("--worker")
-        .stdout(std::process::Stdio::inherit())
-        .spawn()
-        .expect("failed to execute child");
-    child.wait().expect("failed to wait on child");
-}
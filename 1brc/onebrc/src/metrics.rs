@@ -0,0 +1,142 @@
+//! How the chunks were shared out, with `--features metrics`.
+//!
+//! Every worker counts the chunks it claims and their bytes, and notes when it
+//! runs out of work. Once all of them are done, [`Metrics::log`] writes a line
+//! per worker to stderr, with how long it was busy and how long it then sat
+//! idle waiting for the last one to finish. Without the feature the types are
+//! empty and the calls compile to nothing, so the hot loop is unaffected.
+//!
+//! A [`Metrics`] can be shared by several runs over parts of one input, like
+//! the segments between checkpoints: each worker's counts add up, and busy and
+//! idle are measured to its last finish, so the log is still a line per worker.
+
+#[cfg(feature = "metrics")]
+use std::sync::Mutex;
+#[cfg(feature = "metrics")]
+use std::time::{Duration, Instant};
+
+/// The workers of one or more parallel runs.
+pub struct Metrics {
+    #[cfg(feature = "metrics")]
+    what: &'static str,
+    #[cfg(feature = "metrics")]
+    started: Instant,
+    #[cfg(feature = "metrics")]
+    done: Mutex<Vec<Done>>,
+}
+
+/// What a worker did, as [`Metrics::log`] reports it.
+#[cfg(feature = "metrics")]
+struct Done {
+    index: usize,
+    chunks: usize,
+    bytes: usize,
+    finished: Instant,
+}
+
+/// The counters of one worker; recorded in its [`Metrics`] when dropped.
+pub struct Worker<'a> {
+    #[cfg(feature = "metrics")]
+    metrics: &'a Metrics,
+    #[cfg(feature = "metrics")]
+    index: usize,
+    #[cfg(feature = "metrics")]
+    chunks: usize,
+    #[cfg(feature = "metrics")]
+    bytes: usize,
+    #[cfg(not(feature = "metrics"))]
+    metrics: std::marker::PhantomData<&'a Metrics>,
+}
+
+impl Metrics {
+    /// A run whose workers are logged as `what`, e.g. `thread` or `process`.
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn new(what: &'static str) -> Metrics {
+        Metrics {
+            #[cfg(feature = "metrics")]
+            what,
+            #[cfg(feature = "metrics")]
+            started: Instant::now(),
+            #[cfg(feature = "metrics")]
+            done: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The counters for worker `index`.
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn worker(&self, index: usize) -> Worker<'_> {
+        Worker {
+            #[cfg(feature = "metrics")]
+            metrics: self,
+            #[cfg(feature = "metrics")]
+            index,
+            #[cfg(feature = "metrics")]
+            chunks: 0,
+            #[cfg(feature = "metrics")]
+            bytes: 0,
+            #[cfg(not(feature = "metrics"))]
+            metrics: std::marker::PhantomData,
+        }
+    }
+
+    /// Write a line per finished worker to stderr, e.g.
+    /// `thread 3: 41 chunks, 812.4 MB, busy 1502.3 ms, idle 0.8 ms`.
+    pub fn log(&self) {
+        #[cfg(feature = "metrics")]
+        {
+            let mut done = self.done.lock().unwrap();
+            done.sort_by_key(|d| d.index);
+            let Some(last) = done.iter().map(|d| d.finished).max() else {
+                return;
+            };
+            let ms = |d: Duration| d.as_secs_f64() * 1e3;
+            for d in done.iter() {
+                eprintln!(
+                    "{} {}: {} chunks, {:.1} MB, busy {:.1} ms, idle {:.1} ms",
+                    self.what,
+                    d.index,
+                    d.chunks,
+                    d.bytes as f64 / 1e6,
+                    ms(d.finished - self.started),
+                    ms(last - d.finished),
+                );
+            }
+        }
+    }
+}
+
+impl Worker<'_> {
+    /// Count the chunk `[start, end)`.
+    #[inline(always)]
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn chunk(&mut self, start: usize, end: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            self.chunks += 1;
+            self.bytes += end - start;
+        }
+    }
+}
+
+impl Drop for Worker<'_> {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        {
+            let finished = Instant::now();
+            let mut done = self.metrics.done.lock().unwrap();
+            match done.iter_mut().find(|d| d.index == self.index) {
+                Some(d) => {
+                    d.chunks += self.chunks;
+                    d.bytes += self.bytes;
+                    d.finished = finished;
+                }
+                None => done.push(Done {
+                    index: self.index,
+                    chunks: self.chunks,
+                    bytes: self.bytes,
+                    finished,
+                }),
+            }
+        }
+    }
+}
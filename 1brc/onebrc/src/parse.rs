@@ -0,0 +1,328 @@
+//! Input formats other than the challenge's own (`--input-format`).
+//!
+//! An [`InputFormat`] says which byte separates the columns, which columns
+//! hold the station name and the temperature, whether lines may end in
+//! `"\r\n"`, and what the temperatures look like:
+//!
+//! ```text
+//! 1brc                                  name;12.3, the default
+//! general                               name;101.25 or name;1e1
+//! sep=tab,crlf                          name<TAB>12.3<CR><LF>
+//! general,sep=comma,key=1,value=3       2024-01-01,name,x,7.25
+//! ```
+//!
+//! The hot loop in [`chunk`](crate::chunk) handles any separator, but only
+//! the name in the first column and the temperature in the second, in the
+//! 1BRC shape `-?\d?\d\.\d`. [`Values::General`] also accepts values such
+//! as `101.25`, `-0.5`, `7` and `1e1`, and rejects anything that is not a
+//! number. Chunks that happen to be entirely in the 1BRC shape (checked by
+//! [`is_fixed_shape`]) still go through the branchless fast path; the others,
+//! and every chunk of a layout with the columns elsewhere, are split into
+//! fields line by line and parsed with [`parse_general`].
+//!
+//! Stats are kept in tenths of a degree either way, so general values are
+//! rounded to the nearest tenth (halves away from zero).
+
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// How the records of the input are laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputFormat {
+    pub values: Values,
+    /// The byte between columns.
+    pub separator: u8,
+    /// Column (from 0) of the station name.
+    pub key: usize,
+    /// Column (from 0) of the temperature.
+    pub value: usize,
+    /// Whether a `'\r'` before the `'\n'` is part of the line terminator.
+    pub crlf: bool,
+}
+
+impl Default for InputFormat {
+    fn default() -> Self {
+        Self {
+            values: Values::Fixed,
+            separator: b';',
+            key: 0,
+            value: 1,
+            crlf: false,
+        }
+    }
+}
+
+impl InputFormat {
+    /// Whether the fast path can find the name and temperature: they are the
+    /// first two columns.
+    pub fn is_two_column(&self) -> bool {
+        self.key == 0 && self.value == 1
+    }
+}
+
+/// What the temperature column may contain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Values {
+    /// `-99.9..=99.9` with exactly one fractional digit, trusted as is on
+    /// the fast path.
+    #[default]
+    Fixed,
+    /// Any decimal number, optionally with an exponent, validated.
+    General,
+}
+
+/// Separators that have a name in the spec; any other ASCII punctuation or
+/// space can be given as itself.
+const SEPARATORS: [(&str, u8); 5] = [
+    ("semicolon", b';'),
+    ("comma", b','),
+    ("tab", b'\t'),
+    ("space", b' '),
+    ("pipe", b'|'),
+];
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    /// A comma-separated list of `1brc`, `general`, `sep=SEP`, `key=N`,
+    /// `value=N` and `crlf`; see the module docs.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut format = InputFormat::default();
+        for item in s.split(',') {
+            let column = |v: &str| {
+                v.parse::<usize>()
+                    .map_err(|_| format!("invalid column {v:?} in input format"))
+            };
+            match item.split_once('=') {
+                None if item == "1brc" => format.values = Values::Fixed,
+                None if item == "general" => format.values = Values::General,
+                None if item == "crlf" => format.crlf = true,
+                Some(("sep", v)) => format.separator = separator(v)?,
+                Some(("key", v)) => format.key = column(v)?,
+                Some(("value", v)) => format.value = column(v)?,
+                _ => {
+                    return Err(format!(
+                        "unknown input format {item:?} (expected 1brc, general, \
+                         sep=SEP, key=N, value=N or crlf)"
+                    ));
+                }
+            }
+        }
+        if format.key == format.value {
+            return Err("the key and value columns must differ".to_string());
+        }
+        Ok(format)
+    }
+}
+
+fn separator(v: &str) -> Result<u8, String> {
+    if let Some(&(_, b)) = SEPARATORS.iter().find(|&&(name, _)| name == v) {
+        return Ok(b);
+    }
+    match v.as_bytes() {
+        // Not something that can be part of a number.
+        &[b] if (b.is_ascii_punctuation() || b == b' ') && !b"+-.".contains(&b) => Ok(b),
+        _ => Err(format!(
+            "invalid separator {v:?} (expected one punctuation character, \
+             semicolon, comma, tab, space or pipe)"
+        )),
+    }
+}
+
+/// The inverse of [`InputFormat::from_str`].
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.values {
+            Values::Fixed => "1brc",
+            Values::General => "general",
+        })?;
+        let default = InputFormat::default();
+        if self.separator != default.separator {
+            match SEPARATORS.iter().find(|&&(_, b)| b == self.separator) {
+                Some((name, _)) => write!(f, ",sep={name}")?,
+                None => write!(f, ",sep={}", self.separator as char)?,
+            }
+        }
+        if !self.is_two_column() {
+            write!(f, ",key={},value={}", self.key, self.value)?;
+        }
+        if self.crlf {
+            f.write_str(",crlf")?;
+        }
+        Ok(())
+    }
+}
+
+/// A record the input format does not accept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidRecord {
+    /// Byte offset of the start of the record in the input.
+    pub offset: u64,
+    pub reason: &'static str,
+}
+
+impl InvalidRecord {
+    /// The same record in an input that starts `base` bytes earlier.
+    pub(crate) fn shifted(self, base: usize) -> Self {
+        Self {
+            offset: self.offset + base as u64,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for InvalidRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid record at byte {}: {}", self.offset, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidRecord {}
+
+impl From<InvalidRecord> for std::io::Error {
+    fn from(record: InvalidRecord) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, record)
+    }
+}
+
+/// Largest absolute value, in tenths, that [`parse_general`] accepts.
+pub const MAX_TENTHS: i16 = i16::MAX;
+
+/// Parse a decimal number such as `12.3`, `-0.5`, `+7`, `.5`, `101.25` or
+/// `1e1` into tenths, rounding halves away from zero.
+pub fn parse_general(value: &[u8]) -> Result<i16, &'static str> {
+    let (negative, rest) = match value {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        rest => (false, rest),
+    };
+    let int_len = digits(rest);
+    let (int, rest) = rest.split_at(int_len);
+    let (frac, rest) = match rest {
+        [b'.', rest @ ..] => rest.split_at(digits(rest)),
+        rest => (&rest[..0], rest),
+    };
+    if int.is_empty() && frac.is_empty() {
+        return Err("temperature is not a number");
+    }
+    let exponent = match rest {
+        [] => 0,
+        [b'e' | b'E', rest @ ..] => {
+            let (negative, exp) = match rest {
+                [b'-', exp @ ..] => (true, exp),
+                [b'+', exp @ ..] => (false, exp),
+                exp => (false, exp),
+            };
+            if exp.is_empty() || digits(exp) != exp.len() {
+                return Err("temperature has a malformed exponent");
+            }
+            let exp = exp.iter().fold(0i64, |e, &d| {
+                e.saturating_mul(10).saturating_add((d - b'0') as i64)
+            });
+            if negative { -exp } else { exp }
+        }
+        _ => return Err("temperature is not a number"),
+    };
+
+    // The first `keep` digits of int and frac (the integer part moved by the
+    // exponent, and one more) are the tenths; the next one rounds them.
+    let digit = |i: i64| -> u32 {
+        let i = usize::try_from(i).ok();
+        let d = i.and_then(|i| int.get(i).or_else(|| frac.get(i - int.len())));
+        d.map_or(0, |&d| (d - b'0') as u32)
+    };
+    let keep = (int.len() as i64)
+        .saturating_add(exponent)
+        .saturating_add(1);
+    let total = (int.len() + frac.len()) as i64;
+    let mut tenths = 0u32;
+    for i in 0..keep.max(0) {
+        if i >= total && tenths == 0 {
+            break;
+        }
+        tenths = tenths * 10 + digit(i);
+        if tenths > MAX_TENTHS as u32 {
+            return Err("temperature is out of range");
+        }
+    }
+    if digit(keep) >= 5 {
+        tenths += 1;
+    }
+    if tenths > MAX_TENTHS as u32 {
+        return Err("temperature is out of range");
+    }
+    let tenths = tenths as i16;
+    Ok(if negative { -tenths } else { tenths })
+}
+
+/// Length of the run of ASCII digits at the start of `s`.
+fn digits(s: &[u8]) -> usize {
+    s.iter()
+        .position(|b| !b.is_ascii_digit())
+        .unwrap_or(s.len())
+}
+
+/// The station name and temperature columns of `line` (without its `'\n'`),
+/// as ranges into it.
+pub fn split_record(
+    line: &[u8],
+    format: &InputFormat,
+) -> Result<(Range<usize>, Range<usize>), &'static str> {
+    let line = match line {
+        [line @ .., b'\r'] if format.crlf => line,
+        line => line,
+    };
+    let (mut key, mut value) = (None, None);
+    let ends = memchr::memchr_iter(format.separator, line).chain([line.len()]);
+    let mut start = 0;
+    for (column, end) in ends.enumerate() {
+        if column == format.key {
+            key = Some(start..end);
+        } else if column == format.value {
+            value = Some(start..end);
+        }
+        if let (Some(key), Some(value)) = (&key, &value) {
+            return Ok((key.clone(), value.clone()));
+        }
+        start = end + 1;
+    }
+    Err(if memchr::memchr(format.separator, line).is_none() {
+        "missing separator"
+    } else if key.is_none() {
+        "no station name column"
+    } else {
+        "no temperature column"
+    })
+}
+
+/// Whether every line of `chunk` (which ends with a `'\n'`) is
+/// `name;-?\d?\d\.\d` (with `format`'s separator and terminator) with no
+/// other separator, i.e. safe for the fast path.
+pub fn is_fixed_shape(chunk: &[u8], format: &InputFormat) -> bool {
+    let mut start = 0;
+    let mut lines = 0;
+    for nl in memchr::memchr_iter(b'\n', chunk) {
+        let line = match &chunk[start..nl] {
+            [line @ .., b'\r'] if format.crlf => line,
+            line => line,
+        };
+        start = nl + 1;
+        let [rest @ .., a, b'.', b] = line else {
+            return false;
+        };
+        if !a.is_ascii_digit() || !b.is_ascii_digit() {
+            return false;
+        }
+        let rest = match rest {
+            [rest @ .., d] if d.is_ascii_digit() => rest,
+            rest => rest,
+        };
+        let rest = rest.strip_suffix(b"-").unwrap_or(rest);
+        if rest.last() != Some(&format.separator) {
+            return false;
+        }
+        lines += 1;
+    }
+    memchr::memchr_iter(format.separator, chunk).count() == lines
+}
@@ -0,0 +1,94 @@
+//! Partial results: a [`Report`] in a compact binary form that can be
+//! written by one run and merged with others later (`--format partial`,
+//! `candidate_22 merge`).
+//!
+//! ```text
+//! "1brc-prt" | version: u32 | stats: u8 (0 basic, 1 extended) | stations: u64
+//! per station, by name: name length: u32 | name (UTF-8) | Stats::encode
+//! ```
+//!
+//! All integers are little-endian. Merging is [`Report::merge`], which only
+//! takes minimums, maximums and sums, so it is associative and commutative;
+//! since stations are written in name order, equal reports give identical
+//! files whatever way the shards were grouped.
+
+use crate::report::Report;
+use crate::stats::{Stats, StatsMode, take};
+use std::io::{self, ErrorKind};
+
+const MAGIC: &[u8; 8] = b"1brc-prt";
+const VERSION: u32 = 2;
+/// Magic, version and stats byte.
+const HEADER_LEN: usize = 13;
+
+/// Append `report` in the partial format.
+pub fn encode<S: Stats>(report: &Report<S>, out: &mut Vec<u8>) {
+    out.extend(MAGIC);
+    out.extend(VERSION.to_le_bytes());
+    out.push(match mode_of::<S>() {
+        StatsMode::Basic => 0,
+        StatsMode::Extended => 1,
+    });
+    out.extend((report.stations.len() as u64).to_le_bytes());
+    for (name, stats) in &report.stations {
+        out.extend((name.len() as u32).to_le_bytes());
+        out.extend(name.as_bytes());
+        stats.encode(out);
+    }
+}
+
+/// Read a partial written by [`encode`] from the front of `input`, advancing
+/// it. `None` if it is not one, was written with other [`Stats`], or has a
+/// station twice or stats no records could give (see [`Stats::decode`]).
+pub(crate) fn decode_from<S: Stats>(input: &mut &[u8]) -> Option<Report<S>> {
+    if mode(input)? != mode_of::<S>() {
+        return None;
+    }
+    *input = &input[HEADER_LEN..];
+
+    let mut report = Report::default();
+    for _ in 0..u64::from_le_bytes(take(input)?) {
+        let len = u32::from_le_bytes(take(input)?) as usize;
+        let name = input.get(..len)?;
+        *input = &input[len..];
+        let name = String::from_utf8(name.to_vec()).ok()?;
+        if report.stations.insert(name, S::decode(input)?).is_some() {
+            return None;
+        }
+    }
+    Some(report)
+}
+
+/// Parse a whole partial file.
+pub fn decode<S: Stats>(bytes: &[u8]) -> io::Result<Report<S>> {
+    let invalid = |why| io::Error::new(ErrorKind::InvalidData, why);
+    match mode(bytes) {
+        None => return Err(invalid("not a partial result of this version")),
+        Some(mode) if mode != mode_of::<S>() => return Err(invalid("written with other --stats")),
+        Some(_) => {}
+    }
+    let mut input = bytes;
+    decode_from(&mut input)
+        .filter(|_| input.is_empty())
+        .ok_or_else(|| invalid("truncated or corrupt partial result"))
+}
+
+/// Which [`Stats`] a partial holds, from its header.
+pub fn mode(bytes: &[u8]) -> Option<StatsMode> {
+    let header = bytes.get(..HEADER_LEN)?;
+    if &header[..8] != MAGIC || header[8..12] != VERSION.to_le_bytes() {
+        return None;
+    }
+    match header[12] {
+        0 => Some(StatsMode::Basic),
+        1 => Some(StatsMode::Extended),
+        _ => None,
+    }
+}
+
+fn mode_of<S: Stats>() -> StatsMode {
+    match S::default().extended() {
+        None => StatsMode::Basic,
+        Some(_) => StatsMode::Extended,
+    }
+}
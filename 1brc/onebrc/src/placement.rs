@@ -0,0 +1,237 @@
+//! Which CPUs the worker threads run on (`--pin`, `--cpus`,
+//! `--prefer-pcores`).
+//!
+//! By default workers are ordinary threads that the scheduler moves around.
+//! A [`Placement`] restricts them to some CPUs and can pin each worker to one
+//! of them. On hybrid CPUs (the 285K has 8 P-cores and 16 E-cores) a pinned
+//! worker on a slower core claims proportionally smaller chunks, so that it
+//! does not finish long after the others.
+//!
+//! The [`Topology`] is the calling process's affinity mask, with each CPU's
+//! class and capacity read from `/sys/devices/system/cpu`: `cpu_capacity`
+//! where the kernel exposes it (CPUs below the largest capacity are
+//! efficiency cores), otherwise `topology/core_type`.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+
+/// Where the kernel describes the CPUs.
+pub const SYSFS_CPUS: &str = "/sys/devices/system/cpu";
+
+/// The capacity of the fastest CPUs, as in `cpu_capacity`.
+pub const FULL_CAPACITY: u32 = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoreClass {
+    Performance,
+    Efficiency,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cpu {
+    pub id: usize,
+    pub class: CoreClass,
+    /// Relative speed, [`FULL_CAPACITY`] for the fastest; half of that for
+    /// efficiency cores where the kernel does not say.
+    pub capacity: u32,
+}
+
+/// The CPUs this process may run on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Topology {
+    pub cpus: Vec<Cpu>,
+}
+
+impl Topology {
+    /// The affinity mask of the calling thread, described by [`SYSFS_CPUS`].
+    pub fn detect() -> io::Result<Topology> {
+        Ok(Topology::read(Path::new(SYSFS_CPUS), &affinity()?))
+    }
+
+    /// The CPUs in `allowed`, described by the sysfs tree at `root`.
+    pub fn read(root: &Path, allowed: &[usize]) -> Topology {
+        let read = |cpu: usize, file: &str| {
+            fs::read_to_string(root.join(format!("cpu{cpu}")).join(file))
+                .ok()
+                .map(|s| s.trim().to_string())
+        };
+        let capacities: Vec<Option<u32>> = allowed
+            .iter()
+            .map(|&cpu| read(cpu, "cpu_capacity")?.parse().ok())
+            .collect();
+        let max = capacities.iter().flatten().copied().max();
+
+        let cpus = allowed
+            .iter()
+            .zip(capacities)
+            .map(|(&id, capacity)| {
+                let class = match (capacity, max) {
+                    (Some(capacity), Some(max)) if capacity < max => CoreClass::Efficiency,
+                    (Some(_), Some(_)) => CoreClass::Performance,
+                    _ => match read(id, "topology/core_type") {
+                        Some(kind) if is_efficiency(&kind) => CoreClass::Efficiency,
+                        _ => CoreClass::Performance,
+                    },
+                };
+                let capacity = match (capacity, max, class) {
+                    (Some(capacity), Some(max), _) => {
+                        (capacity as u64 * FULL_CAPACITY as u64 / max.max(1) as u64) as u32
+                    }
+                    (_, _, CoreClass::Performance) => FULL_CAPACITY,
+                    (_, _, CoreClass::Efficiency) => FULL_CAPACITY / 2,
+                };
+                Cpu {
+                    id,
+                    class,
+                    capacity,
+                }
+            })
+            .collect();
+        Topology { cpus }
+    }
+}
+
+/// `core_type` names for efficiency cores: `intel_atom`, `atom`, `E`, ...
+fn is_efficiency(kind: &str) -> bool {
+    let kind = kind.to_ascii_lowercase();
+    kind == "e" || kind.contains("atom") || kind.contains("efficien")
+}
+
+/// How the workers are placed on CPUs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    /// The CPUs the workers run on, in the order they are handed out.
+    pub cpus: Vec<Cpu>,
+    /// Whether worker `i` is pinned to `cpus[i % cpus.len()]`, rather than
+    /// allowed on all of them.
+    pub pin: bool,
+}
+
+impl Placement {
+    /// Workers on the CPUs of `topology`, or on those of them in `only`.
+    /// `prefer_pcores` pins them, performance cores first.
+    pub fn new(
+        topology: &Topology,
+        only: Option<&[usize]>,
+        pin: bool,
+        prefer_pcores: bool,
+    ) -> Result<Placement, String> {
+        let mut cpus = topology.cpus.clone();
+        if let Some(only) = only {
+            if let Some(cpu) = only.iter().find(|&&id| !cpus.iter().any(|c| c.id == id)) {
+                return Err(format!("CPU {cpu} is not available to this process"));
+            }
+            cpus.retain(|cpu| only.contains(&cpu.id));
+        }
+        if cpus.is_empty() {
+            return Err("no CPUs to run on".to_string());
+        }
+        if prefer_pcores {
+            // Stable, so each class stays in CPU order.
+            cpus.sort_by_key(|cpu| cpu.class != CoreClass::Performance);
+        }
+        Ok(Placement {
+            cpus,
+            pin: pin || prefer_pcores,
+        })
+    }
+
+    /// The CPU worker `index` is pinned to, if workers are pinned.
+    pub fn cpu(&self, index: usize) -> Option<&Cpu> {
+        self.pin.then(|| &self.cpus[index % self.cpus.len()])
+    }
+
+    /// Move the calling thread to where worker `index` runs, and return the
+    /// chunk size it should claim instead of `chunk_size`.
+    pub fn enter(&self, index: usize, chunk_size: usize) -> usize {
+        let ids: Vec<usize> = match self.cpu(index) {
+            Some(cpu) => vec![cpu.id],
+            None => self.cpus.iter().map(|cpu| cpu.id).collect(),
+        };
+        // Best effort: a CPU may have gone offline since the plan was made.
+        let _ = set_affinity(&ids);
+        self.chunk_size(index, chunk_size)
+    }
+
+    /// `chunk_size` scaled by the capacity of worker `index`'s CPU relative
+    /// to the fastest one used, if workers are pinned.
+    pub fn chunk_size(&self, index: usize, chunk_size: usize) -> usize {
+        let Some(cpu) = self.cpu(index) else {
+            return chunk_size;
+        };
+        let max = self.cpus.iter().map(|cpu| cpu.capacity).max().unwrap_or(1);
+        let scaled = chunk_size as u64 * cpu.capacity as u64 / max.max(1) as u64;
+        (scaled as usize).max(1)
+    }
+}
+
+impl fmt::Display for Placement {
+    /// E.g. `pinned to 0,1,2,3 (4 performance, 0 efficiency)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids: Vec<String> = self.cpus.iter().map(|cpu| cpu.id.to_string()).collect();
+        let count = |class| self.cpus.iter().filter(|cpu| cpu.class == class).count();
+        write!(
+            f,
+            "{} {} ({} performance, {} efficiency)",
+            if self.pin { "pinned to" } else { "running on" },
+            ids.join(","),
+            count(CoreClass::Performance),
+            count(CoreClass::Efficiency),
+        )
+    }
+}
+
+/// Parse a CPU list such as `0-7`, `0,2,4` or `0-3,8-11`, as in `--cpus`
+/// and the sysfs `*_list` files. Sorted, without duplicates.
+pub fn parse_cpu_list(s: &str) -> Result<Vec<usize>, String> {
+    let invalid = || format!("invalid CPU list {s:?}");
+    let mut cpus = Vec::new();
+    for part in s.trim().split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first, last),
+            None => (part, part),
+        };
+        let first: usize = first.parse().map_err(|_| invalid())?;
+        let last: usize = last.parse().map_err(|_| invalid())?;
+        if first > last {
+            return Err(invalid());
+        }
+        if last >= libc::CPU_SETSIZE as usize {
+            return Err(format!("CPU {last} is out of range"));
+        }
+        cpus.extend(first..=last);
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// The CPUs in the calling thread's affinity mask.
+pub fn affinity() -> io::Result<Vec<usize>> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect())
+    }
+}
+
+/// Restrict the calling thread to `cpus`.
+pub fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
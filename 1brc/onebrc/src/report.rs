@@ -0,0 +1,314 @@
+use crate::group::{GroupBy, UNKNOWN};
+use crate::keys::Keys;
+use crate::parse::InvalidRecord;
+use crate::partial;
+use crate::stats::{StationStats, Stats};
+use crate::table::NameTable;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Output format of a [`Report`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// `{Abha=-33.8/18.0/71.1, ...}`, see the `Display` impl.
+    #[default]
+    Challenge,
+    /// A JSON array with one object per station.
+    Json,
+    /// `station,min,mean,max,count,sum` with a header line.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+    /// The binary [`partial`](crate::partial) format, for merging later.
+    Partial,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "challenge" => Ok(Format::Challenge),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "ndjson" => Ok(Format::Ndjson),
+            "partial" => Ok(Format::Partial),
+            _ => Err(format!(
+                "unknown format {s:?} (expected challenge, json, csv, ndjson or partial)"
+            )),
+        }
+    }
+}
+
+/// A value in tenths, printed exactly: `Tenths(-123)` is `-12.3`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tenths(pub i64);
+
+impl fmt::Display for Tenths {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{}", abs / 10, abs % 10)
+    }
+}
+
+/// Merged result of a run, ordered by station name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report<S = StationStats> {
+    pub stations: BTreeMap<String, S>,
+}
+
+impl<S> Default for Report<S> {
+    fn default() -> Self {
+        Self {
+            stations: BTreeMap::new(),
+        }
+    }
+}
+
+impl<S: Stats> Report<S> {
+    /// [`Report::merge_table_keyed`] with the names as they are.
+    ///
+    /// # Panics
+    /// If a name is not UTF-8.
+    pub fn merge_table(&mut self, table: &NameTable<S>) {
+        self.merge_table_keyed(table, &Keys::default())
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Merge `table`, turning each of its names into a key with `keys`. A name
+    /// that `keys` rejects is returned with its offset in the table's data.
+    pub fn merge_table_keyed(
+        &mut self,
+        table: &NameTable<S>,
+        keys: &Keys,
+    ) -> Result<(), InvalidRecord> {
+        for (offset, name, stats) in table.iter_names() {
+            let key = keys
+                .key(name)
+                .map_err(|reason| InvalidRecord { offset, reason })?;
+            self.merge_station(&key, stats);
+        }
+        Ok(())
+    }
+
+    pub fn merge_station(&mut self, name: &str, stats: &S) {
+        match self.stations.get_mut(name) {
+            Some(entry) => entry.merge(stats),
+            None => {
+                self.stations.insert(name.to_string(), stats.clone());
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Report<S>) {
+        for (k, v) in &other.stations {
+            self.merge_station(k, v);
+        }
+    }
+
+    /// The stations merged by their group under `by`; those without one are
+    /// merged into [`UNKNOWN`].
+    pub fn grouped(&self, by: &GroupBy) -> Report<S> {
+        let mut report = Report::default();
+        for (k, v) in &self.stations {
+            report.merge_station(by.group(k).unwrap_or(UNKNOWN), v);
+        }
+        report
+    }
+
+    /// Write the report in `format`, followed by a newline (except for the
+    /// binary [`Format::Partial`]).
+    ///
+    /// Stations are ordered by the bytes of their name, and all values are
+    /// rendered from the integer tenths, so equal reports give identical
+    /// output. In every format the mean is rounded half up (see
+    /// [`StationStats::mean_tenths`]); the structured ones add `sum`, the exact
+    /// total, and extended stats add `stddev` (three decimals) and
+    /// `p50`/`p90`/`p99`.
+    pub fn write_to<W: Write>(&self, out: &mut W, format: Format) -> io::Result<()> {
+        let names = column_names::<S>();
+        match format {
+            Format::Challenge => writeln!(out, "{self}"),
+            Format::Json => {
+                out.write_all(b"[")?;
+                let mut sep = "\n";
+                for (k, v) in &self.stations {
+                    out.write_all(sep.as_bytes())?;
+                    write_json_object(out, &names, k, &columns(v))?;
+                    sep = ",\n";
+                }
+                out.write_all(b"\n]\n")
+            }
+            Format::Csv => {
+                writeln!(out, "station,{}", names.join(","))?;
+                for (k, v) in &self.stations {
+                    write_csv_field(out, k)?;
+                    for value in columns(v) {
+                        write!(out, ",{value}")?;
+                    }
+                    out.write_all(b"\n")?;
+                }
+                Ok(())
+            }
+            Format::Ndjson => {
+                for (k, v) in &self.stations {
+                    write_json_object(out, &names, k, &columns(v))?;
+                    out.write_all(b"\n")?;
+                }
+                Ok(())
+            }
+            Format::Partial => {
+                let mut buf = Vec::new();
+                partial::encode(self, &mut buf);
+                out.write_all(&buf)
+            }
+        }
+    }
+}
+
+/// Stations by their raw name bytes, for a worker that merges many short-lived
+/// [`NameTable`]s (one per reused buffer). Each distinct name is turned into
+/// its key once, the first time the worker sees it, rather than once per
+/// table.
+pub(crate) struct RawReport<S> {
+    keys: Keys,
+    stations: HashMap<Box<[u8]>, (String, S)>,
+}
+
+impl<S: Stats> RawReport<S> {
+    pub(crate) fn new(keys: Keys) -> Self {
+        Self {
+            keys,
+            stations: HashMap::new(),
+        }
+    }
+
+    /// Like [`Report::merge_table_keyed`].
+    pub(crate) fn merge_table(&mut self, table: &NameTable<S>) -> Result<(), InvalidRecord> {
+        for (offset, name, stats) in table.iter_names() {
+            match self.stations.get_mut(name) {
+                Some((_, entry)) => entry.merge(stats),
+                None => {
+                    let key = self
+                        .keys
+                        .key(name)
+                        .map_err(|reason| InvalidRecord { offset, reason })?;
+                    let entry = (key.into_owned(), stats.clone());
+                    self.stations.insert(name.into(), entry);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the stations by key; names with the same key become one.
+    pub(crate) fn into_report(self) -> Report<S> {
+        let mut report = Report::default();
+        for (key, stats) in self.stations.into_values() {
+            report.merge_station(&key, &stats);
+        }
+        report
+    }
+}
+
+/// The 1BRC challenge format: `{Abha=-33.8/18.0/71.1, ...}`, with the mean
+/// rounded half up like the structured formats.
+impl<S: Stats> fmt::Display for Report<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("{")?;
+        let mut sep = "";
+        for (k, v) in &self.stations {
+            let v = v.basic();
+            write!(
+                f,
+                "{sep}{k}={}/{}/{}",
+                Tenths(v.min as i64),
+                Tenths(v.mean_tenths()),
+                Tenths(v.max as i64)
+            )?;
+            sep = ", ";
+        }
+        f.write_str("}")
+    }
+}
+
+const BASIC_COLUMNS: [&str; 5] = ["min", "mean", "max", "count", "sum"];
+const EXTENDED_COLUMNS: [&str; 4] = ["stddev", "p50", "p90", "p99"];
+
+fn column_names<S: Stats>() -> Vec<&'static str> {
+    let mut names = BASIC_COLUMNS.to_vec();
+    if S::default().extended().is_some() {
+        names.extend(EXTENDED_COLUMNS);
+    }
+    names
+}
+
+/// Values for [`column_names`], rendered as JSON numbers.
+fn columns<S: Stats>(v: &S) -> Vec<String> {
+    let b = v.basic();
+    let mut values = vec![
+        Tenths(b.min as i64).to_string(),
+        Tenths(b.mean_tenths()).to_string(),
+        Tenths(b.max as i64).to_string(),
+        b.count.to_string(),
+        Tenths(b.total).to_string(),
+    ];
+    if let Some(x) = v.extended() {
+        values.push(format!("{:.3}", x.stddev()));
+        for p in [50, 90, 99] {
+            values.push(Tenths(x.percentile(p) as i64).to_string());
+        }
+    }
+    values
+}
+
+fn write_json_object<W: Write>(
+    out: &mut W,
+    names: &[&str],
+    station: &str,
+    values: &[String],
+) -> io::Result<()> {
+    out.write_all(b"{\"station\":")?;
+    write_json_string(out, station)?;
+    for (name, value) in names.iter().zip(values) {
+        write!(out, ",\"{name}\":{value}")?;
+    }
+    out.write_all(b"}")
+}
+
+fn write_json_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, b) in s.bytes().enumerate() {
+        let escaped = match b {
+            b'"' => "\\\"",
+            b'\\' => "\\\\",
+            b'\n' => "\\n",
+            b'\r' => "\\r",
+            b'\t' => "\\t",
+            0..0x20 => "",
+            _ => continue,
+        };
+        out.write_all(&s.as_bytes()[start..i])?;
+        if escaped.is_empty() {
+            write!(out, "\\u{b:04x}")?;
+        } else {
+            out.write_all(escaped.as_bytes())?;
+        }
+        start = i + 1;
+    }
+    out.write_all(&s.as_bytes()[start..])?;
+    out.write_all(b"\"")
+}
+
+/// RFC 4180: quote fields containing a separator, quote or line break.
+fn write_csv_field<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    if s.contains([',', '"', '\n', '\r']) {
+        write!(out, "\"{}\"", s.replace('"', "\"\""))
+    } else {
+        out.write_all(s.as_bytes())
+    }
+}
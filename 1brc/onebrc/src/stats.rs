@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Running aggregate for one station. Temperatures are in tenths of a degree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StationStats {
+    pub min: i16,
+    pub max: i16,
+    pub count: usize,
+    pub total: i64,
+}
+
+impl Default for StationStats {
+    fn default() -> Self {
+        Self {
+            min: i16::MAX,
+            max: i16::MIN,
+            count: 0,
+            total: 0,
+        }
+    }
+}
+
+impl StationStats {
+    #[inline(always)]
+    pub fn record(&mut self, temp: i16) {
+        self.count += 1;
+        self.min = self.min.min(temp);
+        self.max = self.max.max(temp);
+        self.total += temp as i64;
+    }
+
+    pub fn merge(&mut self, other: &StationStats) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+        self.total += other.total;
+    }
+
+    /// Mean in degrees (not tenths).
+    pub fn mean(&self) -> f64 {
+        self.total as f64 / 10.0 / self.count as f64
+    }
+
+    /// Mean in tenths, rounded half up like the Java reference (`Math.round`),
+    /// computed without going through floating point.
+    pub fn mean_tenths(&self) -> i64 {
+        let count = self.count as i64;
+        (2 * self.total + count).div_euclid(2 * count)
+    }
+}
+
+/// Temperatures in the 1BRC data are in `-999..=999` tenths, so one bucket
+/// per possible value.
+pub const HISTOGRAM_BUCKETS: usize = 1999;
+
+const HISTOGRAM_OFFSET: i16 = 999;
+
+/// A per-station aggregate the scan loop can record into.
+///
+/// [`NameTable`](crate::NameTable), the chunk loop and [`Report`](crate::Report)
+/// are generic over it, so the default [`StationStats`] path compiles to the
+/// same code as before and the extra work of [`ExtendedStats`] is opt-in.
+pub trait Stats: Clone + Default {
+    fn record(&mut self, temp: i16);
+    fn merge(&mut self, other: &Self);
+    fn basic(&self) -> &StationStats;
+
+    /// `Some` for stats that carry more than min/mean/max.
+    fn extended(&self) -> Option<&ExtendedStats> {
+        None
+    }
+
+    /// Append a little-endian encoding of `self`, e.g. for a checkpoint.
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Read back what [`Stats::encode`] wrote and advance `input` past it.
+    /// `None` if `input` is cut short or malformed, or is not the stats of at
+    /// least one record (no count, min above max, ...).
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+}
+
+/// The next `N` bytes of `input`, advancing it.
+pub(crate) fn take<const N: usize>(input: &mut &[u8]) -> Option<[u8; N]> {
+    let (head, rest) = input.split_first_chunk::<N>()?;
+    *input = rest;
+    Some(*head)
+}
+
+impl Stats for StationStats {
+    #[inline(always)]
+    fn record(&mut self, temp: i16) {
+        StationStats::record(self, temp)
+    }
+
+    fn merge(&mut self, other: &Self) {
+        StationStats::merge(self, other)
+    }
+
+    fn basic(&self) -> &StationStats {
+        self
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.min.to_le_bytes());
+        out.extend(self.max.to_le_bytes());
+        out.extend((self.count as u64).to_le_bytes());
+        out.extend(self.total.to_le_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let stats = Self {
+            min: i16::from_le_bytes(take(input)?),
+            max: i16::from_le_bytes(take(input)?),
+            count: u64::from_le_bytes(take(input)?) as usize,
+            total: i64::from_le_bytes(take(input)?),
+        };
+        // A zero count would divide by zero in the mean.
+        (stats.count > 0 && stats.min <= stats.max).then_some(stats)
+    }
+}
+
+/// [`StationStats`] plus a sum of squares and a full histogram, for the
+/// standard deviation and exact percentiles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedStats {
+    pub basic: StationStats,
+    /// Sum of `temp * temp`, in tenths squared.
+    pub sum_squares: i128,
+    /// `histogram[t + 999]` counts the records with temperature `t` tenths.
+    pub histogram: Box<[u64]>,
+    /// Counts of the temperatures outside the histogram, which only
+    /// `--input-format general` produces.
+    pub outliers: BTreeMap<i16, u64>,
+}
+
+impl Default for ExtendedStats {
+    fn default() -> Self {
+        Self {
+            basic: StationStats::default(),
+            sum_squares: 0,
+            histogram: vec![0; HISTOGRAM_BUCKETS].into_boxed_slice(),
+            outliers: BTreeMap::new(),
+        }
+    }
+}
+
+impl ExtendedStats {
+    /// Population variance in degrees squared.
+    pub fn variance(&self) -> f64 {
+        let n = self.basic.count as i128;
+        let total = self.basic.total as i128;
+        // Exact up to the final division: n² var = n Σt² - (Σt)².
+        let scaled = n * self.sum_squares - total * total;
+        scaled as f64 / (n * n) as f64 / 100.0
+    }
+
+    /// Population standard deviation in degrees.
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// The `percent`th percentile in tenths, by the nearest-rank method: the
+    /// smallest recorded value with at least `percent`% of the records at or
+    /// below it. Always one of the recorded temperatures.
+    pub fn percentile(&self, percent: u32) -> i16 {
+        let n = self.basic.count as u64;
+        let rank = (n * percent.min(100) as u64).div_ceil(100).max(1);
+        let below = self.outliers.range(..-HISTOGRAM_OFFSET);
+        let above = self.outliers.range(HISTOGRAM_OFFSET + 1..);
+        let histogram = self.histogram.iter().enumerate();
+        let counts = below
+            .map(|(&t, &c)| (t, c))
+            .chain(histogram.map(|(i, &c)| (i as i16 - HISTOGRAM_OFFSET, c)))
+            .chain(above.map(|(&t, &c)| (t, c)));
+        let mut seen = 0;
+        for (t, c) in counts {
+            seen += c;
+            if seen >= rank {
+                return t;
+            }
+        }
+        self.basic.max
+    }
+}
+
+impl Stats for ExtendedStats {
+    #[inline(always)]
+    fn record(&mut self, temp: i16) {
+        self.basic.record(temp);
+        self.sum_squares += (temp as i32 * temp as i32) as i128;
+        match self
+            .histogram
+            .get_mut((temp as i32 + HISTOGRAM_OFFSET as i32) as usize)
+        {
+            Some(c) => *c += 1,
+            None => *self.outliers.entry(temp).or_default() += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.basic.merge(&other.basic);
+        self.sum_squares += other.sum_squares;
+        for (a, b) in self.histogram.iter_mut().zip(other.histogram.iter()) {
+            *a += b;
+        }
+        for (&t, &c) in &other.outliers {
+            *self.outliers.entry(t).or_default() += c;
+        }
+    }
+
+    fn basic(&self) -> &StationStats {
+        &self.basic
+    }
+
+    fn extended(&self) -> Option<&ExtendedStats> {
+        Some(self)
+    }
+
+    /// Only the non-empty histogram buckets are written, then the outliers.
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.basic.encode(out);
+        out.extend(self.sum_squares.to_le_bytes());
+        let used = self.histogram.iter().filter(|&&c| c != 0).count();
+        out.extend((used as u16).to_le_bytes());
+        for (i, &c) in self.histogram.iter().enumerate() {
+            if c != 0 {
+                out.extend((i as u16).to_le_bytes());
+                out.extend(c.to_le_bytes());
+            }
+        }
+        out.extend((self.outliers.len() as u32).to_le_bytes());
+        for (&t, &c) in &self.outliers {
+            out.extend(t.to_le_bytes());
+            out.extend(c.to_le_bytes());
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let mut stats = Self {
+            basic: StationStats::decode(input)?,
+            sum_squares: i128::from_le_bytes(take(input)?),
+            ..Self::default()
+        };
+        for _ in 0..u16::from_le_bytes(take(input)?) {
+            let i = u16::from_le_bytes(take(input)?) as usize;
+            *stats.histogram.get_mut(i)? = u64::from_le_bytes(take(input)?);
+        }
+        for _ in 0..u32::from_le_bytes(take(input)?) {
+            let t = i16::from_le_bytes(take(input)?);
+            stats.outliers.insert(t, u64::from_le_bytes(take(input)?));
+        }
+        // The percentiles walk the counts expecting to find all the records.
+        let counted = stats
+            .histogram
+            .iter()
+            .chain(stats.outliers.values())
+            .try_fold(0u64, |sum, &c| sum.checked_add(c))?;
+        (counted == stats.basic.count as u64).then_some(stats)
+    }
+}
+
+/// Which [`Stats`] a binary aggregates into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StatsMode {
+    /// min/mean/max/count/sum, the fast default.
+    #[default]
+    Basic,
+    /// Adds standard deviation and p50/p90/p99 (see [`ExtendedStats`]).
+    Extended,
+}
+
+impl FromStr for StatsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "basic" => Ok(StatsMode::Basic),
+            "extended" => Ok(StatsMode::Extended),
+            _ => Err(format!(
+                "unknown stats mode {s:?} (expected basic or extended)"
+            )),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Aggregation from a `Read` (pipes, FIFOs, decompressors) without mmap.
+//!
+//! The calling thread reads newline-aligned chunks into a fixed pool of
+//! buffers; the workers run the same [`chunk_statistics`] loop over
+//! them as the mmap path does. Every buffer ends with [`PADDING`] zero bytes,
+//! so the SWAR loads stay inside it. A partial last line is carried over to
+//! the next buffer, and a missing final `'\n'` is added at EOF.
+//!
+//! Buffers are reused, so the [`NameTable`] (which points into its buffer) is
+//! merged into a per-thread report by raw name after each chunk and then
+//! recycled. That keys each distinct name once per thread, not once per chunk.
+
+use crate::chunk::{PADDING, chunk_statistics};
+use crate::parse::InvalidRecord;
+use crate::report::{RawReport, Report};
+use crate::stats::Stats;
+use crate::table::NameTable;
+use crate::{Options, TABLE_CAPACITY};
+use memchr::memrchr;
+use std::io::{self, ErrorKind, Read};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+/// A filled buffer: records in `buf[..len]`, followed by padding. `offset`
+/// is where `buf` starts in the input.
+struct Chunk {
+    buf: Vec<u8>,
+    len: usize,
+    offset: usize,
+}
+
+/// Aggregate all records read from `reader`. Gives the same [`Report`] as
+/// [`aggregate_with`](crate::aggregate_with) on the same bytes.
+pub fn aggregate_reader<S, R>(reader: R, options: Options) -> io::Result<Report<S>>
+where
+    S: Stats + Send,
+    R: Read,
+{
+    aggregate_lines(reader, options, None)
+}
+
+/// Like [`aggregate_reader`], but if `partial` is given an unterminated last
+/// line is moved there instead of being aggregated. Used to stitch together
+/// lines that span independently decompressed frames.
+pub(crate) fn aggregate_lines<S, R>(
+    mut reader: R,
+    options: Options,
+    partial: Option<&mut Vec<u8>>,
+) -> io::Result<Report<S>>
+where
+    S: Stats + Send,
+    R: Read,
+{
+    let threads = options.threads.max(1);
+    let chunk_size = options.chunk_size.max(1);
+    let pool = threads * 2;
+
+    let (free_tx, free_rx) = mpsc::sync_channel::<Vec<u8>>(pool);
+    for _ in 0..pool {
+        free_tx
+            .send(Vec::with_capacity(chunk_size + PADDING))
+            .unwrap();
+    }
+    let (full_tx, full_rx) = mpsc::sync_channel::<Chunk>(pool);
+    let full_rx = Mutex::new(full_rx);
+
+    thread::scope(|s| {
+        let mut handles = Vec::with_capacity(threads);
+        for index in 0..threads {
+            let free_tx = free_tx.clone();
+            let full_rx = &full_rx;
+            handles.push(s.spawn(move || {
+                // The reader picks the chunk sizes here.
+                options.enter_worker(index);
+                worker::<S>(full_rx, free_tx, options)
+            }));
+        }
+        drop(free_tx);
+
+        // On error, dropping full_tx still lets the workers finish.
+        read_chunks(&mut reader, chunk_size, &free_rx, full_tx, partial)?;
+
+        let mut report = Report::default();
+        let mut invalid = Vec::new();
+        for handle in handles {
+            match handle.join().unwrap() {
+                Ok(part) => report.merge(&part),
+                Err(e) => invalid.push(e),
+            }
+        }
+        match invalid.into_iter().min_by_key(|e| e.offset) {
+            Some(e) => Err(e.into()),
+            None => Ok(report),
+        }
+    })
+}
+
+/// Stops at the first invalid record; the reader carries on with the others.
+fn worker<S: Stats>(
+    full_rx: &Mutex<Receiver<Chunk>>,
+    free_tx: SyncSender<Vec<u8>>,
+    options: Options,
+) -> Result<Report<S>, InvalidRecord> {
+    let mut report = RawReport::new(options.keys);
+    let mut table = NameTable::<S>::with_capacity(&[], TABLE_CAPACITY).with_filter(options.filter);
+    loop {
+        // Hold the lock only for the recv, not while processing.
+        let chunk = full_rx.lock().unwrap().recv();
+        let Ok(Chunk { buf, len, offset }) = chunk else {
+            break;
+        };
+
+        let mut statistics = table.recycle(&buf);
+        chunk_statistics(&buf, 0, len, &mut statistics, options.input, options.kernel)
+            .and_then(|()| report.merge_table(&statistics))
+            .map_err(|e| e.shifted(offset))?;
+        table = statistics.recycle(&[]);
+
+        // The reader may already be gone; then the buffer is simply dropped.
+        let _ = free_tx.send(buf);
+    }
+    Ok(report.into_report())
+}
+
+/// Fill buffers from `free` with newline-aligned chunks of at least
+/// `chunk_size` bytes (or up to EOF) and send them to `full`. An unterminated
+/// last line goes to `partial` if given, and is completed otherwise.
+fn read_chunks<R: Read>(
+    reader: &mut R,
+    chunk_size: usize,
+    free: &Receiver<Vec<u8>>,
+    full: SyncSender<Chunk>,
+    mut partial: Option<&mut Vec<u8>>,
+) -> io::Result<()> {
+    let mut carry = Vec::new();
+    let mut eof = false;
+    let mut offset = 0;
+
+    while !eof {
+        // Every worker has stopped at an invalid record.
+        let Ok(mut buf) = free.recv() else {
+            break;
+        };
+        buf.clear();
+        buf.extend_from_slice(&carry);
+        carry.clear();
+
+        // Read until the buffer is full and holds a '\n' (a line can be
+        // longer than a chunk), or until EOF. `buf[filled..]` is scratch.
+        let mut filled = buf.len();
+        let mut searched = 0;
+        let len = loop {
+            if filled >= chunk_size {
+                if let Some(nl) = memrchr(b'\n', &buf[searched..filled]) {
+                    break searched + nl + 1;
+                }
+                searched = filled;
+            }
+            if filled == buf.len() {
+                buf.resize(chunk_size.max(filled + 4096), 0);
+            }
+            match reader.read(&mut buf[filled..]) {
+                Ok(0) => {
+                    eof = true;
+                    buf.truncate(filled);
+                    if buf.last().is_some_and(|&b| b != b'\n') {
+                        match partial.as_deref_mut() {
+                            Some(partial) => {
+                                let start = memrchr(b'\n', &buf).map_or(0, |nl| nl + 1);
+                                partial.extend_from_slice(&buf[start..]);
+                                buf.truncate(start);
+                            }
+                            None => buf.push(b'\n'),
+                        }
+                    }
+                    break buf.len();
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        };
+
+        if len == 0 {
+            break;
+        }
+        if !eof {
+            carry.extend_from_slice(&buf[len..filled]);
+        }
+        buf.truncate(len);
+        buf.resize(len + PADDING, 0);
+        if full.send(Chunk { buf, len, offset }).is_err() {
+            break;
+        }
+        offset += len;
+    }
+    Ok(())
+}
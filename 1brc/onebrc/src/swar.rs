@@ -0,0 +1,90 @@
+//! SWAR (SIMD within a register) helpers: byte search and temperature parsing
+//! on 8-byte little-endian words.
+
+// Java-style mask table (avoids variable shifts in mask_low_bytes)
+pub const MASK1: [u64; 9] = [
+    0x00,
+    0xFF,
+    0xFFFF,
+    0xFFFFFF,
+    0xFFFFFFFF,
+    0xFFFFFFFFFF,
+    0xFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+];
+
+/// The 8 bytes at `p` as a little-endian word: byte `i` is bits `8i..8i + 8`,
+/// on every target. The masks and shifts below all rely on that order.
+///
+/// # Safety
+/// `p..p + 8` must be readable.
+#[inline(always)]
+pub unsafe fn load_u64(p: *const u8) -> u64 { unsafe {
+    u64::from_le((p as *const u64).read_unaligned())
+}}
+
+#[inline(always)]
+pub fn find_byte_mask(word: u64, byte: u8) -> u64 {
+    let x = word ^ u64::from_le_bytes([byte; 8]);
+    x.wrapping_sub(0x0101_0101_0101_0101) & !x & 0x8080_8080_8080_8080
+}
+
+#[inline(always)]
+pub fn first_hit_byte_index(mask: u64) -> usize {
+    // mask has 0x80 set in the byte lane that matched
+    (mask.trailing_zeros() >> 3) as usize
+}
+
+/// Branchless temp parse (tenths) ported from the Java winner.
+/// Input: pointer at first char after ';' (digit or '-')
+///
+/// The word is loaded little-endian, so the first character is the low byte:
+/// bit 4 of byte 1, 2 or 3 is clear only for the '.', and bit 4 of byte 0 is
+/// clear only for a '-'.
+///
+/// # Safety
+/// Reads 8 bytes starting at `semi_plus_1`, which may extend past the `'\n'`.
+#[inline(always)]
+pub unsafe fn parse_temp_branchless(semi_plus_1: *const u8) -> i16 { unsafe {
+    let number_word = load_u64(semi_plus_1);
+
+    // Java: trailingZeros(~numberWord & 0x10101000L)
+    let decimal_sep_pos = ((!number_word) & 0x0000_0000_1010_1000u64).trailing_zeros() as i32;
+
+    let shift = 28 - decimal_sep_pos;
+
+    // signed is -1 if negative, 0 otherwise
+    let signed = ((!number_word) << 59) as i64 >> 63;
+    let design_mask = !((signed as u64) & 0xFF);
+
+    let digits = ((number_word & design_mask) << shift) & 0x0000_000F_000F_0F00_u64;
+    let abs_value = (((digits.wrapping_mul(0x640a_0001)) >> 32) & 0x3FF) as i64;
+
+    ((abs_value ^ signed) - signed) as i16
+}}
+
+/// Pointer to the first `byte` in `[p, end)`, or `end` if there is none.
+///
+/// # Safety
+/// `[p, end)` must be a readable range within one allocation.
+#[inline(always)]
+pub unsafe fn scan_to_byte_bounded(mut p: *const u8, end: *const u8, byte: u8) -> *const u8 { unsafe {
+    // Fast path: 8-byte scanning while we can read a full u64 without crossing `end`.
+    while p.add(8) <= end {
+        let w = load_u64(p);
+        let m = find_byte_mask(w, byte);
+        if m != 0 {
+            return p.add(first_hit_byte_index(m));
+        }
+        p = p.add(8);
+    }
+    // Tail (<=7 bytes): scalar scan
+    while p < end {
+        if *p == byte {
+            return p;
+        }
+        p = p.add(1);
+    }
+    end
+}}
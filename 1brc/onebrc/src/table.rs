@@ -0,0 +1,365 @@
+use crate::chunk::PADDING;
+use crate::filter::Filter;
+use crate::stats::{StationStats, Stats};
+use crate::swar::{MASK1, load_u64};
+use std::mem;
+
+/// Fraction of the slots in use at which the table grows.
+pub const MAX_LOAD: f64 = 0.70;
+
+/// Old slots moved into the grown table per insert, and per lookup that had
+/// to go to the old slots.
+pub const MIGRATE_STEP: usize = 64;
+
+/// Distance between probes; odd, so it visits every slot of a power of two.
+const PROBE_STEP: usize = 31;
+
+#[derive(Clone)]
+struct Entry<S> {
+    name_off: u64,
+    name_len: u16,
+    hash: u64,
+    w1: u64,
+    w2: u64,
+    /// Set for names the table's [`Filter`] rejects; left out of iteration.
+    ignored: bool,
+    stats: S,
+}
+
+#[inline(always)]
+unsafe fn load_prefix2(p: *const u8, len: usize) -> (u64, u64) { unsafe {
+    let w1 = load_u64(p);
+
+    if len >= 16 {
+        let w2 = load_u64(p.add(8));
+        (w1, w2)
+    } else if len <= 8 {
+        (w1 & *MASK1.get_unchecked(len), 0)
+    } else {
+        let w2 = load_u64(p.add(8));
+        (w1, w2 & *MASK1.get_unchecked(len - 8))
+    }
+}}
+
+#[inline(always)]
+fn hash16(w1: u64, w2: u64, len: u16) -> u64 {
+    let mut x = w1 ^ w2 ^ ((len as u64) << 48);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x
+}
+
+/// Open-addressing table keyed by station names that live in `data`.
+///
+/// Entries store the name as an offset into `data`, so keys are never copied
+/// while aggregating.
+///
+/// `capacity` is only a starting size. Once [`MAX_LOAD`] of the slots are in
+/// use, the table switches to twice as many and moves the old ones over
+/// [`MIGRATE_STEP`] at a time on the following inserts, so that no insert
+/// pays for rehashing the whole table. Until they are all moved, a name that
+/// is not in the new slots is looked up in the old ones before inserting, and
+/// finding it there moves some more, so the migration finishes even once no
+/// new names arrive. Lookups of names already in the new slots never see the
+/// old ones.
+///
+/// With a [`Filter`], names it rejects get ignored entries; see
+/// [`crate::filter`]. The table borrows its input for `'a` and the filter for
+/// `'f`, which can outlive many inputs (see [`NameTable::recycle`]).
+pub struct NameTable<'a, 'f, S = StationStats> {
+    data: &'a [u8],
+    slots: Vec<u64>, // 0 = empty, else (fp<<32) | (entry_index+1)
+    entries: Vec<Entry<S>>,
+    mask: usize,
+    /// The slots from before the last grow; empty once all are moved.
+    old_slots: Vec<u64>,
+    old_mask: usize,
+    /// `old_slots[..migrated]` are in `slots`.
+    migrated: usize,
+    filter: Option<&'f Filter>,
+}
+
+impl<'a, 'f, S: Stats> NameTable<'a, 'f, S> {
+    pub fn with_capacity(data: &'a [u8], capacity: usize) -> Self {
+        let desired = ((capacity as f64) / MAX_LOAD).ceil() as usize;
+        let slots_len = desired.next_power_of_two().max(8);
+
+        Self {
+            data,
+            slots: vec![0; slots_len],
+            entries: Vec::with_capacity(capacity),
+            mask: slots_len - 1,
+            old_slots: Vec::new(),
+            old_mask: 0,
+            migrated: 0,
+            filter: None,
+        }
+    }
+
+    /// Ignore the names that `filter` rejects.
+    pub fn with_filter(mut self, filter: Option<&'f Filter>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Empty the table and point it at new `data`, keeping the allocations.
+    pub fn recycle<'b>(mut self, data: &'b [u8]) -> NameTable<'b, 'f, S> {
+        self.slots.fill(0);
+        self.entries.clear();
+        NameTable {
+            data,
+            slots: self.slots,
+            entries: self.entries,
+            mask: self.mask,
+            old_slots: Vec::new(),
+            old_mask: 0,
+            migrated: 0,
+            filter: self.filter,
+        }
+    }
+
+    #[inline(always)]
+    fn fingerprint(hash: u64) -> u32 {
+        // cheap 32-bit fingerprint; any decent mix is fine
+        let x = hash ^ (hash >> 33) ^ (hash >> 15);
+        x as u32
+    }
+
+    /// Lookup or insert a key given as (offset, len) into `self.data`.
+    /// Returns a mutable reference to the entry's Stats.
+    ///
+    /// # Safety
+    /// Names are read 8 bytes at a time without bounds checks, so
+    /// `name_off + name_len + `[`PADDING`] must be at most `self.data.len()`.
+    #[inline(always)]
+    pub unsafe fn get_or_insert_stats(&mut self, name_off: u64, name_len: u16) -> &mut S {
+        let len = name_len as usize;
+        debug_assert!(name_off as usize + len + PADDING <= self.data.len());
+
+        let base = self.data.as_ptr();
+        let key_ptr = unsafe { base.add(name_off as usize) };
+
+        let (w1, w2) = unsafe { load_prefix2(key_ptr, len) };
+        let mut h = hash16(w1, w2, name_len);
+        if len > 16 {
+            // Also hash the end, or IDs with a long common prefix all collide.
+            h = hash16(h, unsafe { load_u64(key_ptr.add(len - 8)) }, name_len);
+        }
+
+        let fp = Self::fingerprint(h) as u64;
+
+        let mut i = self.index_for(h);
+        let step = PROBE_STEP;
+
+        loop {
+            let slot = unsafe { *self.slots.get_unchecked(i) };
+
+            if slot == 0 {
+                let idx = self.insert_at(i, name_off, name_len, h, w1, w2);
+                return unsafe { &mut self.entries.get_unchecked_mut(idx).stats };
+            }
+
+            // fast reject by fingerprint before chasing pointers
+            let slot_fp = (slot >> 32) as u32;
+            if slot_fp == fp as u32 {
+                let entry_idx = ((slot as u32) - 1) as usize;
+                let e = unsafe { self.entries.get_unchecked(entry_idx) };
+
+                // full check
+                if e.hash == h && e.name_len == name_len && e.w1 == w1 && e.w2 == w2 {
+                    if len <= 16 {
+                        return unsafe { &mut self.entries.get_unchecked_mut(entry_idx).stats };
+                    }
+                    let cand_ptr = unsafe { base.add(e.name_off as usize) };
+                    if unsafe { Self::bytes_eq_u64_ptr(cand_ptr, key_ptr, len) } {
+                        return unsafe { &mut self.entries.get_unchecked_mut(entry_idx).stats };
+                    }
+                }
+            }
+
+            i = (i + step) & self.mask;
+        }
+    }
+
+    /// Insert the name into the empty slot `i` of its probe sequence, unless
+    /// it is still in the old slots, and return its entry. Moves some old
+    /// slots over, whether or not the name was there, or grows the table. This
+    /// is where the filter is asked.
+    #[cold]
+    #[inline(never)]
+    fn insert_at(&mut self, i: usize, name_off: u64, name_len: u16, h: u64, w1: u64, w2: u64) -> usize {
+        if !self.old_slots.is_empty()
+            && let Some(idx) = self.find_old(name_off, name_len, h, w1, w2)
+        {
+            self.migrate(MIGRATE_STEP);
+            return idx;
+        }
+
+        let idx = self.entries.len();
+        assert!(idx < u32::MAX as usize, "too many names for one table");
+        let ignored = self.filter.is_some_and(|filter| {
+            let off = name_off as usize;
+            filter.ignores(&self.data[off..off + name_len as usize])
+        });
+        self.entries.push(Entry {
+            name_off,
+            name_len,
+            hash: h,
+            w1,
+            w2,
+            ignored,
+            stats: Default::default(),
+        });
+        let fp = Self::fingerprint(h) as u64;
+        self.slots[i] = (fp << 32) | ((idx as u64) + 1);
+
+        if !self.old_slots.is_empty() {
+            self.migrate(MIGRATE_STEP);
+        } else if self.should_grow() {
+            self.grow();
+        }
+        idx
+    }
+
+    /// The entry of the name in the old slots, if it is there.
+    fn find_old(&self, name_off: u64, name_len: u16, h: u64, w1: u64, w2: u64) -> Option<usize> {
+        let fp = Self::fingerprint(h);
+        let key_ptr = unsafe { self.data.as_ptr().add(name_off as usize) };
+        let mut i = (h as usize) & self.old_mask;
+        loop {
+            let slot = self.old_slots[i];
+            if slot == 0 {
+                return None;
+            }
+            let entry_idx = ((slot as u32) - 1) as usize;
+            let e = &self.entries[entry_idx];
+            if (slot >> 32) as u32 == fp
+                && e.hash == h
+                && e.name_len == name_len
+                && e.w1 == w1
+                && e.w2 == w2
+            {
+                let cand_ptr = unsafe { self.data.as_ptr().add(e.name_off as usize) };
+                if name_len <= 16 || unsafe { Self::bytes_eq_u64_ptr(cand_ptr, key_ptr, name_len as usize) } {
+                    return Some(entry_idx);
+                }
+            }
+            i = (i + PROBE_STEP) & self.old_mask;
+        }
+    }
+
+    #[inline]
+    fn should_grow(&self) -> bool {
+        (self.entries.len() as f64) >= (self.slots.len() as f64) * MAX_LOAD
+    }
+
+    /// Switch to twice as many slots; the current ones become the old slots.
+    fn grow(&mut self) {
+        let len = self.slots.len() * 2;
+        self.old_slots = mem::replace(&mut self.slots, vec![0; len]);
+        self.old_mask = self.mask;
+        self.mask = len - 1;
+        self.migrated = 0;
+    }
+
+    /// Move up to `n` more old slots into `slots`.
+    fn migrate(&mut self, n: usize) {
+        let end = (self.migrated + n).min(self.old_slots.len());
+        for j in self.migrated..end {
+            let slot = self.old_slots[j];
+            if slot == 0 {
+                continue;
+            }
+            let hash = self.entries[((slot as u32) - 1) as usize].hash;
+            let mut i = self.index_for(hash);
+            while self.slots[i] != 0 {
+                i = (i + PROBE_STEP) & self.mask;
+            }
+            self.slots[i] = slot;
+        }
+        self.migrated = end;
+        if end == self.old_slots.len() {
+            self.old_slots = Vec::new();
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn bytes_eq_u64_ptr(mut a: *const u8, mut b: *const u8, mut len: usize) -> bool { unsafe {
+        // NEON is baseline on aarch64: 16 bytes per compare for long names.
+        #[cfg(target_arch = "aarch64")]
+        {
+            use std::arch::aarch64::*;
+            while len >= 16 {
+                if vminvq_u8(vceqq_u8(vld1q_u8(a), vld1q_u8(b))) != 0xFF {
+                    return false;
+                }
+                a = a.add(16);
+                b = b.add(16);
+                len -= 16;
+            }
+        }
+        while len >= 8 {
+            if load_u64(a) != load_u64(b) {
+                return false;
+            }
+            a = a.add(8);
+            b = b.add(8);
+            len -= 8;
+        }
+        if len != 0 {
+            // Reads up to 7 bytes past the key; see chunk::PADDING.
+            let mask = MASK1[len];
+            let wa = load_u64(a) & mask;
+            let wb = load_u64(b) & mask;
+            wa == wb
+        } else {
+            true
+        }
+    }}
+
+    #[inline(always)]
+    fn index_for(&self, hash: u64) -> usize {
+        // hash16 already ends with `x ^= x >> 33`; doing that again here
+        // would undo it and leave only the low bits of a product, which
+        // sequential IDs barely change.
+        (hash as usize) & self.mask
+    }
+
+    /// Number of distinct names, ignored ones included.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Number of slots (a power of two), for tests and metrics.
+    pub fn slots_len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Whether old slots from the last grow are still being moved over.
+    pub fn is_migrating(&self) -> bool {
+        !self.old_slots.is_empty()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The names and their stats, without the ignored ones.
+    pub fn iter_entries(&self) -> impl Iterator<Item = (&[u8], &S)> + '_ {
+        self.entries.iter().filter(|e| !e.ignored).map(|e| {
+            let off = e.name_off as usize;
+            let len = e.name_len as usize;
+            (&self.data[off..off + len], &e.stats)
+        })
+    }
+
+    /// Like [`NameTable::iter_entries`], with the offset of each name in `data`.
+    pub fn iter_names(&self) -> impl Iterator<Item = (u64, &[u8], &S)> + '_ {
+        self.entries.iter().filter(|e| !e.ignored).map(|e| {
+            let off = e.name_off as usize;
+            let len = e.name_len as usize;
+            (e.name_off, &self.data[off..off + len], &e.stats)
+        })
+    }
+}
@@ -0,0 +1,691 @@
+//! Aggregation from a file through io_uring with `O_DIRECT` reads, without
+//! mmap (`--io-uring`).
+//!
+//! The file is cut into fixed chunks that the workers claim in order, as the
+//! mmap path does. Every worker has its own ring and [`DEPTH`] buffers
+//! registered with it, and keeps that many reads in flight: each covers a
+//! chunk, block-aligned, plus the end of the line before it and up to
+//! [`OVERLAP`] bytes after it. Whichever read completes first is snapped to
+//! the records that start in its chunk and aggregated by the same
+//! [`chunk_statistics`] loop as everywhere else, straight from the buffer.
+//!
+//! `O_DIRECT` bypasses the page cache, so there are no page faults and
+//! nothing to unmap, but every run reads the disk. Short reads, and records
+//! that end further than [`OVERLAP`] past their chunk, are finished with
+//! `pread`. Where `O_DIRECT` or io_uring is not available (older kernels,
+//! seccomp'd containers, some filesystems) every chunk is read with `pread`
+//! into the same buffers; [`aggregate_file`] says which way it went.
+//!
+//! As in [`crate::stream`], buffers are reused, so each chunk's
+//! [`NameTable`] is merged into a per-thread report by raw name and then
+//! recycled.
+
+use crate::chunk::{PADDING, chunk_statistics};
+use crate::parse::InvalidRecord;
+use crate::report::{RawReport, Report};
+use crate::stats::Stats;
+use crate::table::NameTable;
+use crate::{Options, TABLE_CAPACITY};
+use memchr::memchr;
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::ops::Range;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::thread;
+
+/// Reads each worker keeps in flight.
+pub const DEPTH: usize = 4;
+
+/// Alignment of `O_DIRECT` offsets, lengths and buffers; a multiple of every
+/// common logical block size.
+pub const ALIGN: usize = 4096;
+
+/// Bytes read past the end of a chunk, for the record that crosses it.
+pub const OVERLAP: usize = 4096;
+
+/// How the input was read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadMode {
+    /// io_uring reads with `O_DIRECT`, into registered buffers.
+    Direct,
+    /// Buffered `pread`, through the page cache.
+    Buffered,
+}
+
+impl fmt::Display for ReadMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReadMode::Direct => "io_uring with O_DIRECT",
+            ReadMode::Buffered => "pread",
+        })
+    }
+}
+
+/// Aggregate the file at `path`, reading it the way `mode` asks. Gives the
+/// same [`Report`] as [`aggregate_with`](crate::aggregate_with) on its
+/// contents, and says how it was read: [`ReadMode::Direct`] falls back to
+/// [`ReadMode::Buffered`] if any worker could not use it.
+pub fn aggregate_file<S: Stats + Send>(
+    path: &Path,
+    options: Options,
+    mode: ReadMode,
+) -> io::Result<(Report<S>, ReadMode)> {
+    let file = File::open(path)?;
+    let direct = match mode {
+        ReadMode::Direct => OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+            .ok(),
+        ReadMode::Buffered => None,
+    };
+    let len = file.metadata()?.len() as usize;
+    let chunk = options.chunk_size.max(1).next_multiple_of(ALIGN);
+    let input = Input {
+        file: &file,
+        len,
+        chunk,
+        chunks: len.div_ceil(chunk),
+        next: AtomicUsize::new(0),
+        options,
+    };
+    let buffered = AtomicBool::new(direct.is_none());
+
+    let report = thread::scope(|s| {
+        let (input, direct, buffered) = (&input, direct.as_ref(), &buffered);
+        let handles: Vec<_> = (0..options.threads.max(1))
+            .map(|index| {
+                s.spawn(move || {
+                    // Chunks are fixed by the buffers here.
+                    options.enter_worker(index);
+                    input.worker::<S>(direct, buffered)
+                })
+            })
+            .collect();
+        let mut report = Report::default();
+        let mut invalid = Vec::new();
+        for handle in handles {
+            match handle.join().unwrap() {
+                Ok(part) => report.merge(&part),
+                Err(Stop::Invalid(e)) => invalid.push(e),
+                Err(Stop::Io(e)) => return Err(e),
+            }
+        }
+        match invalid.into_iter().min_by_key(|e| e.offset) {
+            Some(e) => Err(e.into()),
+            None => Ok(report),
+        }
+    })?;
+    let mode = match buffered.into_inner() {
+        true => ReadMode::Buffered,
+        false => ReadMode::Direct,
+    };
+    Ok((report, mode))
+}
+
+/// Why a worker stopped early.
+enum Stop {
+    Invalid(InvalidRecord),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Self {
+        Stop::Io(e)
+    }
+}
+
+/// The file as the workers see it: `chunks` chunks of `chunk` bytes.
+struct Input<'a> {
+    /// Opened without `O_DIRECT`, for `pread`.
+    file: &'a File,
+    len: usize,
+    chunk: usize,
+    chunks: usize,
+    next: AtomicUsize,
+    options: Options<'a>,
+}
+
+impl<'a> Input<'a> {
+    fn claim(&self) -> Option<usize> {
+        let k = self.next.fetch_add(1, Ordering::Relaxed);
+        (k < self.chunks).then_some(k)
+    }
+
+    /// Let the other workers stop too.
+    fn stop(&self) {
+        self.next.store(self.chunks, Ordering::Relaxed);
+    }
+
+    /// Where the read for chunk `k` starts, and how long it is. It starts a
+    /// block early, for the `'\n'` before the chunk.
+    fn range(&self, k: usize) -> (usize, usize) {
+        let start = (k * self.chunk).saturating_sub(ALIGN);
+        (start, (k + 1) * self.chunk + OVERLAP - start)
+    }
+
+    /// Room for the longest read, and for a `'\n'` and [`PADDING`] after it.
+    fn buffer_size(&self) -> usize {
+        ALIGN + self.chunk + OVERLAP + ALIGN
+    }
+
+    fn worker<S: Stats>(
+        &self,
+        direct: Option<&File>,
+        buffered: &AtomicBool,
+    ) -> Result<Report<S>, Stop> {
+        let mut report = RawReport::new(self.options.keys);
+        let mut table =
+            Some(NameTable::with_capacity(&[], TABLE_CAPACITY).with_filter(self.options.filter));
+        let mut buffers = Buffers::new(DEPTH, self.buffer_size());
+
+        let ring = direct.and_then(|file| {
+            let ring = Ring::new(DEPTH as u32).ok()?;
+            ring.register(&buffers).ok()?;
+            Some((ring, file.as_raw_fd()))
+        });
+        let Some((mut ring, fd)) = ring else {
+            buffered.store(true, Ordering::Relaxed);
+            while let Some(k) = self.claim() {
+                let result = self.aggregate_chunk(k, buffers.get(0), 0, &mut table, &mut report);
+                result.inspect_err(|_| self.stop())?;
+            }
+            return Ok(report.into_report());
+        };
+
+        // The chunk being read into each buffer.
+        let mut chunks = [0; DEPTH];
+        let mut free: Vec<usize> = (0..DEPTH).collect();
+        let result = 'run: loop {
+            while let Some(&i) = free.last()
+                && let Some(k) = self.claim()
+            {
+                let (start, len) = self.range(k);
+                unsafe { ring.read_fixed(fd, buffers.ptr(i), len, start, i) };
+                chunks[i] = k;
+                free.pop();
+            }
+            if free.len() == DEPTH {
+                break Ok(());
+            }
+            if let Err(e) = ring.submit_and_wait(1) {
+                break Err(e.into());
+            }
+            while let Some((i, res)) = ring.complete() {
+                free.push(i);
+                let filled = match res {
+                    0.. => res as usize,
+                    // No O_DIRECT here after all: pread the whole chunk.
+                    _ if -res == libc::EINVAL => {
+                        buffered.store(true, Ordering::Relaxed);
+                        0
+                    }
+                    _ => break 'run Err(io::Error::from_raw_os_error(-res).into()),
+                };
+                let buf = buffers.get(i);
+                if let Err(e) =
+                    self.aggregate_chunk(chunks[i], buf, filled, &mut table, &mut report)
+                {
+                    break 'run Err(e);
+                }
+            }
+        };
+
+        if result.is_err() {
+            self.stop();
+            // The kernel must be done with the buffers before they are freed.
+            while free.len() < DEPTH {
+                if ring.submit_and_wait(1).is_err() {
+                    mem::forget(buffers);
+                    break;
+                }
+                while let Some((i, _)) = ring.complete() {
+                    free.push(i);
+                }
+            }
+        }
+        result.map(|()| report.into_report())
+    }
+
+    /// Aggregate the records that start in chunk `k`, from `buf` with its
+    /// first `filled` bytes read. The rest of the read is done with `pread`.
+    fn aggregate_chunk<S: Stats>(
+        &self,
+        k: usize,
+        buf: &mut [u8],
+        mut filled: usize,
+        table: &mut Option<NameTable<'static, 'a, S>>,
+        report: &mut RawReport<S>,
+    ) -> Result<(), Stop> {
+        let (start, len) = self.range(k);
+        let len = len.min(self.len - start);
+        while filled < len {
+            match self
+                .file
+                .read_at(&mut buf[filled..len], (start + filled) as u64)
+            {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Positions in `buf` from here on.
+        let chunk_start = k * self.chunk - start;
+        let chunk_end = ((k + 1) * self.chunk).min(self.len) - start;
+        let first = match k {
+            0 => 0,
+            _ => match memchr(b'\n', &buf[chunk_start - 1..filled]) {
+                Some(i) => chunk_start + i,
+                None => return Ok(()),
+            },
+        };
+        if first >= chunk_end {
+            return Ok(());
+        }
+        let end = if start + chunk_end == self.len {
+            if buf[filled - 1] != b'\n' {
+                buf[filled] = b'\n';
+                filled += 1;
+            }
+            filled
+        } else {
+            match memchr(b'\n', &buf[chunk_end - 1..filled]) {
+                Some(i) => chunk_end + i,
+                None => {
+                    return self.aggregate_long(&buf[first..filled], start + first, table, report);
+                }
+            }
+        };
+        self.aggregate(buf, first..end, start, table, report)
+    }
+
+    /// The records of a chunk whose last line ends more than [`OVERLAP`]
+    /// bytes after it: `head`, from `offset` in the file, and the rest of
+    /// the line read with `pread`.
+    fn aggregate_long<S: Stats>(
+        &self,
+        head: &[u8],
+        offset: usize,
+        table: &mut Option<NameTable<'static, 'a, S>>,
+        report: &mut RawReport<S>,
+    ) -> Result<(), Stop> {
+        let mut data = head.to_vec();
+        let mut searched = data.len();
+        let end = loop {
+            let filled = data.len();
+            data.resize(filled + OVERLAP, 0);
+            let n = match self
+                .file
+                .read_at(&mut data[filled..], (offset + filled) as u64)
+            {
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {
+                    data.truncate(filled);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            data.truncate(filled + n);
+            if let Some(i) = memchr(b'\n', &data[searched..]) {
+                break searched + i + 1;
+            }
+            searched = data.len();
+            if n == 0 {
+                data.push(b'\n');
+                break data.len();
+            }
+        };
+        data.resize(end + PADDING, 0);
+        self.aggregate(&data, 0..end, offset, table, report)
+    }
+
+    /// Run [`chunk_statistics`] over `data[range]`, which is at `offset` in
+    /// the file and followed by [`PADDING`] bytes, and merge the table.
+    fn aggregate<S: Stats>(
+        &self,
+        data: &[u8],
+        range: Range<usize>,
+        offset: usize,
+        table: &mut Option<NameTable<'static, 'a, S>>,
+        report: &mut RawReport<S>,
+    ) -> Result<(), Stop> {
+        let options = self.options;
+        let mut statistics = table.take().unwrap().recycle(data);
+        let result = chunk_statistics(
+            data,
+            range.start,
+            range.end,
+            &mut statistics,
+            options.input,
+            options.kernel,
+        )
+        .and_then(|()| report.merge_table(&statistics))
+        .map_err(|e| Stop::Invalid(e.shifted(offset)));
+        *table = Some(statistics.recycle(&[]));
+        result
+    }
+}
+
+/// `count` zeroed buffers of `size` bytes in one [`ALIGN`]ed allocation.
+struct Buffers {
+    ptr: *mut u8,
+    layout: Layout,
+    size: usize,
+}
+
+impl Buffers {
+    fn new(count: usize, size: usize) -> Self {
+        let layout = Layout::from_size_align(count * size, ALIGN).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Self { ptr, layout, size }
+    }
+
+    fn ptr(&self, i: usize) -> *mut u8 {
+        unsafe { self.ptr.add(i * self.size) }
+    }
+
+    /// Buffer `i`, which must not have a read in flight.
+    fn get(&mut self, i: usize) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr(i), self.size) }
+    }
+}
+
+impl Drop for Buffers {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) };
+    }
+}
+
+// The parts of <linux/io_uring.h> used here. The structs are the kernel's
+// layout; not every field is read.
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_REGISTER_BUFFERS: u32 = 0;
+const IORING_OP_READ_FIXED: u8 = 4;
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+const _: () = assert!(mem::size_of::<Params>() == 120);
+const _: () = assert!(mem::size_of::<Sqe>() == 64);
+const _: () = assert!(mem::size_of::<Cqe>() == 16);
+
+/// A shared mapping of the ring fd.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    /// The value at byte `offset`.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+/// A minimal io_uring: one submitter, no SQPOLL, never more entries in
+/// flight than it has.
+struct Ring {
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_array: *mut u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    /// Entries pushed but not yet handed to the kernel.
+    unsubmitted: u32,
+    // Unmapped before the fd is closed.
+    _maps: Vec<Mapping>,
+    fd: OwnedFd,
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Self> {
+        let mut p = Params::default();
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, &mut p as *mut Params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let raw = fd.as_raw_fd();
+
+        let sq_len = p.sq_off.array as usize + p.sq_entries as usize * mem::size_of::<u32>();
+        let cq_len = p.cq_off.cqes as usize + p.cq_entries as usize * mem::size_of::<Cqe>();
+        let mut maps = Vec::with_capacity(3);
+        if p.features & IORING_FEAT_SINGLE_MMAP != 0 {
+            maps.push(Mapping::new(raw, sq_len.max(cq_len), IORING_OFF_SQ_RING)?);
+        } else {
+            maps.push(Mapping::new(raw, sq_len, IORING_OFF_SQ_RING)?);
+            maps.push(Mapping::new(raw, cq_len, IORING_OFF_CQ_RING)?);
+        }
+        let sqes_len = p.sq_entries as usize * mem::size_of::<Sqe>();
+        maps.push(Mapping::new(raw, sqes_len, IORING_OFF_SQES)?);
+        let (sq, cq, sqes) = (&maps[0], &maps[maps.len() - 2], &maps[maps.len() - 1]);
+
+        Ok(Self {
+            sq_tail: sq.at(p.sq_off.tail),
+            sq_mask: unsafe { *sq.at::<u32>(p.sq_off.ring_mask) },
+            sq_array: sq.at(p.sq_off.array),
+            sqes: sqes.ptr.cast(),
+            cq_head: cq.at(p.cq_off.head),
+            cq_tail: cq.at(p.cq_off.tail),
+            cq_mask: unsafe { *cq.at::<u32>(p.cq_off.ring_mask) },
+            cqes: cq.at(p.cq_off.cqes),
+            unsubmitted: 0,
+            _maps: maps,
+            fd,
+        })
+    }
+
+    /// Register every buffer of `buffers`, in order, for
+    /// [`Ring::read_fixed`].
+    fn register(&self, buffers: &Buffers) -> io::Result<()> {
+        let count = buffers.layout.size() / buffers.size;
+        let iovecs: Vec<libc::iovec> = (0..count)
+            .map(|i| libc::iovec {
+                iov_base: buffers.ptr(i).cast(),
+                iov_len: buffers.size,
+            })
+            .collect();
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                self.fd.as_raw_fd(),
+                IORING_REGISTER_BUFFERS,
+                iovecs.as_ptr(),
+                iovecs.len() as u32,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Queue a read of `len` bytes at `offset` of `fd` into registered
+    /// buffer `index`, which starts at `buf`.
+    ///
+    /// # Safety
+    /// `buf..buf + len` must be registered buffer `index` and stay untouched
+    /// until the read completes; fewer reads than the ring has entries may
+    /// be in flight.
+    unsafe fn read_fixed(
+        &mut self,
+        fd: RawFd,
+        buf: *mut u8,
+        len: usize,
+        offset: usize,
+        index: usize,
+    ) {
+        let tail = unsafe { (*self.sq_tail).load(Ordering::Relaxed) };
+        let slot = tail & self.sq_mask;
+        unsafe {
+            self.sqes.add(slot as usize).write(Sqe {
+                opcode: IORING_OP_READ_FIXED,
+                fd,
+                off: offset as u64,
+                addr: buf as u64,
+                len: len as u32,
+                user_data: index as u64,
+                buf_index: index as u16,
+                ..Sqe::default()
+            });
+            self.sq_array.add(slot as usize).write(slot);
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.unsubmitted += 1;
+    }
+
+    /// Hand the queued reads to the kernel and wait for `min` completions.
+    fn submit_and_wait(&mut self, min: u32) -> io::Result<()> {
+        loop {
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd(),
+                    self.unsubmitted,
+                    min,
+                    IORING_ENTER_GETEVENTS,
+                    ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            if res >= 0 {
+                self.unsubmitted -= res as u32;
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    /// The buffer index and result of the next completed read, if any.
+    fn complete(&mut self) -> Option<(usize, i32)> {
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            if head == (*self.cq_tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = &*self.cqes.add((head & self.cq_mask) as usize);
+            let done = (cqe.user_data as usize, cqe.res);
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(done)
+        }
+    }
+}
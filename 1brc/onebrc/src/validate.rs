@@ -0,0 +1,246 @@
+//! Checking every record instead of trusting the input (`--validate`,
+//! `--skip-invalid`).
+//!
+//! The fast path assumes well-formed input: a line without a separator or
+//! with a garbled temperature is "parsed" into nonsense, and a name that is
+//! not UTF-8 only fails when the report is built. [`validate`] goes through
+//! the input line by line instead, on the usual chunks and threads, and
+//! rejects:
+//!
+//! - empty lines,
+//! - lines without the separator (or too few columns for the
+//!   [`InputFormat`]),
+//! - station names that are not UTF-8 or longer than [`MAX_NAME_LEN`] bytes,
+//! - temperatures that are not numbers, and for [`Values::Fixed`] anything
+//!   outside `-99.9..=99.9` or not written like `-12.3`.
+//!
+//! Rejected lines are counted and left out of the report; the first few are
+//! kept with their byte offset and line number.
+
+use crate::chunk::{claim_guided, padded_tail, split_tail};
+use crate::metrics::Metrics;
+use crate::parse::{InputFormat, Values, parse_general, split_record};
+use crate::report::Report;
+use crate::stats::Stats;
+use crate::table::NameTable;
+use crate::{Options, TABLE_CAPACITY};
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::AtomicUsize;
+use std::thread;
+
+/// Longest station name, in bytes, that the challenge allows.
+pub const MAX_NAME_LEN: usize = 100;
+
+/// Default for how many rejected lines to report.
+pub const MAX_ERRORS: usize = 10;
+
+/// A line that [`validate`] rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    /// Line number, from 1.
+    pub line: u64,
+    /// Byte offset of the start of the line in the input.
+    pub offset: u64,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {} (byte {}): {}",
+            self.line, self.offset, self.reason
+        )
+    }
+}
+
+/// The outcome of [`validate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validated<S> {
+    /// The valid records.
+    pub report: Report<S>,
+    /// How many lines were rejected.
+    pub rejected: u64,
+    /// The first rejected lines, in input order.
+    pub first: Vec<Rejection>,
+}
+
+/// Aggregate the valid records of `data` on `options.threads` threads,
+/// keeping the first `keep` rejected lines. The last line does not need a
+/// `'\n'`.
+pub fn validate<S: Stats + Send>(data: &[u8], options: Options, keep: usize) -> Validated<S> {
+    let body = split_tail(data);
+    let next = &AtomicUsize::new(0);
+    let threads = options.threads.max(1);
+    let metrics = &Metrics::new("thread");
+
+    let mut validated = thread::scope(|s| {
+        let mut handles = Vec::with_capacity(threads);
+        for index in 0..threads {
+            handles.push(s.spawn(move || {
+                let guided = options.enter_worker(index);
+                let mut worker = metrics.worker(index);
+                let mut statistics =
+                    NameTable::<S>::with_capacity(data, TABLE_CAPACITY).with_filter(options.filter);
+                let mut rejects = Rejects::new(keep);
+                while let Some((start, end)) = claim_guided(&data[..body], next, guided) {
+                    worker.chunk(start, end);
+                    validate_chunk(
+                        data,
+                        start..end,
+                        &options.input,
+                        &mut statistics,
+                        &mut rejects,
+                    );
+                }
+                drop(worker);
+                let mut report = Report::default();
+                report
+                    .merge_table_keyed(&statistics, &options.keys)
+                    .expect("names are UTF-8");
+                (report, rejects)
+            }));
+        }
+        let mut validated = Validated {
+            report: Report::default(),
+            rejected: 0,
+            first: Vec::new(),
+        };
+        for handle in handles {
+            let (report, rejects) = handle.join().unwrap();
+            validated.add(report, rejects, 0);
+        }
+        validated
+    });
+    metrics.log();
+
+    let (tail, tail_len) = padded_tail(&data[body..]);
+    if tail_len > 0 {
+        let mut statistics = NameTable::<S>::with_capacity(&tail, 16).with_filter(options.filter);
+        let mut rejects = Rejects::new(keep);
+        validate_chunk(
+            &tail,
+            0..tail_len,
+            &options.input,
+            &mut statistics,
+            &mut rejects,
+        );
+        let mut report = Report::default();
+        report
+            .merge_table_keyed(&statistics, &options.keys)
+            .expect("names are UTF-8");
+        validated.add(report, rejects, body);
+    }
+
+    validated.first.sort_by_key(|r| r.offset);
+    validated.first.truncate(keep);
+    // Line numbers are only worked out for the lines reported.
+    let (mut line, mut counted) = (1, 0);
+    for rejection in &mut validated.first {
+        let offset = rejection.offset as usize;
+        line += memchr::memchr_iter(b'\n', &data[counted..offset]).count() as u64;
+        counted = offset;
+        rejection.line = line;
+    }
+    validated
+}
+
+impl<S: Stats> Validated<S> {
+    fn add(&mut self, report: Report<S>, rejects: Rejects, base: usize) {
+        self.report.merge(&report);
+        self.rejected += rejects.count;
+        self.first
+            .extend(rejects.first.into_iter().map(|(offset, reason)| Rejection {
+                line: 0,
+                offset: (base + offset) as u64,
+                reason,
+            }));
+    }
+}
+
+/// The rejected lines one thread has seen. A thread claims chunks in input
+/// order, so its first `keep` are all that can be among the first overall.
+struct Rejects {
+    count: u64,
+    first: Vec<(usize, &'static str)>,
+    keep: usize,
+}
+
+impl Rejects {
+    fn new(keep: usize) -> Self {
+        Self {
+            count: 0,
+            first: Vec::new(),
+            keep,
+        }
+    }
+}
+
+/// Aggregate the valid lines in `data[range]`, which ends with a `'\n'` and
+/// is followed by [`PADDING`](crate::chunk::PADDING) bytes.
+fn validate_chunk<S: Stats>(
+    data: &[u8],
+    range: Range<usize>,
+    format: &InputFormat,
+    statistics: &mut NameTable<S>,
+    rejects: &mut Rejects,
+) {
+    let mut start = range.start;
+    for nl in memchr::memchr_iter(b'\n', &data[range.clone()]) {
+        let nl = range.start + nl;
+        match check_record(&data[start..nl], format) {
+            // `data` extends PADDING bytes beyond the range.
+            Ok((key, temp)) => unsafe {
+                statistics.get_or_insert_stats((start + key.start) as u64, key.len() as u16)
+            }
+            .record(temp),
+            Err(reason) => {
+                rejects.count += 1;
+                if rejects.first.len() < rejects.keep {
+                    rejects.first.push((start, reason));
+                }
+            }
+        }
+        start = nl + 1;
+    }
+}
+
+/// The station name (as a range into `line`) and temperature of `line`
+/// (without its `'\n'`), or why it is rejected.
+pub fn check_record(
+    line: &[u8],
+    format: &InputFormat,
+) -> Result<(Range<usize>, i16), &'static str> {
+    if line.is_empty() || (format.crlf && line == b"\r") {
+        return Err("empty line");
+    }
+    let (key, value) = split_record(line, format)?;
+    if key.len() > MAX_NAME_LEN {
+        return Err("station name is longer than 100 bytes");
+    }
+    if std::str::from_utf8(&line[key.clone()]).is_err() {
+        return Err("station name is not UTF-8");
+    }
+    let value = &line[value];
+    let temp = parse_general(value)?;
+    if format.values == Values::Fixed {
+        if !(-999..=999).contains(&temp) {
+            return Err("temperature is out of range");
+        }
+        if !is_1brc_value(value) {
+            return Err("temperature is not formatted like -12.3");
+        }
+    }
+    Ok((key, temp))
+}
+
+/// Whether `value` is `-?\d?\d\.\d`.
+fn is_1brc_value(value: &[u8]) -> bool {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    match digits {
+        [a, b'.', b] => a.is_ascii_digit() && b.is_ascii_digit(),
+        [a, b, b'.', c] => a.is_ascii_digit() && b.is_ascii_digit() && c.is_ascii_digit(),
+        _ => false,
+    }
+}
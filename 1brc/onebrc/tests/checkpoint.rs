@@ -0,0 +1,151 @@
+//! Checkpoint/resume gives the same report as one uninterrupted run.
+
+mod common;
+
+use common::{options, sample};
+use onebrc::checkpoint::{self, Checkpoint, aggregate_checkpointed};
+use onebrc::{ExtendedStats, Report, StationStats, aggregate, aggregate_with};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+fn checkpoint_in(dir: &Path, every: usize) -> Checkpoint {
+    Checkpoint {
+        path: dir.join("run.ckpt"),
+        every,
+    }
+}
+
+/// Byte offset just past the `n`th line.
+fn line_end(data: &[u8], n: usize) -> usize {
+    data.iter()
+        .enumerate()
+        .filter(|&(_, &b)| b == b'\n')
+        .nth(n - 1)
+        .unwrap()
+        .0
+        + 1
+}
+
+#[test]
+fn uninterrupted_run_matches_and_cleans_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = sample(30_000, 1);
+    let expected = aggregate(&data, options(3, 4096));
+
+    for every in [1000, 64 << 10, 1 << 30] {
+        let checkpoint = checkpoint_in(dir.path(), every);
+        let report: Report = aggregate_checkpointed(&data, options(3, 4096), &checkpoint).unwrap();
+        assert_eq!(report.stations, expected.stations, "every {every}");
+        assert!(!checkpoint.path.exists());
+    }
+}
+
+#[test]
+fn resumes_from_the_offset() {
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = checkpoint_in(dir.path(), 50_000);
+    let data = sample(30_000, 2);
+    let cut = line_end(&data, 12_345);
+
+    // As left behind by a run killed after `cut`.
+    let prefix = aggregate(&data[..cut], options(3, 4096));
+    checkpoint::save(&checkpoint, &data, cut, &prefix).unwrap();
+    let report: Report = aggregate_checkpointed(&data, options(3, 4096), &checkpoint).unwrap();
+    assert_eq!(report.stations, aggregate(&data, options(3, 4096)).stations);
+
+    // Nothing before the offset is read again.
+    checkpoint::save(&checkpoint, &data, cut, &Report::<StationStats>::default()).unwrap();
+    let report: Report = aggregate_checkpointed(&data, options(3, 4096), &checkpoint).unwrap();
+    assert_eq!(
+        report.stations,
+        aggregate(&data[cut..], options(3, 4096)).stations
+    );
+}
+
+#[test]
+fn extended_stats_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = checkpoint_in(dir.path(), 1 << 20);
+    let data = sample(20_000, 3);
+    let cut = line_end(&data, 5_000);
+
+    let prefix = aggregate_with::<ExtendedStats>(&data[..cut], options(3, 4096));
+    checkpoint::save(&checkpoint, &data, cut, &prefix).unwrap();
+    let (offset, loaded) = checkpoint::load::<ExtendedStats>(&checkpoint, &data)
+        .unwrap()
+        .unwrap();
+    assert_eq!(offset, cut);
+    assert_eq!(loaded.stations, prefix.stations);
+
+    let report =
+        aggregate_checkpointed::<ExtendedStats>(&data, options(3, 4096), &checkpoint).unwrap();
+    assert_eq!(
+        report.stations,
+        aggregate_with::<ExtendedStats>(&data, options(3, 4096)).stations
+    );
+}
+
+#[test]
+fn refuses_a_checkpoint_for_something_else() {
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = checkpoint_in(dir.path(), 1 << 20);
+    let data = sample(10_000, 4);
+    let cut = line_end(&data, 100);
+    let report = aggregate(&data[..cut], options(3, 4096));
+    checkpoint::save(&checkpoint, &data, cut, &report).unwrap();
+
+    // Same length, different bytes.
+    let mut other = data.clone();
+    other[cut + 1] ^= 1;
+    assert!(aggregate_checkpointed::<StationStats>(&other, options(3, 4096), &checkpoint).is_err());
+    // Different length.
+    assert!(
+        aggregate_checkpointed::<StationStats>(&data[..cut], options(3, 4096), &checkpoint)
+            .is_err()
+    );
+    // Different stats.
+    assert!(aggregate_checkpointed::<ExtendedStats>(&data, options(3, 4096), &checkpoint).is_err());
+    // Not a checkpoint at all.
+    std::fs::write(&checkpoint.path, b"Hamburg;12.0\n").unwrap();
+    assert!(aggregate_checkpointed::<StationStats>(&data, options(3, 4096), &checkpoint).is_err());
+}
+
+#[test]
+fn candidate_22_resumes() {
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = checkpoint_in(dir.path(), 0);
+    let data = sample(20_000, 5);
+    let input = dir.path().join("measurements.txt");
+    std::fs::File::create(&input)
+        .unwrap()
+        .write_all(&data)
+        .unwrap();
+
+    let run = |extra: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--no-fork", "--format", "csv"])
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        out.stdout
+    };
+    let expected = run(&[]);
+
+    let cut = line_end(&data, 7_777);
+    checkpoint::save(
+        &checkpoint,
+        &data,
+        cut,
+        &aggregate(&data[..cut], options(3, 4096)),
+    )
+    .unwrap();
+    let path = checkpoint.path.to_str().unwrap();
+    assert_eq!(
+        run(&["--checkpoint", path, "--checkpoint-every", "64K"]),
+        expected
+    );
+    assert!(!checkpoint.path.exists());
+}
@@ -0,0 +1,49 @@
+#![allow(dead_code)]
+
+use onebrc::Options;
+use onebrc::generate::write_rows;
+use std::collections::BTreeMap;
+
+/// `(min, sum, count, max)` per station, temperatures in tenths.
+pub type Reference = BTreeMap<String, (i16, i64, usize, i16)>;
+
+/// The obviously-correct aggregation: split on lines and `;`, parse with `str::parse`.
+pub fn reference(data: &[u8]) -> Reference {
+    let mut out = Reference::new();
+    for line in str::from_utf8(data).unwrap().lines() {
+        let (name, temp) = line.rsplit_once(';').unwrap();
+        let temp = (temp.parse::<f64>().unwrap() * 10.0).round() as i16;
+        let e = out
+            .entry(name.to_string())
+            .or_insert((i16::MAX, 0, 0, i16::MIN));
+        e.0 = e.0.min(temp);
+        e.1 += temp as i64;
+        e.2 += 1;
+        e.3 = e.3.max(temp);
+    }
+    out
+}
+
+pub fn from_report(report: &onebrc::Report) -> Reference {
+    report
+        .stations
+        .iter()
+        .map(|(k, v)| (k.clone(), (v.min, v.total, v.count, v.max)))
+        .collect()
+}
+
+/// `rows` generated measurements over the default stations, from `seed`.
+pub fn sample(rows: u64, seed: u64) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_rows(&mut data, rows, seed, 1).unwrap();
+    data
+}
+
+/// The defaults, with `threads` workers claiming `chunk_size`-byte chunks.
+pub fn options(threads: usize, chunk_size: usize) -> Options<'static> {
+    Options {
+        threads,
+        chunk_size,
+        ..Options::default()
+    }
+}
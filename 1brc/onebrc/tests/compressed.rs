@@ -0,0 +1,155 @@
+//! gzip and zstd input gives the same reports as the plain file.
+
+mod common;
+
+use common::{options, sample};
+use flate2::Compression as Level;
+use flate2::write::GzEncoder;
+use onebrc::compressed::{self, Compression, decompress, zstd_frames};
+use onebrc::stream::aggregate_reader;
+use onebrc::{Options, Report, StationStats, aggregate};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Level::fast());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// One zstd frame per piece of `data`, split at the given byte offsets.
+fn zstd_split(data: &[u8], cuts: &[usize]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    for &end in cuts.iter().chain([&data.len()]) {
+        out.extend(zstd::encode_all(&data[start..end], 1).unwrap());
+        start = end;
+    }
+    out
+}
+
+fn from_memory(data: &[u8], options: Options) -> Report {
+    compressed::aggregate::<StationStats>(data, options).unwrap()
+}
+
+#[test]
+fn detects_magic_bytes() {
+    assert_eq!(Compression::detect(&gzip(b"a;1.0\n")), Compression::Gzip);
+    assert_eq!(
+        Compression::detect(&zstd_split(b"a;1.0\n", &[])),
+        Compression::Zstd
+    );
+    assert_eq!(
+        Compression::detect(&[0x50, 0x2a, 0x4d, 0x18, 0, 0, 0, 0]),
+        Compression::Zstd
+    );
+    assert_eq!(Compression::detect(b"Hamburg;12.0\n"), Compression::None);
+    assert_eq!(Compression::detect(b""), Compression::None);
+}
+
+#[test]
+fn gzip_matches_plain() {
+    let data = sample(50_000, 21);
+    let expected = aggregate(&data, Options::default());
+
+    // Concatenated members, as written by `gzip -c a b` or pigz --independent.
+    let mut members = gzip(&data[..12_345]);
+    members.extend(gzip(&data[12_345..]));
+    for input in [gzip(&data), members] {
+        assert_eq!(
+            from_memory(&input, options(2, 4096)).stations,
+            expected.stations
+        );
+    }
+}
+
+#[test]
+fn zstd_frames_match_plain() {
+    let data = sample(50_000, 21);
+    let expected = aggregate(&data, Options::default());
+    let n = data.len();
+
+    for cuts in [
+        vec![],
+        // Mid-line, right after a newline and right before one.
+        vec![n / 3, 2 * n / 3],
+        vec![data.iter().position(|&b| b == b'\n').unwrap() + 1],
+        vec![data.iter().position(|&b| b == b'\n').unwrap()],
+        // Frames too small to hold a whole line, and an empty one.
+        vec![100, 101, 103, 103, 107, n / 2],
+    ] {
+        let input = zstd_split(&data, &cuts);
+        assert_eq!(zstd_frames(&input).unwrap().len(), cuts.len() + 1);
+        for threads in [1, 4] {
+            assert_eq!(
+                from_memory(&input, options(threads, 4096)).stations,
+                expected.stations,
+                "cuts {cuts:?}, {threads} threads"
+            );
+        }
+    }
+}
+
+#[test]
+fn zstd_skippable_frames_and_missing_newline() {
+    let data = b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4";
+    let expected = aggregate(data, Options::default());
+
+    let mut input = vec![0x50, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3];
+    input.extend(zstd_split(data, &[5, 20]));
+    assert_eq!(zstd_frames(&input).unwrap().len(), 4);
+    assert_eq!(
+        from_memory(&input, options(3, 8)).stations,
+        expected.stations
+    );
+}
+
+#[test]
+fn corrupt_zstd_is_an_error() {
+    let mut input = zstd_split(&sample(1000, 21), &[]);
+    input.truncate(input.len() / 2);
+    assert!(compressed::aggregate::<StationStats>(&input, options(2, 4096)).is_err());
+}
+
+#[test]
+fn decompress_passes_plain_input_through() {
+    for data in [&b""[..], b"a;1", b"Hamburg;12.0\n"] {
+        let report = aggregate_reader::<StationStats, _>(decompress(data).unwrap(), options(1, 16));
+        assert_eq!(
+            report.unwrap().stations,
+            aggregate(data, Options::default()).stations
+        );
+    }
+}
+
+#[test]
+fn candidate_22_reads_compressed_files_and_pipes() {
+    let data = sample(20_000, 21);
+    let run = |path: &std::path::Path, stdin: Option<&[u8]>| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(path)
+            .args(["--threads", "3"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut pipe = child.stdin.take().unwrap();
+        let input = stdin.unwrap_or_default().to_vec();
+        let writer = std::thread::spawn(move || pipe.write_all(&input));
+        let out = child.wait_with_output().unwrap();
+        writer.join().unwrap().unwrap();
+        assert!(out.status.success());
+        out.stdout
+    };
+
+    let mut plain = tempfile::NamedTempFile::new().unwrap();
+    plain.write_all(&data).unwrap();
+    let expected = run(plain.path(), None);
+
+    let zst = zstd_split(&data, &[data.len() / 2]);
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&zst).unwrap();
+    assert_eq!(run(file.path(), None), expected);
+    assert_eq!(run("-".as_ref(), Some(&zst)), expected);
+    assert_eq!(run("-".as_ref(), Some(&gzip(&data))), expected);
+}
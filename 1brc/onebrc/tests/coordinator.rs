@@ -0,0 +1,153 @@
+//! Worker processes coordinated over Unix sockets give the same report as one
+//! process, even when some of them fail.
+
+mod common;
+
+use common::{options, sample};
+use onebrc::coordinator::{coordinate, work};
+use onebrc::{ExtendedStats, Report, StationStats, Stats, aggregate_with};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Coordinate `data` with one thread per entry of `workers` acting as a
+/// worker process on the other end of its socket.
+fn run<S: Stats + Send>(data: &[u8], workers: &[&(dyn Fn(UnixStream) + Sync)]) -> Report<S> {
+    thread::scope(|s| {
+        let streams = workers
+            .iter()
+            .map(|worker| {
+                let (ours, theirs) = UnixStream::pair().unwrap();
+                s.spawn(move || worker(theirs));
+                ours
+            })
+            .collect();
+        coordinate(data, options(2, 4096), streams).unwrap()
+    })
+}
+
+#[test]
+fn workers_match_one_process() {
+    let data = sample(50_000, 1);
+    let good = |stream| work::<StationStats>(&data, options(2, 4096), stream).unwrap();
+    for n in [1, 3] {
+        let workers = vec![&good as &(dyn Fn(UnixStream) + Sync); n];
+        assert_eq!(
+            run::<StationStats>(&data, &workers),
+            aggregate_with(&data, options(2, 4096))
+        );
+    }
+
+    let extended = |stream| work::<ExtendedStats>(&data, options(2, 4096), stream).unwrap();
+    assert_eq!(
+        run::<ExtendedStats>(&data, &[&extended, &extended]),
+        aggregate_with(&data, options(2, 4096))
+    );
+}
+
+#[test]
+fn failed_workers_are_redone() {
+    let data = sample(50_000, 2);
+    let expected = aggregate_with::<StationStats>(&data, options(2, 4096));
+
+    let good = |stream| work::<StationStats>(&data, options(2, 4096), stream).unwrap();
+    // Claims a few ranges and dies without a result.
+    let crashes = |mut stream: UnixStream| {
+        let mut reply = [0; 16];
+        stream.read_exact(&mut reply[..8]).unwrap();
+        for _ in 0..3 {
+            stream.write_all(b"C").unwrap();
+            stream.read_exact(&mut reply).unwrap();
+        }
+    };
+    // Claims a range, then talks nonsense.
+    let garbles = |mut stream: UnixStream| {
+        let mut reply = [0; 16];
+        stream.read_exact(&mut reply[..8]).unwrap();
+        stream.write_all(b"C").unwrap();
+        stream.read_exact(&mut reply).unwrap();
+        stream.write_all(b"R\x04\0\0\0\0\0\0\0junk").unwrap();
+    };
+    // Claims a range and announces a result larger than any memory.
+    let oversized = |mut stream: UnixStream| {
+        let mut reply = [0; 16];
+        stream.read_exact(&mut reply[..8]).unwrap();
+        stream.write_all(b"C").unwrap();
+        stream.read_exact(&mut reply).unwrap();
+        stream
+            .write_all(b"R\xff\xff\xff\xff\xff\xff\xff\xffjunk")
+            .unwrap();
+    };
+    // Sends a result for other stats.
+    let mismatched = |stream| work::<ExtendedStats>(&data, options(2, 4096), stream).unwrap();
+    let gone = |_| {};
+
+    assert_eq!(
+        run::<StationStats>(&data, &[&crashes, &good, &garbles, &mismatched]),
+        expected
+    );
+    assert_eq!(run::<StationStats>(&data, &[&oversized, &good]), expected);
+    // Nobody did anything: the coordinator aggregates it all itself.
+    assert_eq!(run::<StationStats>(&data, &[&gone, &crashes]), expected);
+    assert_eq!(run::<StationStats>(&data, &[]), expected);
+}
+
+#[test]
+fn silent_workers_time_out() {
+    let data = sample(20_000, 4);
+    let (ours, mut theirs) = UnixStream::pair().unwrap();
+    ours.set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let started = Instant::now();
+    thread::scope(|s| {
+        // Claims a range and then neither answers nor hangs up.
+        s.spawn(move || {
+            let mut reply = [0; 16];
+            theirs.read_exact(&mut reply[..8]).unwrap();
+            theirs.write_all(b"C").unwrap();
+            theirs.read_exact(&mut reply).unwrap();
+            thread::sleep(Duration::from_secs(2));
+        });
+        let report: Report = coordinate(&data, options(2, 4096), vec![ours]).unwrap();
+        assert_eq!(report, aggregate_with(&data, options(2, 4096)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    });
+}
+
+#[test]
+fn worker_rejects_another_input() {
+    let data = sample(10_000, 3);
+    let (mut ours, theirs) = UnixStream::pair().unwrap();
+    ours.write_all(&(data.len() as u64 / 2).to_le_bytes())
+        .unwrap();
+    assert!(work::<StationStats>(&data, options(2, 4096), theirs).is_err());
+}
+
+#[test]
+fn candidate_22_with_processes() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(&input, sample(100_000, 4)).unwrap();
+
+    let run = |extra: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--threads", "4", "--chunk-size", "16K", "--format", "csv"])
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        out.stdout
+    };
+    assert_eq!(run(&["--processes", "3"]), run(&[]));
+    assert_eq!(
+        run(&["--processes", "2", "--stats", "extended", "--no-fork"]),
+        run(&["--stats", "extended"])
+    );
+}
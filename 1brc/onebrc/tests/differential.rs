@@ -0,0 +1,346 @@
+//! Every engine against the reference aggregation.
+//!
+//! Each binary is run with `--no-fork` on a set of generated inputs and its
+//! `{name=min/mean/max, ...}` output is parsed and compared with
+//! [`common::reference`]. Min and max must match exactly; the mean may be
+//! rounded either way, but must be within half a tenth of the exact mean.
+
+mod common;
+
+use common::{Reference, from_report, reference};
+use onebrc::generate::{SplitMix64, write_rows};
+use onebrc::{Options, aggregate};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Engine {
+    name: &'static str,
+    exe: &'static str,
+    /// Refuses to run more threads than there are CPUs.
+    cpu_bound: bool,
+}
+
+macro_rules! engine {
+    ($name:literal) => {
+        Engine {
+            name: $name,
+            exe: env!(concat!("CARGO_BIN_EXE_", $name)),
+            cpu_bound: false,
+        }
+    };
+}
+
+const ENGINES: &[Engine] = &[
+    engine!("onebrc"),
+    engine!("candidate_1"),
+    engine!("candidate_2"),
+    engine!("candidate_3"),
+    engine!("candidate_4"),
+    engine!("candidate_5"),
+    engine!("candidate_6"),
+    engine!("candidate_7"),
+    engine!("candidate_8"),
+    engine!("candidate_9"),
+    engine!("candidate_10"),
+    engine!("candidate_11"),
+    engine!("candidate_12"),
+    engine!("candidate_13"),
+    engine!("candidate_14"),
+    engine!("candidate_15"),
+    engine!("candidate_16"),
+    engine!("candidate_17"),
+    engine!("candidate_18"),
+    engine!("candidate_19"),
+    engine!("candidate_20"),
+    engine!("candidate_21"),
+    engine!("candidate_22"),
+    engine!("thomaswue_ported"),
+    engine!("java_robot"),
+    engine!("nico_lube"),
+    Engine {
+        cpu_bound: true,
+        ..engine!("austindonisan")
+    },
+];
+
+/// `(min, mean, max)` as printed.
+type Printed = BTreeMap<String, (f64, f64, f64)>;
+
+/// Parse `{a=1.0/2.0/3.0, b=...}`. Names may contain `", "`, so an entry only
+/// ends at a `", "` that follows three slash-separated numbers.
+fn parse_output(out: &str) -> Result<Printed, String> {
+    let body = out
+        .trim_end()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or_else(|| format!("not a {{...}} report: {out:?}"))?;
+
+    let mut stations = Printed::new();
+    let mut rest = body;
+    while !rest.is_empty() {
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| format!("missing '=' in {rest:?}"))?;
+        let name = &rest[..eq];
+        let after = &rest[eq + 1..];
+        let end = after.find(", ").unwrap_or(after.len());
+        let fields: Vec<f64> = after[..end]
+            .split('/')
+            .map(|f| {
+                f.parse()
+                    .map_err(|_| format!("bad number {f:?} for {name:?}"))
+            })
+            .collect::<Result<_, _>>()?;
+        let [min, mean, max] = fields[..] else {
+            return Err(format!(
+                "expected min/mean/max for {name:?}, got {:?}",
+                &after[..end]
+            ));
+        };
+        if stations
+            .insert(name.to_string(), (min, mean, max))
+            .is_some()
+        {
+            return Err(format!("{name:?} printed twice"));
+        }
+        rest = after[end..].strip_prefix(", ").unwrap_or("");
+    }
+    Ok(stations)
+}
+
+fn tenths(v: f64) -> i16 {
+    (v * 10.0).round() as i16
+}
+
+fn compare(expected: &Reference, printed: &Printed) -> Result<(), String> {
+    for name in printed.keys() {
+        if !expected.contains_key(name) {
+            return Err(format!("unexpected station {name:?}"));
+        }
+    }
+    for (name, &(min, sum, count, max)) in expected {
+        let &(p_min, p_mean, p_max) = printed
+            .get(name)
+            .ok_or_else(|| format!("missing station {name:?}"))?;
+        let exact = sum as f64 / count as f64;
+        if tenths(p_min) != min
+            || tenths(p_max) != max
+            || (p_mean * 10.0 - exact).abs() > 0.5 + 1e-6
+        {
+            return Err(format!(
+                "{name:?}: printed {p_min}/{p_mean}/{p_max}, expected {}/{:.3}/{} (count {count})",
+                min as f64 / 10.0,
+                exact / 10.0,
+                max as f64 / 10.0
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn push_row(out: &mut Vec<u8>, name: &str, tenths: i16) {
+    writeln!(
+        out,
+        "{name};{}{}.{}",
+        if tenths < 0 { "-" } else { "" },
+        tenths.unsigned_abs() / 10,
+        tenths.unsigned_abs() % 10
+    )
+    .unwrap();
+}
+
+fn shuffled(mut rows: Vec<(String, i16)>, seed: u64) -> Vec<u8> {
+    let mut rng = SplitMix64::new(seed);
+    for i in (1..rows.len()).rev() {
+        rows.swap(i, rng.below(i + 1));
+    }
+    let mut out = Vec::new();
+    for (name, temp) in &rows {
+        push_row(&mut out, name, *temp);
+    }
+    out
+}
+
+/// A name of exactly `len` bytes, multi-byte UTF-8 where `utf8` is set.
+fn name_of_len(len: usize, tag: usize, utf8: bool) -> String {
+    let mut name = format!("{tag:03}");
+    name.truncate(len);
+    while name.len() < len {
+        if utf8 && len - name.len() >= 2 {
+            name.push('é');
+        } else {
+            name.push((b'a' + (name.len() % 26) as u8) as char);
+        }
+    }
+    name
+}
+
+fn cases() -> Vec<(&'static str, Vec<u8>)> {
+    let mut cases = Vec::new();
+
+    let mut generated = Vec::new();
+    write_rows(&mut generated, 200_000, 42, 2).unwrap();
+    cases.push(("generated", generated));
+
+    cases.push(("single_row", b"Hamburg;12.0\n".to_vec()));
+
+    let mut rng = SplitMix64::new(1);
+    let mut rows = Vec::new();
+    for name in ["Hot", "Cold", "Both", "Zero"] {
+        for _ in 0..500 {
+            let temp = match name {
+                "Hot" => 999 - rng.below(3) as i16,
+                "Cold" => -999 + rng.below(3) as i16,
+                "Both" => [-999, 999, -1, 1][rng.below(4)],
+                _ => [0, -1, 1][rng.below(3)],
+            };
+            rows.push((name.to_string(), temp));
+        }
+    }
+    cases.push(("extremes", shuffled(rows, 2)));
+
+    // 1-byte names next to 100-byte names that share long prefixes, so that
+    // neither a short compare nor the first 16 bytes are enough to tell them apart.
+    let mut rows = Vec::new();
+    for i in 0..2_000 {
+        let temp = rng.below(1999) as i16 - 999;
+        let name = match i % 4 {
+            0 => ((b'a' + (i / 4 % 26) as u8) as char).to_string(),
+            1 => name_of_len(100, i % 5, false),
+            2 => name_of_len(100, i % 5, true),
+            _ => name_of_len(1 + i % 100, 0, false),
+        };
+        rows.push((name, temp));
+    }
+    cases.push(("short_and_long_names", shuffled(rows, 3)));
+
+    let mut rows = Vec::new();
+    for i in 0..10_000 {
+        for _ in 0..3 {
+            rows.push((format!("Station {i}"), rng.below(1999) as i16 - 999));
+        }
+    }
+    cases.push(("10k_stations", shuffled(rows, 4)));
+
+    cases
+}
+
+/// Generous, since the binaries are built without optimizations here.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+fn run(engine: &Engine, path: &Path, threads: usize, chunk_size: &str) -> Result<Printed, String> {
+    let mut child = Command::new(engine.exe)
+        .arg(path)
+        .args([
+            "--no-fork",
+            "--threads",
+            &threads.to_string(),
+            "--chunk-size",
+            chunk_size,
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // Drain stdout on another thread so a chatty child cannot block on a full pipe.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut out = Vec::new();
+        stdout.read_to_end(&mut out).map(|_| out)
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if started.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("timed out after {TIMEOUT:?}"));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let out = reader.join().unwrap().map_err(|e| e.to_string())?;
+    if !status.success() {
+        let mut err = String::new();
+        child.stderr.take().unwrap().read_to_string(&mut err).ok();
+        return Err(format!("exited with {status}: {}", err.trim_end()));
+    }
+    parse_output(&String::from_utf8_lossy(&out))
+}
+
+/// Run every engine on every case and collect the disagreements.
+fn check() -> Vec<String> {
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut failures = Vec::new();
+
+    for (case, data) in cases() {
+        let expected = reference(&data);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        file.flush().unwrap();
+
+        for engine in ENGINES {
+            for (threads, chunk_size) in [(1, "1M"), (4, "64K")] {
+                let threads = if engine.cpu_bound {
+                    threads.min(cpus)
+                } else {
+                    threads
+                };
+                let result = run(engine, file.path(), threads, chunk_size)
+                    .and_then(|p| compare(&expected, &p));
+                if let Err(e) = result {
+                    failures.push(format!(
+                        "{} on {case} ({threads} threads, {chunk_size}): {e}",
+                        engine.name
+                    ));
+                }
+            }
+        }
+    }
+    failures
+}
+
+#[test]
+fn library_matches_reference() {
+    for (case, data) in cases() {
+        let expected = reference(&data);
+        for threads in [1, 4] {
+            let report = aggregate(
+                &data,
+                Options {
+                    threads,
+                    chunk_size: 4096,
+                    ..Options::default()
+                },
+            );
+            assert_eq!(from_report(&report), expected, "{case}, {threads} threads");
+        }
+    }
+}
+
+#[test]
+fn engines_match_reference() {
+    let failures = check();
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn parse_output_handles_commas_in_names() {
+    let printed =
+        parse_output("{Flores,  Petén=-1.0/2.5/3.0, Washington, D.C.=0.0/0.0/0.0}\n").unwrap();
+    assert_eq!(printed["Flores,  Petén"], (-1.0, 2.5, 3.0));
+    assert_eq!(printed["Washington, D.C."], (0.0, 0.0, 0.0));
+    assert_eq!(printed.len(), 2);
+}
@@ -0,0 +1,140 @@
+//! `--stats extended`: standard deviation and exact percentiles.
+
+mod common;
+
+use common::{from_report, reference};
+use onebrc::generate::write_rows;
+use onebrc::{ExtendedStats, Options, Stats, aggregate, aggregate_with};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::Command;
+
+/// Every temperature per station, sorted.
+fn all_values(data: &[u8]) -> BTreeMap<String, Vec<i16>> {
+    let mut out = BTreeMap::<String, Vec<i16>>::new();
+    for line in str::from_utf8(data).unwrap().lines() {
+        let (name, temp) = line.rsplit_once(';').unwrap();
+        let temp = (temp.parse::<f64>().unwrap() * 10.0).round() as i16;
+        out.entry(name.to_string()).or_default().push(temp);
+    }
+    for v in out.values_mut() {
+        v.sort();
+    }
+    out
+}
+
+fn nearest_rank(sorted: &[i16], percent: usize) -> i16 {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn stddev(values: &[i16]) -> f64 {
+    let n = values.len() as f64;
+    let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let var = values
+        .iter()
+        .map(|&v| (v as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    var.sqrt() / 10.0
+}
+
+fn stats(temps: &[i16]) -> ExtendedStats {
+    let mut s = ExtendedStats::default();
+    for &t in temps {
+        s.record(t);
+    }
+    s
+}
+
+#[test]
+fn matches_sorted_values() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 300_000, 7, 2).unwrap();
+    let expected = all_values(&data);
+
+    for threads in [1, 3] {
+        let report = aggregate_with::<ExtendedStats>(
+            &data,
+            Options {
+                threads,
+                chunk_size: 64 << 10,
+                ..Options::default()
+            },
+        );
+        assert_eq!(report.stations.len(), expected.len());
+        for (name, values) in &expected {
+            let s = &report.stations[name];
+            assert_eq!(s.basic.count, values.len());
+            for p in [1, 50, 90, 99, 100] {
+                assert_eq!(
+                    s.percentile(p),
+                    nearest_rank(values, p as usize),
+                    "{name} p{p}"
+                );
+            }
+            assert!((s.stddev() - stddev(values)).abs() < 1e-9, "{name}");
+        }
+    }
+}
+
+#[test]
+fn basic_part_matches_the_default_mode() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 100_000, 8, 1).unwrap();
+    let basic = aggregate(&data, Options::default());
+    let extended = aggregate_with::<ExtendedStats>(&data, Options::default());
+
+    assert_eq!(from_report(&basic), reference(&data));
+    assert_eq!(basic.to_string(), extended.to_string());
+    for (name, s) in &basic.stations {
+        assert_eq!(&extended.stations[name].basic, s);
+    }
+}
+
+#[test]
+fn percentiles_of_small_samples() {
+    let s = stats(&[-999]);
+    assert_eq!(
+        [s.percentile(0), s.percentile(50), s.percentile(100)],
+        [-999; 3]
+    );
+    assert_eq!(s.stddev(), 0.0);
+
+    let s = stats(&[30, 10, 20, 40]);
+    assert_eq!(s.percentile(25), 10);
+    assert_eq!(s.percentile(50), 20);
+    assert_eq!(s.percentile(51), 30);
+    assert_eq!(s.percentile(99), 40);
+    assert_eq!(s.percentile(100), 40);
+
+    let s = stats(&[-999, 999]);
+    assert!((s.variance() - 99.9 * 99.9).abs() < 1e-9);
+}
+
+#[test]
+fn merge_is_the_same_as_recording_everything() {
+    let (a, b) = ([-5, 7, 7, 999], [-999, 0, 7]);
+    let mut merged = stats(&a);
+    merged.merge(&stats(&b));
+    assert_eq!(merged, stats(&[a.as_slice(), b.as_slice()].concat()));
+}
+
+#[test]
+fn candidate_22_prints_extended_columns() {
+    let data = b"Hamburg;12.0\nHamburg;-3.4\nHamburg;1.0\n";
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(data).unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+        .arg(file.path())
+        .args(["--stats", "extended", "--format", "csv"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "station,min,mean,max,count,sum,stddev,p50,p90,p99\n\
+         Hamburg,-3.4,3.2,12.0,3,9.6,6.477,1.0,12.0,12.0\n"
+    );
+}
@@ -0,0 +1,124 @@
+//! `--include` and `--exclude`: names a `Filter` rejects get ignored entries
+//! in the tables and never reach the report.
+
+mod common;
+
+use onebrc::filter::read_names;
+use onebrc::generate::write_rows;
+use onebrc::stream::aggregate_reader;
+use onebrc::{Filter, Options, Report, try_aggregate_with};
+use std::process::Command;
+
+fn options(threads: usize, chunk_size: usize, filter: &Filter) -> Options<'_> {
+    Options {
+        filter: Some(filter),
+        ..common::options(threads, chunk_size)
+    }
+}
+
+#[test]
+fn filters_names() {
+    let mut filter = Filter::default();
+    assert!(filter.is_empty() && !filter.ignores(b"Oslo"));
+    filter.exclude(b"Lima");
+    assert!(!filter.ignores(b"Oslo") && filter.ignores(b"Lima"));
+    filter.include(b"Oslo");
+    filter.include(b"Lima");
+    assert!(!filter.ignores(b"Oslo"));
+    assert!(filter.ignores(b"Lima"), "exclude wins");
+    assert!(filter.ignores(b"Bergen"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("names.txt");
+    std::fs::write(&path, b"Oslo\r\n\nWashington, D.C.\nOs\xfflo").unwrap();
+    assert_eq!(
+        read_names(&path).unwrap(),
+        [&b"Oslo"[..], b"Washington, D.C.", b"Os\xfflo"]
+    );
+    assert!(read_names(&dir.path().join("missing")).is_err());
+}
+
+#[test]
+fn aggregates_only_the_kept_names() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 50_000, 1, 3).unwrap();
+    let all: Report = try_aggregate_with(&data, Options::default()).unwrap();
+    let names: Vec<&String> = all.stations.keys().collect();
+
+    let mut include = Filter::default();
+    for name in names.iter().step_by(7) {
+        include.include(name.as_bytes());
+    }
+    let mut exclude = Filter::default();
+    for name in names.iter().step_by(3) {
+        exclude.exclude(name.as_bytes());
+    }
+    for filter in [include, exclude] {
+        let mut expected = all.clone();
+        expected
+            .stations
+            .retain(|name, _| !filter.ignores(name.as_bytes()));
+        assert!(!expected.stations.is_empty());
+        assert!(expected.stations.len() < all.stations.len());
+
+        for (threads, chunk_size) in [(1, 1 << 20), (4, 4096), (3, 100)] {
+            let options = options(threads, chunk_size, &filter);
+            assert_eq!(try_aggregate_with(&data, options).unwrap(), expected);
+            let streamed: Report = aggregate_reader(&data[..], options).unwrap();
+            assert_eq!(streamed, expected);
+        }
+    }
+}
+
+#[test]
+fn ignored_names_are_not_checked() {
+    let data = b"Oslo;1.0\nOs\xfflo;2.0\nLima;3.0\n";
+    let mut filter = Filter::default();
+    filter.exclude(b"Os\xfflo");
+    let report: Report = try_aggregate_with(data, options(2, 16, &filter)).unwrap();
+    let names: Vec<_> = report.stations.keys().map(String::as_str).collect();
+    assert_eq!(names, ["Lima", "Oslo"]);
+    assert!(try_aggregate_with::<onebrc::StationStats>(data, Options::default()).is_err());
+}
+
+#[test]
+fn candidate_22_filters() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(
+        &input,
+        "Oslo;1.0\nBergen;3.0\nLima;-2.0\nOslo;5.0\nWashington, D.C.;7.5\n",
+    )
+    .unwrap();
+    let names = dir.path().join("names.txt");
+    std::fs::write(&names, "Washington, D.C.\nLima\n").unwrap();
+    let names = names.to_str().unwrap();
+
+    let run = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--format", "csv", "--threads", "2"])
+            .args(args)
+            .output()
+            .unwrap();
+        (out.status.success(), String::from_utf8(out.stdout).unwrap())
+    };
+    let header = "station,min,mean,max,count,sum\n";
+    let included = format!(
+        "{header}Lima,-2.0,-2.0,-2.0,1,-2.0\n\
+         Oslo,1.0,3.0,5.0,2,6.0\n\
+         \"Washington, D.C.\",7.5,7.5,7.5,1,7.5\n"
+    );
+    let excluded = format!(
+        "{header}Bergen,3.0,3.0,3.0,1,3.0\n\
+         Oslo,1.0,3.0,5.0,2,6.0\n"
+    );
+    for extra in [&[][..], &["--processes", "2"], &["--stream"]] {
+        let args = [&["--include", "Oslo", "--include-from", names][..], extra].concat();
+        assert_eq!(run(&args), (true, included.clone()), "{extra:?}");
+        let args = [&["--exclude-from", names][..], extra].concat();
+        assert_eq!(run(&args), (true, excluded.clone()), "{extra:?}");
+    }
+    assert_eq!(run(&["--include", "Nowhere"]), (true, header.to_string()));
+    assert!(!run(&["--exclude-from", "/no/such/file"]).0);
+}
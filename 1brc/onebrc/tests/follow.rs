@@ -0,0 +1,155 @@
+//! `--follow` aggregates appended lines and holds back a half-written one.
+
+mod common;
+
+use common::{options, sample};
+use onebrc::follow::Follower;
+use onebrc::{Report, StationStats, aggregate};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn append(path: &Path, bytes: &[u8]) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+fn expected(data: &[u8]) -> Report {
+    aggregate(data, options(2, 4096))
+}
+
+#[test]
+fn appended_lines_are_aggregated_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.txt");
+    let data = sample(30_000, 1);
+    File::create(&path).unwrap();
+
+    let mut follower = Follower::<StationStats>::new(&path, options(2, 4096)).unwrap();
+    assert!(!follower.catch_up().unwrap());
+
+    // Pieces of all sizes, most of them ending mid-line.
+    let mut written = 0;
+    for piece in [1, 2, 13, 5000, 7, 40_000, 3, 100_000, usize::MAX] {
+        let end = written + piece.min(data.len() - written);
+        append(&path, &data[written..end]);
+        written = end;
+        follower.catch_up().unwrap();
+
+        let complete = data[..written]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |nl| nl + 1);
+        assert_eq!(follower.offset(), complete);
+        assert_eq!(
+            follower.report().stations,
+            expected(&data[..complete]).stations,
+            "after {written} bytes"
+        );
+    }
+    assert_eq!(follower.report().stations, expected(&data).stations);
+}
+
+#[test]
+fn half_written_line_is_held_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.txt");
+    fs::write(&path, "Hamburg;12.0\nBulawayo;8").unwrap();
+
+    let mut follower = Follower::<StationStats>::new(&path, options(2, 4096)).unwrap();
+    follower.catch_up().unwrap();
+    assert_eq!(
+        follower.report().stations,
+        expected(b"Hamburg;12.0\n").stations
+    );
+
+    append(&path, b".9");
+    assert!(!follower.catch_up().unwrap());
+    append(&path, b"\n");
+    assert!(follower.catch_up().unwrap());
+    assert_eq!(
+        follower.report().stations,
+        expected(b"Hamburg;12.0\nBulawayo;8.9\n").stations
+    );
+}
+
+#[test]
+fn truncated_or_replaced_files_start_over() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.txt");
+    fs::write(&path, "a;1.0\nb;2.0\n").unwrap();
+    let mut follower = Follower::<StationStats>::new(&path, options(2, 4096)).unwrap();
+    follower.catch_up().unwrap();
+
+    // Truncated, as by `> log.txt`.
+    File::create(&path).unwrap();
+    append(&path, b"a;3.0\n");
+    follower.catch_up().unwrap();
+
+    // Rotated: the old file gets one more line, then a new one takes its place.
+    append(&path, b"b;4.0\n");
+    fs::rename(&path, dir.path().join("log.txt.1")).unwrap();
+    fs::write(&path, "c;5.0\n").unwrap();
+    follower.catch_up().unwrap();
+
+    assert_eq!(
+        follower.report().stations,
+        expected(b"a;1.0\nb;2.0\na;3.0\nb;4.0\nc;5.0\n").stations
+    );
+}
+
+#[test]
+fn truncated_and_regrown_files_start_over() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.txt");
+    fs::write(&path, "a;1.0\nb;2.0\n").unwrap();
+    let mut follower = Follower::<StationStats>::new(&path, options(2, 4096)).unwrap();
+    follower.catch_up().unwrap();
+
+    // `copytruncate`, and more written than there was before the next look.
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(0).unwrap();
+    append(&path, b"c;3.0\nd;4.0\ne;5.0\n");
+    assert!(follower.catch_up().unwrap());
+    assert_eq!(follower.offset(), 18);
+    assert_eq!(
+        follower.report().stations,
+        expected(b"a;1.0\nb;2.0\nc;3.0\nd;4.0\ne;5.0\n").stations
+    );
+}
+
+#[test]
+fn candidate_22_reports_on_sigusr1() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.txt");
+    fs::write(&path, "Hamburg;12.0\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+        .arg(&path)
+        .args(["--follow", "--interval", "0", "--threads", "2"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let pid = child.id() as i32;
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut next_report = || lines.next().unwrap().unwrap();
+
+    // The handler is installed before the first report is printed.
+    assert_eq!(next_report(), "{Hamburg=12.0/12.0/12.0}");
+
+    append(&path, b"Hamburg;-3.4\nBulawayo;8");
+    unsafe { libc::kill(pid, libc::SIGUSR1) };
+    assert_eq!(next_report(), "{Hamburg=-3.4/4.3/12.0}");
+
+    append(&path, b".9\n");
+    unsafe { libc::kill(pid, libc::SIGUSR1) };
+    assert_eq!(
+        next_report(),
+        "{Bulawayo=8.9/8.9/8.9, Hamburg=-3.4/4.3/12.0}"
+    );
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
@@ -0,0 +1,229 @@
+//! `--input-format general`: values in any decimal notation, validated, with
+//! the fast path kept for chunks in the 1BRC shape.
+
+use onebrc::generate::{SplitMix64, write_rows};
+use onebrc::parse::{InputFormat, InvalidRecord, Values, is_fixed_shape, parse_general};
+use onebrc::stream::aggregate_reader;
+use onebrc::{ExtendedStats, Options, Report, StationStats, Stats, try_aggregate_with};
+use std::process::Command;
+
+fn general(threads: usize, chunk_size: usize) -> Options<'static> {
+    Options {
+        threads,
+        chunk_size,
+        input: InputFormat {
+            values: Values::General,
+            ..Default::default()
+        },
+        ..Options::default()
+    }
+}
+
+/// The report of `data`, one line at a time through [`parse_general`].
+fn reference<S: Stats>(data: &[u8]) -> Report<S> {
+    let mut report = Report::default();
+    for line in data
+        .strip_suffix(b"\n")
+        .unwrap_or(data)
+        .split(|&b| b == b'\n')
+    {
+        let semi = line.iter().position(|&b| b == b';').unwrap();
+        let mut stats = S::default();
+        stats.record(parse_general(&line[semi + 1..]).unwrap());
+        report.merge_station(std::str::from_utf8(&line[..semi]).unwrap(), &stats);
+    }
+    report
+}
+
+/// Generated rows with every `every`th value rewritten in another notation.
+fn mixed(rows: u64, seed: u64, every: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_rows(&mut data, rows, seed, 1).unwrap();
+    let mut rng = SplitMix64::new(seed);
+    let mut out = Vec::with_capacity(data.len() * 2);
+    for (i, line) in data.split_inclusive(|&b| b == b'\n').enumerate() {
+        let semi = line.iter().position(|&b| b == b';').unwrap();
+        let value = std::str::from_utf8(&line[semi + 1..line.len() - 1]).unwrap();
+        out.extend_from_slice(&line[..=semi]);
+        if i % every != 0 {
+            out.extend_from_slice(&line[semi + 1..]);
+            continue;
+        }
+        let v: f64 = value.parse().unwrap();
+        let rewritten = match rng.below(5) {
+            0 => format!("{value}{}", rng.below(10)),
+            1 => format!("{}", v.trunc() as i64),
+            2 => format!("{v:e}"),
+            3 => format!("{:.3}", v * 25.0),
+            _ => format!("+{}", value.trim_start_matches('-')),
+        };
+        out.extend_from_slice(rewritten.as_bytes());
+        out.push(b'\n');
+    }
+    out
+}
+
+#[test]
+fn parses_general_values() {
+    for (value, tenths) in [
+        ("12.3", 123),
+        ("-0.5", -5),
+        ("7", 70),
+        ("1e1", 100),
+        ("101.25", 1013),
+        ("-101.25", -1013),
+        ("101.24", 1012),
+        ("0.05", 1),
+        ("-0.05", -1),
+        ("0.0499", 0),
+        ("+.5", 5),
+        ("5.", 50),
+        ("5e-2", 1),
+        ("1E+2", 1000),
+        ("-1.5e-1", -2),
+        ("0012.30", 123),
+        ("3276.7", 32767),
+        ("-3276.7", -32767),
+        ("0e99999999999999999999", 0),
+        ("0.00000000000000000000000001e26", 10),
+    ] {
+        assert_eq!(parse_general(value.as_bytes()), Ok(tenths), "{value}");
+    }
+    for value in [
+        "",
+        "-",
+        ".",
+        "+-1",
+        "abc",
+        "1.2.3",
+        "1e",
+        "1e+",
+        "e5",
+        "12 ",
+        " 12",
+        "1,5",
+        "0x10",
+        "NaN",
+        "inf",
+        "3276.75",
+        "1e4",
+        "-1e99999999999999999999",
+    ] {
+        assert!(parse_general(value.as_bytes()).is_err(), "{value}");
+    }
+}
+
+#[test]
+fn recognises_the_1brc_shape() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 10_000, 1, 1).unwrap();
+    assert!(is_fixed_shape(&data, &InputFormat::default()));
+    assert!(is_fixed_shape(
+        b"a;1.0\nb;-1.0\nc;12.3\nd;-99.9\n",
+        &InputFormat::default()
+    ));
+    for chunk in [
+        &b"a;101.2\n"[..],
+        b"a;1.25\n",
+        b"a;7\n",
+        b"a;--1.0\n",
+        b"a;b;1.0\n",
+        b"1.0\n",
+        b"a;1.0\n\n",
+        b"a;x.0\n",
+    ] {
+        assert!(
+            !is_fixed_shape(chunk, &InputFormat::default()),
+            "{}",
+            String::from_utf8_lossy(chunk)
+        );
+    }
+}
+
+#[test]
+fn matches_line_by_line_parsing() {
+    for (every, seed) in [(1, 2), (7, 3), (5000, 4)] {
+        let data = mixed(20_000, seed, every);
+        for (threads, chunk_size) in [(1, 1 << 20), (3, 4096), (4, 100)] {
+            let options = general(threads, chunk_size);
+            assert_eq!(
+                try_aggregate_with::<StationStats>(&data, options).unwrap(),
+                reference(&data),
+                "every {every}, {threads} threads, chunks of {chunk_size}"
+            );
+            assert_eq!(
+                aggregate_reader::<StationStats, _>(&data[..], options).unwrap(),
+                reference(&data)
+            );
+        }
+    }
+}
+
+#[test]
+fn extended_stats_keep_values_beyond_the_histogram() {
+    let data = mixed(20_000, 5, 3);
+    let report = try_aggregate_with::<ExtendedStats>(&data, general(2, 4096)).unwrap();
+    assert_eq!(report, reference(&data));
+    assert!(report.stations.values().any(|s| !s.outliers.is_empty()));
+    for stats in report.stations.values() {
+        assert_eq!(stats.percentile(0), stats.basic.min);
+        assert_eq!(stats.percentile(100), stats.basic.max);
+    }
+}
+
+#[test]
+fn reports_the_first_invalid_record() {
+    let mut data = mixed(20_000, 6, 11);
+    let at = data.len() / 2;
+    let at = at + data[at..].iter().position(|&b| b == b'\n').unwrap() + 1;
+    data.splice(at..at, *b"Bad Station;12.3.4\n");
+    data.extend_from_slice(b"Worse Station;twelve\n");
+
+    let expected = InvalidRecord {
+        offset: at as u64,
+        reason: "temperature is not a number",
+    };
+    for (threads, chunk_size) in [(1, 1 << 20), (4, 1000)] {
+        let options = general(threads, chunk_size);
+        let err = try_aggregate_with::<StationStats>(&data, options).unwrap_err();
+        assert_eq!(err, expected);
+        let err = aggregate_reader::<StationStats, _>(&data[..], options).unwrap_err();
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<InvalidRecord>(),
+            Some(&expected)
+        );
+    }
+
+    let tail = b"a;1.0\nb;2.0\nc;\n";
+    let err = try_aggregate_with::<StationStats>(tail, general(1, 4096)).unwrap_err();
+    assert_eq!(err.offset, 12);
+}
+
+#[test]
+fn candidate_22_input_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(&input, b"Oslo;7\nOslo;-0.25\nLima;1e1\nLima;101.25\n").unwrap();
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let out = run(&["--input-format", "general", "--format", "csv"]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "station,min,mean,max,count,sum\n\
+         Lima,10.0,55.7,101.3,2,111.3\n\
+         Oslo,-0.3,3.4,7.0,2,6.7\n"
+    );
+
+    std::fs::write(&input, b"Oslo;7\nOslo;seven\n").unwrap();
+    let out = run(&["--input-format", "general", "--no-fork"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("offset: 7"), "{stderr}");
+}
@@ -0,0 +1,139 @@
+//! `--group-by`: the merged report re-aggregated by a key derived from each
+//! station name.
+
+use onebrc::generate::write_rows;
+use onebrc::group::{GroupBy, UNKNOWN, parse_map};
+use onebrc::{ExtendedStats, Options, Report, StationStats, try_aggregate_with};
+use std::process::Command;
+
+#[test]
+fn derives_groups() {
+    let prefix: GroupBy = "prefix:2".parse().unwrap();
+    assert_eq!(prefix.group("DE-Berlin"), Some("DE"));
+    assert_eq!(prefix.group("Zü"), Some("Zü"));
+    assert_eq!(prefix.group("Zürich"), Some("Zü"));
+    assert_eq!(prefix.group("Z"), None);
+
+    let regex: GroupBy = r"regex:^(\w+)/".parse().unwrap();
+    assert_eq!(regex.group("eu/Berlin"), Some("eu"));
+    assert_eq!(regex.group("Berlin"), None);
+    let whole: GroupBy = r"regex:\d+".parse().unwrap();
+    assert_eq!(whole.group("sensor 42b"), Some("42"));
+
+    let map = GroupBy::Map(parse_map("Oslo,NO\n\"Washington, D.C.\",US\n").unwrap());
+    assert_eq!(map.group("Washington, D.C."), Some("US"));
+    assert_eq!(map.group("Lima"), None);
+
+    for spec in [
+        "prefix:0",
+        "prefix:x",
+        "regex:(",
+        "map:/no/such/file",
+        "suffix:3",
+    ] {
+        assert!(spec.parse::<GroupBy>().is_err(), "{spec}");
+    }
+}
+
+#[test]
+fn parses_mapping_files() {
+    let map =
+        parse_map("station,group\r\nOslo,Norway\r\n\r\n\"Say \"\"hi\"\"\",\"a,b\"\r\n").unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["Oslo"], "Norway");
+    assert_eq!(map["Say \"hi\""], "a,b");
+
+    assert_eq!(
+        parse_map("Oslo,NO\nLima\n").unwrap_err(),
+        "line 2: expected 2 fields, found 1"
+    );
+    assert_eq!(
+        parse_map("Oslo,NO,x\n").unwrap_err(),
+        "line 1: expected 2 fields, found 3"
+    );
+    assert_eq!(
+        parse_map("Oslo,NO\nOslo,SE\n").unwrap_err(),
+        "line 2: \"Oslo\" is mapped twice"
+    );
+    assert_eq!(
+        parse_map("\"Oslo,NO\n").unwrap_err(),
+        "line 1: unterminated quote"
+    );
+}
+
+#[test]
+fn groups_a_report() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 50_000, 1, 7).unwrap();
+    let options = Options {
+        threads: 3,
+        chunk_size: 1 << 14,
+        ..Options::default()
+    };
+    // The same rows with every name replaced by its group up front.
+    let by: GroupBy = "regex:^([A-M])".parse().unwrap();
+    let mut renamed = Vec::new();
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        let semi = line.iter().position(|&b| b == b';').unwrap();
+        let name = std::str::from_utf8(&line[..semi]).unwrap();
+        renamed.extend_from_slice(by.group(name).unwrap_or(UNKNOWN).as_bytes());
+        renamed.extend_from_slice(&line[semi..]);
+    }
+
+    let report: Report = try_aggregate_with(&data, options).unwrap();
+    let grouped = report.grouped(&by);
+    assert_eq!(grouped, try_aggregate_with(&renamed, options).unwrap());
+    assert!(grouped.stations.contains_key(UNKNOWN));
+    let count = |r: &Report| r.stations.values().map(|s| s.count).sum::<usize>();
+    assert_eq!(count(&grouped), 50_000);
+
+    let extended: Report<ExtendedStats> = try_aggregate_with(&data, options).unwrap();
+    let expected: Report<ExtendedStats> = try_aggregate_with(&renamed, options).unwrap();
+    assert_eq!(extended.grouped(&by), expected);
+
+    let all: GroupBy = "prefix:1000".parse().unwrap();
+    let unknown = report.grouped(&all);
+    assert_eq!(unknown.stations.keys().collect::<Vec<_>>(), [UNKNOWN]);
+    let mut total = StationStats::default();
+    for stats in report.stations.values() {
+        total.merge(stats);
+    }
+    assert_eq!(unknown.stations[UNKNOWN], total);
+}
+
+#[test]
+fn candidate_22_groups() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(
+        &input,
+        "Oslo;1.0\nBergen;3.0\nLima;-2.0\nOslo;5.0\nWashington, D.C.;7.5\n",
+    )
+    .unwrap();
+    let map = dir.path().join("countries.csv");
+    std::fs::write(
+        &map,
+        "station,group\nOslo,NO\nBergen,NO\n\"Washington, D.C.\",US\n",
+    )
+    .unwrap();
+
+    let run = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--format", "csv", "--threads", "2"])
+            .args(args)
+            .output()
+            .unwrap();
+        (out.status.success(), String::from_utf8(out.stdout).unwrap())
+    };
+    let expected = "station,min,mean,max,count,sum\n\
+                    NO,1.0,3.0,5.0,3,9.0\n\
+                    US,7.5,7.5,7.5,1,7.5\n\
+                    unknown,-2.0,-2.0,-2.0,1,-2.0\n";
+    let group_by = format!("map:{}", map.display());
+    for extra in [&[][..], &["--processes", "2"], &["--stream"]] {
+        let args = [&["--group-by", group_by.as_str()][..], extra].concat();
+        assert_eq!(run(&args), (true, expected.to_string()), "{extra:?}");
+    }
+    assert!(!run(&["--group-by", "map:/no/such/file"]).0);
+}
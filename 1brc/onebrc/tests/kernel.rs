@@ -0,0 +1,166 @@
+//! `--kernel`: every chunk kernel this CPU supports gives the same report.
+
+mod common;
+
+use onebrc::generate::{SplitMix64, write_rows};
+use onebrc::stream::aggregate_reader;
+use onebrc::{ExtendedStats, Kernel, Options, Report, try_aggregate_with};
+use std::process::Command;
+
+fn supported() -> Vec<Kernel> {
+    Kernel::ALL
+        .into_iter()
+        .filter(|k| k.is_supported())
+        .collect()
+}
+
+fn options(threads: usize, chunk_size: usize, kernel: Kernel) -> Options<'static> {
+    Options {
+        kernel,
+        ..common::options(threads, chunk_size)
+    }
+}
+
+/// Names around the 8, 32 and 64 bytes the kernels look at per step, some
+/// with multi-byte characters, in a random order.
+fn odd_names(rows: usize, seed: u64) -> Vec<u8> {
+    let mut rng = SplitMix64::new(seed);
+    let mut data = Vec::new();
+    for _ in 0..rows {
+        let len = [1, 7, 8, 9, 30, 31, 32, 33, 62, 63, 64, 65, 100][rng.below(13)];
+        let name: String = (0..len)
+            .map(|i| match (i + len) % 11 {
+                0 => 'é',
+                _ => char::from(b'a' + (i % 26) as u8),
+            })
+            .collect();
+        let temp = rng.below(1999) as i64 - 999;
+        let sign = if temp < 0 { "-" } else { "" };
+        let line = format!("{name};{sign}{}.{}\n", temp.abs() / 10, temp.abs() % 10);
+        data.extend_from_slice(line.as_bytes());
+    }
+    data
+}
+
+#[test]
+fn detects_and_parses_kernels() {
+    assert!(Kernel::Swar.is_supported());
+    assert!(Kernel::detect().is_supported());
+    assert_eq!(Kernel::default(), Kernel::detect());
+    assert_eq!("auto".parse::<Kernel>(), Ok(Kernel::detect()));
+    for kernel in Kernel::ALL {
+        let parsed = kernel.to_string().parse::<Kernel>();
+        assert_eq!(parsed.is_ok(), kernel.is_supported(), "{kernel}");
+        if let Ok(parsed) = parsed {
+            assert_eq!(parsed, kernel);
+        }
+    }
+    assert!("sse".parse::<Kernel>().is_err());
+}
+
+/// The aarch64 CI job is the only place NEON runs, so it must not be
+/// filtered out of [`supported`] there, or the tests below pass without it.
+#[test]
+fn neon_is_tested_on_aarch64() {
+    let aarch64 = cfg!(target_arch = "aarch64");
+    assert_eq!(supported().contains(&Kernel::Neon), aarch64);
+    if aarch64 {
+        assert_eq!(Kernel::detect(), Kernel::Neon);
+    }
+}
+
+#[test]
+fn kernels_agree() {
+    let mut generated = Vec::new();
+    write_rows(&mut generated, 100_000, 5, 1).unwrap();
+    let odd = odd_names(20_000, 9);
+    for data in [&generated, &odd] {
+        let expected = common::reference(data);
+        for kernel in supported() {
+            for (threads, chunk_size) in [(1, 1 << 20), (3, 4096), (2, 100)] {
+                let options = options(threads, chunk_size, kernel);
+                let report = try_aggregate_with(data, options).unwrap();
+                assert_eq!(common::from_report(&report), expected, "{kernel}");
+                let streamed = aggregate_reader(&data[..], options).unwrap();
+                assert_eq!(report, streamed, "{kernel}");
+            }
+        }
+    }
+}
+
+#[test]
+fn kernels_agree_on_layouts_and_stats() {
+    let data = odd_names(5_000, 4);
+    let tabs: Vec<u8> = data
+        .iter()
+        .map(|&b| if b == b';' { b'\t' } else { b })
+        .collect();
+    let crlf = String::from_utf8(data.clone())
+        .unwrap()
+        .replace('\n', "\r\n")
+        .into_bytes();
+    let expected: Report<ExtendedStats> =
+        try_aggregate_with(&data, options(2, 1000, Kernel::Swar)).unwrap();
+    for kernel in supported() {
+        let report = try_aggregate_with(&data, options(2, 1000, kernel)).unwrap();
+        assert_eq!(report, expected, "{kernel}");
+        let format = "1brc,sep=tab".parse().unwrap();
+        let options = Options {
+            input: format,
+            ..options(2, 1000, kernel)
+        };
+        assert_eq!(
+            try_aggregate_with(&tabs, options).unwrap(),
+            expected,
+            "{kernel}"
+        );
+        let options = Options {
+            input: "1brc,crlf".parse().unwrap(),
+            ..options
+        };
+        assert_eq!(
+            try_aggregate_with(&crlf, options).unwrap(),
+            expected,
+            "{kernel}"
+        );
+    }
+}
+
+/// The fast path trusts `1brc` input, but a line without a separator must
+/// not make it read past the padding (or fail); the other records still count.
+#[test]
+fn survive_a_line_without_separator() {
+    for kernel in supported() {
+        for data in [&b"Oslo;1.0\nno separator\n"[..], b"Oslo;1.0\nx\nLima;2.0\n"] {
+            let report: Report = try_aggregate_with(data, options(1, 1 << 20, kernel)).unwrap();
+            assert_eq!(report.stations["Oslo"].count, 1, "{kernel}");
+        }
+    }
+}
+
+#[test]
+fn candidate_22_kernels() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(&input, odd_names(3_000, 1)).unwrap();
+
+    let run = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--threads", "2", "--chunk-size", "4K"])
+            .args(args)
+            .output()
+            .unwrap();
+        (out.status.success(), String::from_utf8(out.stdout).unwrap())
+    };
+    let (ok, expected) = run(&["--kernel", "swar"]);
+    assert!(ok);
+    for kernel in supported() {
+        let kernel = kernel.to_string();
+        assert_eq!(run(&["--kernel", &kernel]), (true, expected.clone()));
+        let args = ["--kernel", &kernel, "--processes", "2"];
+        assert_eq!(run(&args), (true, expected.clone()));
+    }
+    assert_eq!(run(&[]), (true, expected));
+    assert!(!run(&["--kernel", "sse"]).0);
+}
@@ -0,0 +1,180 @@
+//! `--normalize` and `--invalid-utf8`: station names are turned into report
+//! keys when the tables are merged.
+
+mod common;
+
+use onebrc::generate::write_rows;
+use onebrc::keys::{InvalidUtf8, Keys};
+use onebrc::stream::aggregate_reader;
+use onebrc::{InvalidRecord, Options, Report, StationStats, try_aggregate_with};
+use std::process::Command;
+
+fn options(threads: usize, chunk_size: usize, keys: Keys) -> Options<'static> {
+    Options {
+        keys,
+        ..common::options(threads, chunk_size)
+    }
+}
+
+#[test]
+fn makes_keys() {
+    let all: Keys = "nfc,casefold,trim".parse().unwrap();
+    for (name, key) in [
+        ("Zu\u{308}rich", "zürich"),
+        ("ZÜRICH", "zürich"),
+        ("  Zürich\t", "zürich"),
+        ("Straße", "strasse"),
+        ("STRASSE", "strasse"),
+        ("", ""),
+    ] {
+        assert_eq!(all.key(name.as_bytes()).unwrap(), key, "{name}");
+    }
+
+    let nfc: Keys = "nfc".parse().unwrap();
+    assert_eq!(nfc.key("Zu\u{308}rich".as_bytes()).unwrap(), "Z\u{fc}rich");
+    assert_eq!(nfc.key(b" Oslo").unwrap(), " Oslo");
+    assert_eq!(
+        Keys::default().key("Zu\u{308}rich".as_bytes()).unwrap(),
+        "Zu\u{308}rich"
+    );
+
+    let name = b"Os\xffl\xc3";
+    assert_eq!(Keys::default().key(name), Err("station name is not UTF-8"));
+    let lossy = Keys {
+        invalid_utf8: InvalidUtf8::Lossy,
+        ..Keys::default()
+    };
+    assert_eq!(lossy.key(name).unwrap(), "Os\u{fffd}l\u{fffd}");
+    let escape = Keys {
+        invalid_utf8: InvalidUtf8::Escape,
+        ..all
+    };
+    assert_eq!(escape.key(b" OS\xffLO ").unwrap(), "os\\xfflo");
+    assert_eq!(escape.key(name).unwrap(), "os\\xffl\\xc3");
+}
+
+#[test]
+fn parses_policies() {
+    for (spec, canonical) in [
+        ("none", "none"),
+        ("trim", "trim"),
+        ("trim,nfc", "nfc,trim"),
+        ("casefold,nfc,trim", "nfc,casefold,trim"),
+    ] {
+        let keys: Keys = spec.parse().unwrap();
+        assert_eq!(keys.to_string(), canonical);
+        assert_eq!(canonical.parse::<Keys>(), Ok(keys));
+    }
+    for spec in ["", "nfd", "nfc,", "lower"] {
+        assert!(spec.parse::<Keys>().is_err(), "{spec}");
+    }
+    for policy in ["error", "lossy", "escape"] {
+        let parsed: InvalidUtf8 = policy.parse().unwrap();
+        assert_eq!(parsed.to_string(), policy);
+    }
+    assert!("replace".parse::<InvalidUtf8>().is_err());
+}
+
+#[test]
+fn merges_names_that_normalize_alike() {
+    // The same rows under one spelling and spread over several.
+    let mut plain = Vec::new();
+    write_rows(&mut plain, 20_000, 1, 1).unwrap();
+    let spellings = ["Zürich", "Zu\u{308}rich", "ZÜRICH", " zürich "];
+    let mut mixed = Vec::new();
+    let mut same = Vec::new();
+    for (i, line) in plain.split_inclusive(|&b| b == b'\n').enumerate() {
+        let value = &line[line.iter().position(|&b| b == b';').unwrap()..];
+        if i % 3 == 0 {
+            mixed.extend_from_slice(spellings[i % 4].as_bytes());
+            mixed.extend_from_slice(value);
+            same.extend_from_slice("zürich".as_bytes());
+            same.extend_from_slice(value);
+        } else {
+            let lower = String::from_utf8(line.to_vec()).unwrap().to_lowercase();
+            mixed.extend_from_slice(line);
+            same.extend_from_slice(lower.as_bytes());
+        }
+    }
+
+    let all: Keys = "nfc,casefold,trim".parse().unwrap();
+    // Only the lower-casing differs from case folding in the generated names.
+    let expected: Report = try_aggregate_with(&same, options(1, 1 << 20, all)).unwrap();
+    assert!(expected.stations.contains_key("zürich"));
+    for (threads, chunk_size) in [(1, 1 << 20), (4, 4096), (3, 100)] {
+        let options = options(threads, chunk_size, all);
+        assert_eq!(try_aggregate_with(&mixed, options).unwrap(), expected);
+        let streamed: Report = aggregate_reader(&mixed[..], options).unwrap();
+        assert_eq!(streamed, expected);
+    }
+
+    let raw: Report = try_aggregate_with(&mixed, options(2, 4096, Keys::default())).unwrap();
+    for spelling in spellings {
+        assert!(raw.stations.contains_key(spelling), "{spelling}");
+    }
+}
+
+#[test]
+fn applies_the_utf8_policy() {
+    let data = b"Oslo;1.0\nOs\xfflo;2.0\nLima;3.0\nOs\xfflo;4.0\n";
+    for threads in [1, 2] {
+        let err = try_aggregate_with::<StationStats>(data, options(threads, 16, Keys::default()))
+            .unwrap_err();
+        // Either of the lines with that name.
+        assert!([9, 28].contains(&err.offset), "{err}");
+        assert_eq!(err.reason, "station name is not UTF-8");
+        let err =
+            aggregate_reader::<StationStats, _>(&data[..], options(threads, 16, Keys::default()))
+                .unwrap_err();
+        let err = err.get_ref().unwrap().downcast_ref::<InvalidRecord>();
+        assert!(err.is_some_and(|e| [9, 28].contains(&e.offset)), "{err:?}");
+    }
+
+    let escape = Keys {
+        invalid_utf8: InvalidUtf8::Escape,
+        ..Keys::default()
+    };
+    let report: Report = try_aggregate_with(data, options(2, 16, escape)).unwrap();
+    let names: Vec<_> = report.stations.keys().map(String::as_str).collect();
+    assert_eq!(names, ["Lima", "Os\\xFFlo", "Oslo"]);
+    assert_eq!(report.stations["Os\\xFFlo"].count, 2);
+}
+
+#[test]
+fn candidate_22_normalizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(
+        &input,
+        "Zu\u{308}rich;1.0\nZürich;3.0\nZÜRICH ;5.0\nOslo;1.0\n".as_bytes(),
+    )
+    .unwrap();
+    let mut data = std::fs::read(&input).unwrap();
+    data.extend_from_slice(b"Os\xfflo;2.0\n");
+    std::fs::write(&input, data).unwrap();
+
+    let run = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--format", "csv", "--threads", "2"])
+            .args(args)
+            .output()
+            .unwrap();
+        (out.status.success(), String::from_utf8(out.stdout).unwrap())
+    };
+    let expected = "station,min,mean,max,count,sum\n\
+                    os\\xfflo,2.0,2.0,2.0,1,2.0\n\
+                    oslo,1.0,1.0,1.0,1,1.0\n\
+                    zürich,1.0,3.0,5.0,3,9.0\n";
+    let normalize = ["--normalize", "nfc,casefold,trim"];
+    let escape = ["--invalid-utf8", "escape"];
+    assert_eq!(
+        run(&[&normalize[..], &escape].concat()),
+        (true, expected.to_string())
+    );
+    assert_eq!(
+        run(&[&normalize[..], &escape, &["--processes", "2"]].concat()),
+        (true, expected.to_string())
+    );
+    assert!(!run(&["--no-fork"]).0);
+}
@@ -0,0 +1,188 @@
+//! Input format specs: other separators, column layouts and CRLF give the
+//! same report as the same records in the 1BRC layout.
+
+mod common;
+
+use common::sample;
+use onebrc::parse::{InputFormat, Values, is_fixed_shape, split_record};
+use onebrc::stream::aggregate_reader;
+use onebrc::{Options, Report, aggregate, try_aggregate_with};
+use std::process::Command;
+
+/// Rewrite every `name;value` line of `data` with `line(name, value)`.
+fn relayout(data: &[u8], line: impl Fn(&str, &str) -> String) -> Vec<u8> {
+    let text = std::str::from_utf8(data).unwrap();
+    text.lines()
+        .map(|l| {
+            let (name, value) = l.split_once(';').unwrap();
+            line(name, value)
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn check(data: &[u8], spec: &str, expected: &Report) {
+    let input: InputFormat = spec.parse().unwrap();
+    for (threads, chunk_size) in [(1, 1 << 20), (3, 4096), (2, 64)] {
+        let options = Options {
+            threads,
+            chunk_size,
+            input,
+            ..Options::default()
+        };
+        let report = try_aggregate_with(data, options).unwrap();
+        assert_eq!(&report, expected, "{spec}, chunks of {chunk_size}");
+        let report: Report = aggregate_reader(data, options).unwrap();
+        assert_eq!(&report, expected, "{spec} streamed");
+    }
+}
+
+#[test]
+fn parses_specs() {
+    for (spec, canonical) in [
+        ("1brc", "1brc"),
+        ("general", "general"),
+        ("sep=tab", "1brc,sep=tab"),
+        ("sep=;", "1brc"),
+        ("crlf,sep=|", "1brc,sep=pipe,crlf"),
+        (
+            "general,sep=comma,key=1,value=3",
+            "general,sep=comma,key=1,value=3",
+        ),
+        ("value=0,key=1,sep=:", "1brc,sep=:,key=1,value=0"),
+    ] {
+        let format: InputFormat = spec.parse().unwrap();
+        assert_eq!(format.to_string(), canonical, "{spec}");
+        assert_eq!(canonical.parse::<InputFormat>(), Ok(format));
+    }
+    let format: InputFormat = "general,sep=tab,key=2,value=0,crlf".parse().unwrap();
+    assert_eq!(
+        format,
+        InputFormat {
+            values: Values::General,
+            separator: b'\t',
+            key: 2,
+            value: 0,
+            crlf: true,
+        }
+    );
+
+    for spec in [
+        "", "csv", "sep=", "sep=ab", "sep=.", "sep=-", "sep=7", "sep=x", "key=1", "key=-1",
+        "value=x", "crlf=1",
+    ] {
+        assert!(spec.parse::<InputFormat>().is_err(), "{spec}");
+    }
+}
+
+#[test]
+fn splits_records() {
+    let format: InputFormat = "sep=|,key=2,value=0,crlf".parse().unwrap();
+    let line = b"12.5|x|Oslo\r";
+    let (key, value) = split_record(line, &format).unwrap();
+    assert_eq!((&line[key], &line[value]), (&b"Oslo"[..], &b"12.5"[..]));
+    assert!(split_record(b"12.5|x", &format).is_err());
+    assert!(split_record(b"12.5", &format).is_err());
+
+    let format: InputFormat = "sep=tab,key=0,value=1".parse().unwrap();
+    let (key, value) = split_record(b"\t1.0\textra", &format).unwrap();
+    assert_eq!((key, value), (0..0, 1..4));
+}
+
+#[test]
+fn recognises_the_1brc_shape_in_other_layouts() {
+    let format: InputFormat = "general,sep=tab,crlf".parse().unwrap();
+    assert!(is_fixed_shape(b"a b\t1.0\r\nc;d\t-12.3\n", &format));
+    assert!(!is_fixed_shape(b"a;1.0\r\n", &format));
+    assert!(!is_fixed_shape(b"a\t1.0\r\r\n", &format));
+    assert!(!is_fixed_shape(b"a\tb\t1.0\n", &format));
+}
+
+#[test]
+fn layouts_match_the_1brc_layout() {
+    let data = sample(20_000, 1);
+    let expected = aggregate(&data, Options::default());
+
+    check(
+        &relayout(&data, |n, v| format!("{n}\t{v}\n")),
+        "sep=tab",
+        &expected,
+    );
+    check(
+        &relayout(&data, |n, v| format!("{n};{v}\r\n")),
+        "crlf",
+        &expected,
+    );
+    check(
+        &relayout(&data, |n, v| format!("{n}|{v}\r\n")),
+        "general,sep=pipe,crlf",
+        &expected,
+    );
+    check(
+        &relayout(&data, |n, v| format!("2024-01-01T00:00:00Z|{n}|{v}|ok\n")),
+        "sep=pipe,key=1,value=2",
+        &expected,
+    );
+    check(
+        &relayout(&data, |n, v| format!("{v}\t{n}\r\n")),
+        "general,sep=tab,key=1,value=0,crlf",
+        &expected,
+    );
+}
+
+#[test]
+fn rejects_lines_without_the_columns() {
+    let input = "sep=tab,key=1,value=2".parse().unwrap();
+    let options = Options {
+        threads: 1,
+        chunk_size: 4096,
+        input,
+        ..Options::default()
+    };
+    let data = b"t\tOslo\t1.0\nt\tLima\n";
+    let err = try_aggregate_with::<onebrc::StationStats>(data, options).unwrap_err();
+    assert_eq!((err.offset, err.reason), (11, "no temperature column"));
+}
+
+#[test]
+fn candidate_22_reads_csv_with_crlf() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.csv");
+    std::fs::write(
+        &input,
+        "time,station,temperature\r\n\
+         1,Oslo,7\r\n\
+         2,Lima,-0.25\r\n\
+         3,Oslo,1e1\r\n",
+    )
+    .unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+        .arg(&input)
+        .args(["--input-format", "general,sep=comma,key=1,value=2,crlf"])
+        .arg("--no-fork")
+        .output()
+        .unwrap();
+    // The header is not a number.
+    assert!(!out.status.success());
+
+    let body = std::fs::read(&input).unwrap()[26..].to_vec();
+    std::fs::write(&input, body).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+        .arg(&input)
+        .args(["--input-format", "general,sep=comma,key=1,value=2,crlf"])
+        .args(["--format", "csv", "--processes", "2"])
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "station,min,mean,max,count,sum\n\
+         Lima,-0.3,-0.3,-0.3,1,-0.3\n\
+         Oslo,7.0,8.5,10.0,2,17.0\n"
+    );
+}
@@ -0,0 +1,223 @@
+//! Partial results round-trip, and merging them is associative.
+
+mod common;
+
+use common::options;
+use onebrc::generate::{SplitMix64, write_rows};
+use onebrc::partial::{self, decode, encode};
+use onebrc::report::Format;
+use onebrc::stats::StatsMode;
+use onebrc::{ExtendedStats, Report, StationStats, Stats, aggregate_with};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn bytes<S: Stats>(report: &Report<S>) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode(report, &mut out);
+    out
+}
+
+/// The partial of merging the partials `a` and `b`.
+fn merged<S: Stats>(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut report = decode::<S>(a).unwrap();
+    report.merge(&decode(b).unwrap());
+    bytes(&report)
+}
+
+/// `data` split into `n` line-aligned shards at random places (possibly empty).
+fn shards<'a>(data: &'a [u8], n: usize, rng: &mut SplitMix64) -> Vec<&'a [u8]> {
+    let mut cuts: Vec<usize> = (1..n)
+        .map(|_| {
+            let at = rng.below(data.len());
+            data[at..].iter().position(|&b| b == b'\n').unwrap() + at + 1
+        })
+        .collect();
+    cuts.sort();
+    let mut out = Vec::new();
+    let mut start = 0;
+    for end in cuts.into_iter().chain([data.len()]) {
+        out.push(&data[start..end]);
+        start = end;
+    }
+    out
+}
+
+fn check_associative<S: Stats + Send>(seed: u64) {
+    let mut data = Vec::new();
+    write_rows(&mut data, 20_000, seed, 1).unwrap();
+    let whole = bytes(&aggregate_with::<S>(&data, options(2, 4096)));
+
+    let mut rng = SplitMix64::new(seed);
+    for _ in 0..5 {
+        let parts: Vec<_> = shards(&data, 3, &mut rng)
+            .into_iter()
+            .map(|shard| bytes(&aggregate_with::<S>(shard, options(2, 4096))))
+            .collect();
+        let [a, b, c] = &parts[..] else {
+            unreachable!()
+        };
+
+        let left = merged::<S>(&merged::<S>(a, b), c);
+        let right = merged::<S>(a, &merged::<S>(b, c));
+        let swapped = merged::<S>(&merged::<S>(c, a), b);
+        assert_eq!(left, right);
+        assert_eq!(left, swapped);
+        assert_eq!(left, whole);
+    }
+}
+
+#[test]
+fn merge_is_associative() {
+    for seed in 1..=4 {
+        check_associative::<StationStats>(seed);
+    }
+}
+
+#[test]
+fn merge_of_extended_stats_is_associative() {
+    check_associative::<ExtendedStats>(5);
+}
+
+#[test]
+fn round_trip() {
+    let mut report = Report::<StationStats>::default();
+    for (name, temps) in [
+        ("Zürich", &[-999, 999][..]),
+        ("Washington, D.C.", &[0]),
+        ("", &[-1, -1, -1]),
+    ] {
+        let mut s = StationStats::default();
+        for &t in temps {
+            s.record(t);
+        }
+        report.merge_station(name, &s);
+    }
+    let encoded = bytes(&report);
+    assert_eq!(partial::mode(&encoded), Some(StatsMode::Basic));
+    assert_eq!(decode::<StationStats>(&encoded).unwrap(), report);
+
+    let empty = bytes(&Report::<ExtendedStats>::default());
+    assert_eq!(partial::mode(&empty), Some(StatsMode::Extended));
+    assert!(decode::<ExtendedStats>(&empty).unwrap().stations.is_empty());
+
+    let mut out = Vec::new();
+    report.write_to(&mut out, Format::Partial).unwrap();
+    assert_eq!(out, encoded);
+    assert_eq!("partial".parse(), Ok(Format::Partial));
+}
+
+#[test]
+fn rejects_other_files() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 1000, 6, 1).unwrap();
+    let encoded = bytes(&aggregate_with::<StationStats>(&data, options(2, 4096)));
+
+    assert!(decode::<ExtendedStats>(&encoded).is_err());
+    assert!(decode::<StationStats>(&encoded[..encoded.len() - 1]).is_err());
+    assert!(decode::<StationStats>(&[encoded.as_slice(), b"x"].concat()).is_err());
+    assert!(decode::<StationStats>(&data).is_err());
+    assert_eq!(partial::mode(&data), None);
+}
+
+#[test]
+fn rejects_impossible_stats() {
+    let mut report = Report::<ExtendedStats>::default();
+    let mut s = ExtendedStats::default();
+    s.record(-5);
+    s.record(12);
+    report.merge_station("a", &s);
+    let encoded = bytes(&report);
+    assert!(decode::<ExtendedStats>(&encoded).is_ok());
+    // Header, station count, name length and name "a" come before the stats.
+    let stats = 13 + 8 + 4 + 1;
+    let corrupt = |at: usize, value: &[u8]| {
+        let mut bytes = encoded.clone();
+        bytes[at..at + value.len()].copy_from_slice(value);
+        decode::<ExtendedStats>(&bytes)
+    };
+
+    let count = stats + 4;
+    for bad in [0u64, 1, 3] {
+        let err = corrupt(count, &bad.to_le_bytes()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+    let err = corrupt(stats, &20i16.to_le_bytes()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    let mut twice = encoded.clone();
+    twice[13..21].copy_from_slice(&2u64.to_le_bytes());
+    twice.extend_from_slice(&encoded[21..]);
+    let err = decode::<ExtendedStats>(&twice).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn candidate_22_merges_shards() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut data = Vec::new();
+    write_rows(&mut data, 30_000, 7, 1).unwrap();
+
+    let candidate = |args: &[&dyn AsRef<std::ffi::OsStr>]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        out.stdout
+    };
+    let write = |name: &str, bytes: &[u8]| -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(bytes)
+            .unwrap();
+        path
+    };
+    let to_partial = |input: &Path, name: &str| -> PathBuf {
+        let path = dir.path().join(name);
+        candidate(&[&input, &"--format", &"partial", &"--output", &path]);
+        path
+    };
+
+    let whole = write("all.txt", &data);
+    let expected = candidate(&[&whole]);
+
+    let mut rng = SplitMix64::new(7);
+    let parts: Vec<_> = shards(&data, 4, &mut rng)
+        .into_iter()
+        .enumerate()
+        .map(|(i, shard)| to_partial(&write(&format!("{i}.txt"), shard), &format!("{i}.part")))
+        .collect();
+
+    // All at once, and as a merge of merges.
+    assert_eq!(
+        candidate(&[&"merge", &parts[0], &parts[1], &parts[2], &parts[3]]),
+        expected
+    );
+    let left = dir.path().join("01.part");
+    candidate(&[
+        &"merge",
+        &parts[0],
+        &parts[1],
+        &"--format",
+        &"partial",
+        &"-o",
+        &left,
+    ]);
+    let right = dir.path().join("23.part");
+    candidate(&[
+        &"merge",
+        &parts[3],
+        &parts[2],
+        &"--format",
+        &"partial",
+        &"-o",
+        &right,
+    ]);
+    assert_eq!(candidate(&[&"merge", &right, &left]), expected);
+}
@@ -0,0 +1,190 @@
+//! `--pin`, `--cpus` and `--prefer-pcores`: CPU topology from sysfs, where
+//! workers go, and that pinned workers still give the same report.
+
+use onebrc::generate::write_rows;
+use onebrc::placement::{
+    CoreClass, Cpu, FULL_CAPACITY, Placement, Topology, affinity, parse_cpu_list,
+};
+use onebrc::stream::aggregate_reader;
+use onebrc::uring::{ReadMode, aggregate_file};
+use onebrc::validate::validate;
+use onebrc::{Options, Report, StationStats, try_aggregate_with};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// A sysfs tree with the given files under `cpuN/`.
+fn sysfs(root: &Path, files: &[(usize, &str, &str)]) {
+    for (cpu, file, contents) in files {
+        let path = root.join(format!("cpu{cpu}")).join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("{contents}\n")).unwrap();
+    }
+}
+
+fn cpu(id: usize, class: CoreClass, capacity: u32) -> Cpu {
+    Cpu {
+        id,
+        class,
+        capacity,
+    }
+}
+
+#[test]
+fn parses_cpu_lists() {
+    assert_eq!(parse_cpu_list("0-3").unwrap(), [0, 1, 2, 3]);
+    assert_eq!(parse_cpu_list("8,0-1,1\n").unwrap(), [0, 1, 8]);
+    for list in ["", "3-1", "a", "0-", "1,,2", "100000"] {
+        assert!(parse_cpu_list(list).is_err(), "{list:?}");
+    }
+}
+
+#[test]
+fn reads_topology() {
+    use CoreClass::{Efficiency, Performance};
+    let dir = tempfile::tempdir().unwrap();
+    let capacities = dir.path().join("capacities");
+    sysfs(
+        &capacities,
+        &[
+            (0, "cpu_capacity", "1009"),
+            (1, "cpu_capacity", "1009"),
+            (2, "cpu_capacity", "504"),
+            (3, "cpu_capacity", "504"),
+        ],
+    );
+    let topology = Topology::read(&capacities, &[0, 2, 3]);
+    assert_eq!(
+        topology.cpus,
+        [
+            cpu(0, Performance, FULL_CAPACITY),
+            cpu(2, Efficiency, 511),
+            cpu(3, Efficiency, 511),
+        ]
+    );
+
+    let core_types = dir.path().join("core_types");
+    sysfs(
+        &core_types,
+        &[
+            (0, "topology/core_type", "intel_atom"),
+            (1, "topology/core_type", "intel_core"),
+        ],
+    );
+    let topology = Topology::read(&core_types, &[0, 1, 2]);
+    assert_eq!(
+        topology.cpus,
+        [
+            cpu(0, Efficiency, FULL_CAPACITY / 2),
+            cpu(1, Performance, FULL_CAPACITY),
+            cpu(2, Performance, FULL_CAPACITY),
+        ]
+    );
+}
+
+#[test]
+fn places_workers() {
+    use CoreClass::{Efficiency, Performance};
+    let topology = Topology {
+        cpus: vec![
+            cpu(0, Efficiency, 512),
+            cpu(1, Efficiency, 512),
+            cpu(2, Performance, 1024),
+            cpu(3, Performance, 1024),
+        ],
+    };
+
+    let unpinned = Placement::new(&topology, Some(&[1, 2]), false, false).unwrap();
+    assert_eq!(unpinned.cpu(0), None);
+    assert_eq!(unpinned.chunk_size(0, 1 << 20), 1 << 20);
+    assert_eq!(
+        unpinned.to_string(),
+        "running on 1,2 (1 performance, 1 efficiency)"
+    );
+
+    let pinned = Placement::new(&topology, None, true, false).unwrap();
+    let ids: Vec<usize> = (0..6).map(|i| pinned.cpu(i).unwrap().id).collect();
+    assert_eq!(ids, [0, 1, 2, 3, 0, 1]);
+    assert_eq!(pinned.chunk_size(0, 1 << 20), 1 << 19);
+    assert_eq!(pinned.chunk_size(2, 1 << 20), 1 << 20);
+
+    let preferred = Placement::new(&topology, None, false, true).unwrap();
+    let ids: Vec<usize> = (0..4).map(|i| preferred.cpu(i).unwrap().id).collect();
+    assert_eq!(ids, [2, 3, 0, 1]);
+    assert_eq!(
+        preferred.to_string(),
+        "pinned to 2,3,0,1 (2 performance, 2 efficiency)"
+    );
+    // Only efficiency cores: they are the fastest ones used.
+    let slow = Placement::new(&topology, Some(&[0, 1]), true, false).unwrap();
+    assert_eq!(slow.chunk_size(1, 4096), 4096);
+
+    assert_eq!(
+        Placement::new(&topology, Some(&[3, 7]), true, false).unwrap_err(),
+        "CPU 7 is not available to this process"
+    );
+    assert!(Placement::new(&topology, Some(&[]), true, false).is_err());
+}
+
+#[test]
+fn pinned_workers_agree() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 50_000, 6, 1).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("measurements.txt");
+    fs::write(&path, &data).unwrap();
+
+    let before = affinity().unwrap();
+    let topology = Topology::detect().unwrap();
+    let unpinned = Options {
+        threads: 3,
+        chunk_size: 4096,
+        ..Options::default()
+    };
+    let expected: Report = try_aggregate_with(&data, unpinned).unwrap();
+    for (pin, prefer_pcores) in [(true, false), (false, false), (false, true)] {
+        let placement = Placement::new(&topology, None, pin, prefer_pcores).unwrap();
+        let options = Options {
+            placement: Some(&placement),
+            ..unpinned
+        };
+        assert_eq!(try_aggregate_with(&data, options).unwrap(), expected);
+        assert_eq!(aggregate_reader(&data[..], options).unwrap(), expected);
+        let (report, _) = aggregate_file(&path, options, ReadMode::Direct).unwrap();
+        assert_eq!(report, expected);
+        assert_eq!(validate::<StationStats>(&data, options, 0).report, expected);
+    }
+    assert_eq!(affinity().unwrap(), before, "only the workers move");
+}
+
+#[test]
+fn candidate_22_placement() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    let mut data = Vec::new();
+    write_rows(&mut data, 20_000, 2, 1).unwrap();
+    fs::write(&input, &data).unwrap();
+
+    let run = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--chunk-size", "8K"])
+            .args(args)
+            .output()
+            .unwrap();
+        (out.status.success(), String::from_utf8(out.stdout).unwrap())
+    };
+    let (ok, expected) = run(&["--threads", "2"]);
+    assert!(ok);
+    let first = affinity().unwrap()[0].to_string();
+    for args in [
+        &["--threads", "2", "--pin"][..],
+        &["--threads", "2", "--prefer-pcores"],
+        &["--cpus", &first],
+        &["--cpus", &first, "--pin", "--stream"],
+    ] {
+        assert_eq!(run(args), (true, expected.clone()), "{args:?}");
+    }
+    assert!(!run(&["--cpus", "4000"]).0);
+    assert!(!run(&["--no-fork", "--pin", "--processes", "2"]).0);
+}
@@ -0,0 +1,166 @@
+//! Report output formats.
+
+use onebrc::report::{Format, Tenths};
+use onebrc::{Options, Report, StationStats, aggregate};
+use std::io::Write;
+use std::process::Command;
+
+fn stats(temps: &[i16]) -> StationStats {
+    let mut s = StationStats::default();
+    for &t in temps {
+        s.record(t);
+    }
+    s
+}
+
+fn sample() -> Report {
+    let mut report = Report::default();
+    report.merge_station("Zürich", &stats(&[-999, 999, 0]));
+    report.merge_station("Abha", &stats(&[-338, 711, 167]));
+    report.merge_station("Washington, D.C.", &stats(&[-1, -2]));
+    report.merge_station("Say \"hi\"", &stats(&[5]));
+    report
+}
+
+fn render(report: &Report, format: Format) -> String {
+    let mut out = Vec::new();
+    report.write_to(&mut out, format).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn tenths_are_exact() {
+    assert_eq!(Tenths(0).to_string(), "0.0");
+    assert_eq!(Tenths(-4).to_string(), "-0.4");
+    assert_eq!(Tenths(5).to_string(), "0.5");
+    assert_eq!(Tenths(-999).to_string(), "-99.9");
+    assert_eq!(Tenths(123_456_789_012).to_string(), "12345678901.2");
+}
+
+#[test]
+fn mean_rounds_half_up() {
+    let mean = |total, count| {
+        StationStats {
+            min: 0,
+            max: 0,
+            count,
+            total,
+        }
+        .mean_tenths()
+    };
+    assert_eq!(mean(1, 2), 1);
+    assert_eq!(mean(-1, 2), 0);
+    assert_eq!(mean(-3, 2), -1);
+    assert_eq!(mean(-4, 3), -1);
+    assert_eq!(mean(-5, 3), -2);
+    assert_eq!(mean(2214, 10), 221);
+}
+
+#[test]
+fn challenge_is_the_display_format() {
+    let report = sample();
+    assert_eq!(render(&report, Format::default()), format!("{report}\n"));
+    // The same half-up mean as the other formats, where `{:.1}` of the f64
+    // would round 0.25 to even.
+    let mut halves = Report::default();
+    halves.merge_station("Oslo", &stats(&[2, 3]));
+    halves.merge_station("Rome", &stats(&[-2, -3]));
+    assert_eq!(
+        halves.to_string(),
+        "{Oslo=0.2/0.3/0.3, Rome=-0.3/-0.2/-0.2}"
+    );
+}
+
+#[test]
+fn json() {
+    assert_eq!(
+        render(&sample(), Format::Json),
+        r#"[
+{"station":"Abha","min":-33.8,"mean":18.0,"max":71.1,"count":3,"sum":54.0},
+{"station":"Say \"hi\"","min":0.5,"mean":0.5,"max":0.5,"count":1,"sum":0.5},
+{"station":"Washington, D.C.","min":-0.2,"mean":-0.1,"max":-0.1,"count":2,"sum":-0.3},
+{"station":"Zürich","min":-99.9,"mean":0.0,"max":99.9,"count":3,"sum":0.0}
+]
+"#
+    );
+    assert_eq!(render(&Report::default(), Format::Json), "[\n]\n");
+}
+
+#[test]
+fn ndjson() {
+    let out = render(&sample(), Format::Ndjson);
+    let json = render(&sample(), Format::Json);
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines.len(), 4);
+    for line in lines {
+        assert!(json.contains(line));
+    }
+}
+
+#[test]
+fn csv() {
+    assert_eq!(
+        render(&sample(), Format::Csv),
+        "station,min,mean,max,count,sum
+Abha,-33.8,18.0,71.1,3,54.0
+\"Say \"\"hi\"\"\",0.5,0.5,0.5,1,0.5
+\"Washington, D.C.\",-0.2,-0.1,-0.1,2,-0.3
+Zürich,-99.9,0.0,99.9,3,0.0
+"
+    );
+}
+
+#[test]
+fn control_characters_are_escaped_in_json() {
+    let mut report = Report::default();
+    report.merge_station("a\tb\u{1}", &stats(&[1]));
+    assert!(render(&report, Format::Ndjson).starts_with(r#"{"station":"a\tb\u0001","#));
+}
+
+#[test]
+fn ordering_is_by_bytes() {
+    let mut report = Report::default();
+    for name in ["É", "a", "Z"] {
+        report.merge_station(name, &stats(&[1]));
+    }
+    let names: Vec<_> = render(&report, Format::Csv)
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').next().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["Z", "a", "É"]);
+}
+
+#[test]
+fn parse_format() {
+    assert_eq!("json".parse(), Ok(Format::Json));
+    assert_eq!("ndjson".parse(), Ok(Format::Ndjson));
+    assert_eq!("csv".parse(), Ok(Format::Csv));
+    assert_eq!("challenge".parse(), Ok(Format::Challenge));
+    assert!("xml".parse::<Format>().is_err());
+}
+
+#[test]
+fn candidate_22_writes_the_requested_format() {
+    let data = b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n";
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(data).unwrap();
+
+    let expected = aggregate(data, Options::default());
+    for (name, format) in [
+        ("json", Format::Json),
+        ("csv", Format::Csv),
+        ("ndjson", Format::Ndjson),
+    ] {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(file.path())
+            .args(["--format", name])
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        assert_eq!(
+            String::from_utf8(out.stdout).unwrap(),
+            render(&expected, format)
+        );
+    }
+}
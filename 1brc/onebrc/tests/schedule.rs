@@ -0,0 +1,99 @@
+//! Guided self-scheduling: chunks start large and shrink towards the end of
+//! the input, and the report does not depend on how it was shared out.
+
+mod common;
+
+use common::{options, sample};
+use onebrc::chunk::{GUIDED_FACTOR, Guided, claim_guided, split_tail};
+use onebrc::validate::validate;
+use onebrc::{Report, StationStats, try_aggregate_with};
+use std::sync::atomic::AtomicUsize;
+
+#[test]
+fn chunk_sizes() {
+    let guided = Guided::new(1 << 20, 4);
+    assert_eq!(guided.divisor, 4 * GUIDED_FACTOR);
+    assert_eq!(guided.chunk_size(80 << 20), 10 << 20);
+    assert_eq!(guided.chunk_size(3 << 20), 1 << 20);
+    assert_eq!(guided.chunk_size(0), 1 << 20);
+
+    // A worker on a core half as fast takes half of everything.
+    let slow = guided.scaled_to(1 << 19);
+    assert_eq!(slow, Guided::new(1 << 19, 8));
+    assert_eq!(slow.chunk_size(80 << 20), 5 << 20);
+    assert_eq!(guided.scaled_to(1 << 20), guided);
+
+    assert_eq!(Guided::new(0, 0), Guided::new(1, 1));
+}
+
+#[test]
+fn chunks_shrink() {
+    let data = sample(200_000, 4);
+    let body = &data[..split_tail(&data)];
+    let guided = Guided::new(4096, 4);
+    let next = AtomicUsize::new(0);
+
+    let mut chunks = Vec::new();
+    let mut expected_start = 0;
+    while let Some((start, end)) = claim_guided(body, &next, guided) {
+        assert_eq!(start, expected_start);
+        assert_eq!(body[end - 1], b'\n');
+        chunks.push(end - start);
+        expected_start = end;
+    }
+    assert_eq!(expected_start, body.len());
+
+    // Each chunk is its share of what was left, give or take the snap to a
+    // newline; the last ones are the minimum.
+    assert!(chunks[0] >= body.len() / guided.divisor);
+    for pair in chunks.windows(2) {
+        assert!(pair[1] <= pair[0] + 128, "{chunks:?}");
+    }
+    let last = &chunks[chunks.len() - 6..chunks.len() - 1];
+    assert!(last.iter().all(|&c| c < 4096 + 128), "{last:?}");
+    assert!(chunks.len() < body.len() / 4096 / 2, "{}", chunks.len());
+}
+
+#[test]
+fn reports_agree() {
+    let data = sample(100_000, 4);
+    let expected: Report = try_aggregate_with(&data, options(1, data.len())).unwrap();
+    for (threads, chunk_size) in [(2, 1), (5, 4096), (16, 100_000)] {
+        let options = options(threads, chunk_size);
+        assert_eq!(try_aggregate_with(&data, options).unwrap(), expected);
+        assert_eq!(validate::<StationStats>(&data, options, 0).report, expected);
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn candidate_22_logs_workers() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(&input, sample(20_000, 4)).unwrap();
+
+    let checkpoint = dir.path().join("run.ckpt");
+    let checkpoint = checkpoint.to_str().unwrap();
+    let segments = ["--checkpoint", checkpoint, "--checkpoint-every", "16K"];
+    // Once per worker, also over many checkpoint segments.
+    for args in [&[][..], &segments] {
+        let out = std::process::Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--no-fork", "--threads", "3", "--chunk-size", "8K"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        for index in 0..3 {
+            let prefix = format!("thread {index}: ");
+            let mut lines = stderr.lines().filter(|l| l.starts_with(&prefix));
+            let line = lines.next().unwrap_or_else(|| panic!("{stderr}"));
+            assert!(
+                line.contains(" chunks, ") && line.contains(", idle "),
+                "{line}"
+            );
+            assert_eq!(lines.next(), None, "{stderr}");
+        }
+    }
+}
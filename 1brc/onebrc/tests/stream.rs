@@ -0,0 +1,122 @@
+//! The streaming backend gives the same reports as the mmap path.
+
+mod common;
+
+use common::options;
+use onebrc::generate::write_rows;
+use onebrc::stream::aggregate_reader;
+use onebrc::{ExtendedStats, Options, Report, StationStats, aggregate, aggregate_with};
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+fn streamed(data: &[u8], options: Options) -> Report {
+    aggregate_reader::<StationStats, _>(data, options).unwrap()
+}
+
+/// Hands out at most `step` bytes per `read`, like a slow pipe.
+struct Trickle<'a> {
+    data: &'a [u8],
+    step: usize,
+}
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.step.min(buf.len()).min(self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+#[test]
+fn matches_the_mmap_path() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 30_000, 11, 2).unwrap();
+    let expected = aggregate(&data, Options::default());
+
+    for threads in [1, 3] {
+        for chunk_size in [1, 7, 4096, 1 << 20] {
+            let report = streamed(&data, options(threads, chunk_size));
+            assert_eq!(
+                report.stations, expected.stations,
+                "{threads} threads, chunk size {chunk_size}"
+            );
+        }
+    }
+}
+
+#[test]
+fn extended_stats_match_the_mmap_path() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 50_000, 12, 1).unwrap();
+    let expected = aggregate_with::<ExtendedStats>(&data, options(2, 4096));
+    let report = aggregate_reader::<ExtendedStats, _>(&data[..], options(2, 4096)).unwrap();
+    assert_eq!(report.stations, expected.stations);
+}
+
+#[test]
+fn short_reads() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 20_000, 13, 1).unwrap();
+    let expected = aggregate(&data, Options::default());
+    for step in [1, 3, 4097] {
+        let reader = Trickle { data: &data, step };
+        let report = aggregate_reader::<StationStats, _>(reader, options(2, 1000)).unwrap();
+        assert_eq!(report.stations, expected.stations, "step {step}");
+    }
+}
+
+#[test]
+fn edge_cases() {
+    let long = format!("{};1.0\n", "x".repeat(5000));
+    for data in [
+        &b""[..],
+        b"\n",
+        b"Hamburg;12.0",
+        b"Hamburg;12.0\nBulawayo;-8.9",
+        b"a;0.0\nb;-99.9\na;99.9\n",
+        long.as_bytes(),
+    ] {
+        let expected = aggregate(data, Options::default());
+        for chunk_size in [1, 16, 1 << 20] {
+            assert_eq!(
+                streamed(data, options(2, chunk_size)).stations,
+                expected.stations,
+                "{:?}, chunk size {chunk_size}",
+                String::from_utf8_lossy(data)
+            );
+        }
+    }
+}
+
+#[test]
+fn candidate_22_streams_stdin() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 20_000, 14, 1).unwrap();
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&data).unwrap();
+
+    let from_file = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(from_file.status.success());
+
+    for extra in [&[][..], &["--no-fork"]] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg("-")
+            .args(extra)
+            .args(["--chunk-size", "4K"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let input = data.clone();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let out = child.wait_with_output().unwrap();
+        writer.join().unwrap().unwrap();
+        assert!(out.status.success());
+        assert_eq!(out.stdout, from_file.stdout);
+    }
+}
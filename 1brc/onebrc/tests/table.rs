@@ -0,0 +1,140 @@
+//! `NameTable` grows past its initial capacity: a million and more distinct
+//! names, found again while the old slots are still being moved over.
+
+mod common;
+
+use common::options;
+use onebrc::chunk::PADDING;
+use onebrc::generate::SplitMix64;
+use onebrc::stream::aggregate_reader;
+use onebrc::table::MAX_LOAD;
+use onebrc::{NameTable, Report, StationStats, try_aggregate_with};
+
+const KEYS: usize = 1_200_000;
+
+/// Every sensor has one fixed temperature, in tenths.
+fn temperature(id: usize) -> i16 {
+    (id % 1999) as i16 - 999
+}
+
+fn name(id: usize) -> String {
+    match id % 5 {
+        0 => format!("sensor/long-prefix-{id:07}"),
+        _ => format!("s{id:07}"),
+    }
+}
+
+/// `rows` lines over `keys` sensor IDs in a shuffled order, every ID at least
+/// once, some longer than 16 bytes and differing only after their 16th byte.
+/// Also returns how often each ID occurs.
+fn sensors(keys: usize, rows: usize, seed: u64) -> (Vec<u8>, Vec<usize>) {
+    let mut rng = SplitMix64::new(seed);
+    let mut ids: Vec<usize> = (0..rows)
+        .map(|i| if i < keys { i } else { rng.below(keys) })
+        .collect();
+    for i in (1..ids.len()).rev() {
+        ids.swap(i, rng.below(i + 1));
+    }
+    let mut counts = vec![0; keys];
+    let mut data = Vec::with_capacity(rows * 24);
+    for id in ids {
+        counts[id] += 1;
+        let temp = temperature(id);
+        let sign = if temp < 0 { "-" } else { "" };
+        let (whole, tenths) = (temp.abs() / 10, temp.abs() % 10);
+        let line = format!("{};{sign}{whole}.{tenths}\n", name(id));
+        data.extend_from_slice(line.as_bytes());
+    }
+    (data, counts)
+}
+
+/// Checks the stats of one sensor against what [`sensors`] wrote.
+fn check(name: &[u8], stats: &StationStats, counts: &[usize]) {
+    let name = std::str::from_utf8(name).unwrap();
+    let id: usize = name[name.len() - 7..].parse().unwrap();
+    let temp = temperature(id);
+    let expected = StationStats {
+        min: temp,
+        max: temp,
+        count: counts[id],
+        total: temp as i64 * counts[id] as i64,
+    };
+    assert_eq!(*stats, expected, "{name}");
+}
+
+fn check_report(report: &Report, counts: &[usize]) {
+    assert_eq!(report.stations.len(), counts.len());
+    for (name, stats) in &report.stations {
+        check(name.as_bytes(), stats, counts);
+    }
+}
+
+#[test]
+fn grows_to_a_million_names() {
+    let (mut data, counts) = sensors(KEYS, KEYS + KEYS / 4, 1);
+    let len = data.len();
+    data.resize(len + PADDING, 0);
+
+    let mut table = NameTable::<StationStats>::with_capacity(&data, 16);
+    let (mut start, mut migrating) = (0, 0);
+    for nl in memchr::memchr_iter(b'\n', &data[..len]) {
+        let semi = start + memchr::memchr(b';', &data[start..nl]).unwrap();
+        let name = std::str::from_utf8(&data[start..semi]).unwrap();
+        let id: usize = name[name.len() - 7..].parse().unwrap();
+        // `data` is padded above.
+        unsafe { table.get_or_insert_stats(start as u64, (semi - start) as u16) }
+            .record(temperature(id));
+        migrating += table.is_migrating() as usize;
+        start = nl + 1;
+    }
+
+    assert_eq!(table.len(), KEYS);
+    assert!(table.len() as f64 <= table.slots_len() as f64 * MAX_LOAD);
+    assert!(migrating > 0);
+    for (name, stats) in table.iter_entries() {
+        check(name, stats, &counts);
+    }
+}
+
+#[test]
+fn lookups_finish_the_migration() {
+    let mut data = Vec::new();
+    for id in 0..4096 {
+        data.extend_from_slice(format!("s{id:07};0.0\n").as_bytes());
+    }
+    data.resize(data.len() + PADDING, 0);
+    let name = |id: usize| ((id * 13) as u64, 8);
+
+    // Insert until a grow leaves more old slots than one insert moves over.
+    let mut table = NameTable::<StationStats>::with_capacity(&data, 16);
+    let mut names = 0;
+    while !(table.is_migrating() && table.slots_len() >= 1024) {
+        let (off, len) = name(names);
+        // `data` is padded above.
+        unsafe { table.get_or_insert_stats(off, len) }.record(0);
+        names += 1;
+    }
+
+    // Then only names it has already seen, as near the end of a large input.
+    for id in 0..names {
+        let (off, len) = name(id);
+        unsafe { table.get_or_insert_stats(off, len) }.record(0);
+    }
+    assert!(!table.is_migrating());
+    assert_eq!(table.len(), names);
+    assert!(table.iter_entries().all(|(_, stats)| stats.count == 2));
+}
+
+#[test]
+fn aggregates_a_million_names() {
+    let (data, counts) = sensors(KEYS, KEYS + KEYS / 4, 2);
+    let options = options(4, 1 << 16);
+    check_report(
+        &try_aggregate_with::<StationStats>(&data, options).unwrap(),
+        &counts,
+    );
+    check_report(
+        &aggregate_reader::<StationStats, _>(&data[..], options).unwrap(),
+        &counts,
+    );
+}
@@ -0,0 +1,140 @@
+//! The fast path must never read past the end of the input.
+
+mod common;
+
+use common::{from_report, reference};
+use memmap2::Mmap;
+use onebrc::{Options, aggregate};
+use std::io::Write;
+use std::ptr;
+
+const PAGE: usize = 4096;
+
+/// Map `data` so that its last byte is the last byte of a page, followed by a
+/// PROT_NONE guard page. Any read past the end faults.
+struct Guarded {
+    base: *mut libc::c_void,
+    len: usize,
+    offset: usize,
+}
+
+impl Guarded {
+    fn new(data: &[u8]) -> Self {
+        let pages = data.len().div_ceil(PAGE).max(1);
+        let len = (pages + 1) * PAGE;
+        unsafe {
+            let base = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(base, libc::MAP_FAILED);
+            assert_eq!(
+                libc::mprotect(base.add(pages * PAGE), PAGE, libc::PROT_NONE),
+                0
+            );
+            let offset = pages * PAGE - data.len();
+            ptr::copy_nonoverlapping(data.as_ptr(), (base as *mut u8).add(offset), data.len());
+            Self { base, len, offset }
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        let len = self.len - PAGE - self.offset;
+        unsafe { std::slice::from_raw_parts((self.base as *const u8).add(self.offset), len) }
+    }
+}
+
+impl Drop for Guarded {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base, self.len) };
+    }
+}
+
+fn check(data: &[u8]) {
+    let guarded = Guarded::new(data);
+    for threads in [1, 3] {
+        for chunk_size in [16, 100, 1 << 20] {
+            let report = aggregate(
+                guarded.bytes(),
+                Options {
+                    threads,
+                    chunk_size,
+                    ..Options::default()
+                },
+            );
+            assert_eq!(
+                from_report(&report),
+                reference(data),
+                "threads={threads} chunk_size={chunk_size}"
+            );
+        }
+    }
+}
+
+/// Lines of varying name length, padded with a filler line so the input is exactly `len` bytes.
+fn lines_of_len(len: usize, last: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut i = 0;
+    while data.len() + last.len() + 40 < len {
+        let name = "x".repeat(1 + i % 23);
+        writeln!(data, "{name};{}.{}", i % 100, i % 10).unwrap();
+        i += 1;
+    }
+    let filler = len - data.len() - last.len() - ";0.0\n".len();
+    writeln!(data, "{};0.0", "f".repeat(filler)).unwrap();
+    data.extend_from_slice(last.as_bytes());
+    assert_eq!(data.len(), len);
+    data
+}
+
+#[test]
+fn last_line_ends_on_page_boundary() {
+    for last in ["a;1.2\n", "b;-9.9\n", "Petropavlovsk-Kamchatsky;-99.9\n"] {
+        check(&lines_of_len(PAGE, last));
+        check(&lines_of_len(2 * PAGE, last));
+    }
+}
+
+#[test]
+fn long_name_at_the_end() {
+    let last = format!("{};99.9\n", "L".repeat(100));
+    check(&lines_of_len(PAGE, &last));
+}
+
+#[test]
+fn no_trailing_newline() {
+    for last in ["a;1.2", "Oslo;-3.4", "Ouagadougou;45.0"] {
+        check(&lines_of_len(PAGE, last));
+        check(&lines_of_len(PAGE - 3, last));
+    }
+    check(b"a;0.1");
+}
+
+#[test]
+fn tiny_inputs() {
+    check(b"");
+    check(b"a;0.0\n");
+    check(b"a;0.0\nb;-1.0\n");
+}
+
+#[test]
+fn mmapped_file_of_exactly_one_page() {
+    let data = lines_of_len(PAGE, "Tunis;12.3\n");
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&data).unwrap();
+    let map = unsafe { Mmap::map(&file).unwrap() };
+    assert_eq!(map.len(), PAGE);
+    let report = aggregate(
+        &map,
+        Options {
+            threads: 2,
+            chunk_size: 256,
+            ..Options::default()
+        },
+    );
+    assert_eq!(from_report(&report), reference(&data));
+}
@@ -0,0 +1,105 @@
+//! `--io-uring`: reading the file with io_uring and O_DIRECT, or pread, gives
+//! the same report as mapping it.
+
+mod common;
+
+use common::options;
+use onebrc::generate::write_rows;
+use onebrc::uring::{ReadMode, aggregate_file};
+use onebrc::{Options, Report, StationStats, try_aggregate_with};
+use std::process::Command;
+
+#[test]
+fn reads_like_mmap() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 200_000, 3, 5).unwrap();
+    // Lines longer than a chunk and its overlap, and no final '\n'.
+    for len in [5_000, 9_000, 100] {
+        data.extend_from_slice(&vec![b'x'; len]);
+        data.extend_from_slice(b";-1.5\n");
+        write_rows(&mut data, 1_000, len as u64, 1).unwrap();
+    }
+    data.extend_from_slice(b"Lonely;9.9");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("measurements.txt");
+    std::fs::write(&path, &data).unwrap();
+    let empty = dir.path().join("empty.txt");
+    std::fs::write(&empty, b"").unwrap();
+
+    for mode in [ReadMode::Direct, ReadMode::Buffered] {
+        for (threads, chunk_size) in [(1, 1 << 20), (3, 4096), (4, 10_000)] {
+            let options = options(threads, chunk_size);
+            let expected: Report = try_aggregate_with(&data, options).unwrap();
+            let (report, used) = aggregate_file(&path, options, mode).unwrap();
+            assert_eq!(report, expected, "{mode} {chunk_size}");
+            if mode == ReadMode::Buffered {
+                assert_eq!(used, ReadMode::Buffered);
+            }
+        }
+        let (report, _) = aggregate_file::<StationStats>(&empty, options(2, 4096), mode).unwrap();
+        assert!(report.stations.is_empty());
+    }
+    assert!(
+        aggregate_file::<StationStats>(
+            &dir.path().join("missing"),
+            options(1, 4096),
+            ReadMode::Direct
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn reports_the_first_invalid_record() {
+    let mut data = Vec::new();
+    write_rows(&mut data, 20_000, 1, 2).unwrap();
+    let bad = data.len() / 3;
+    let bad = bad + data[bad..].iter().position(|&b| b == b'\n').unwrap() + 1;
+    data.splice(bad..bad, b"Oslo;warm\n".iter().copied());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("measurements.txt");
+    std::fs::write(&path, &data).unwrap();
+    let options = Options {
+        input: "general".parse().unwrap(),
+        ..options(3, 4096)
+    };
+    let expected = try_aggregate_with::<StationStats>(&data, options).unwrap_err();
+    assert_eq!(expected.offset, bad as u64);
+    for mode in [ReadMode::Direct, ReadMode::Buffered] {
+        let err = aggregate_file::<StationStats>(&path, options, mode).unwrap_err();
+        assert_eq!(err.to_string(), expected.to_string(), "{mode}");
+    }
+}
+
+#[test]
+fn candidate_22_io_uring() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    let mut data = Vec::new();
+    write_rows(&mut data, 30_000, 1, 8).unwrap();
+    std::fs::write(&input, &data).unwrap();
+
+    let run = |input: &std::path::Path, args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(input)
+            .args(["--no-fork", "--threads", "2", "--chunk-size", "8K"])
+            .args(args)
+            .output()
+            .unwrap();
+        (out.status.success(), String::from_utf8(out.stdout).unwrap())
+    };
+    let (ok, expected) = run(&input, &[]);
+    assert!(ok);
+    assert_eq!(run(&input, &["--io-uring"]), (true, expected.clone()));
+    let args = ["--io-uring", "--format", "csv", "--stats", "extended"];
+    assert_eq!(run(&input, &args), run(&input, &args[1..]));
+
+    assert!(!run(&input, &["--io-uring", "--stream"]).0);
+    assert!(!run(&input, &["--io-uring", "--validate"]).0);
+    assert!(!run(std::path::Path::new("-"), &["--io-uring"]).0);
+    let gzip = dir.path().join("measurements.txt.gz");
+    std::fs::write(&gzip, [0x1f, 0x8b, 8, 0]).unwrap();
+    assert!(!run(&gzip, &["--io-uring"]).0);
+}
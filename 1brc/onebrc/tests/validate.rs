@@ -0,0 +1,198 @@
+//! `--validate` and `--skip-invalid`: every line is checked, invalid ones are
+//! reported with their line number and left out of the report.
+
+mod common;
+
+use common::sample;
+use onebrc::parse::InputFormat;
+use onebrc::validate::{Rejection, check_record, validate};
+use onebrc::{ExtendedStats, Options, StationStats, aggregate_with};
+use std::process::Command;
+
+const BAD: [(&[u8], &str); 9] = [
+    (b"", "empty line"),
+    (b"Oslo 12.3", "missing separator"),
+    (b"Oslo;", "temperature is not a number"),
+    (b"Oslo;1e", "temperature has a malformed exponent"),
+    (b"Oslo;100.0", "temperature is out of range"),
+    (b"Oslo;12.34", "temperature is not formatted like -12.3"),
+    (b"Oslo;+1.0", "temperature is not formatted like -12.3"),
+    (b"Os\xfflo;1.0", "station name is not UTF-8"),
+    (b"Oslo;-", "temperature is not a number"),
+];
+
+#[test]
+fn checks_records() {
+    let format = InputFormat::default();
+    for (line, name, temp) in [
+        (&b"Oslo;12.3"[..], &b"Oslo"[..], 123),
+        (b"Oslo;-0.5", b"Oslo", -5),
+        (b"Oslo;-99.9", b"Oslo", -999),
+        (b"Oslo;07.0", b"Oslo", 70),
+        (b"Oslo;1.0;extra", b"Oslo", 10),
+        (b";1.0", b"", 10),
+    ] {
+        let (key, t) = check_record(line, &format).unwrap();
+        assert_eq!((&line[key], t), (name, temp));
+    }
+    for (line, reason) in BAD {
+        assert_eq!(
+            check_record(line, &format),
+            Err(reason),
+            "{}",
+            String::from_utf8_lossy(line)
+        );
+    }
+    let long = [&[b'x'; 101][..], b";1.0"].concat();
+    assert_eq!(
+        check_record(&long, &format),
+        Err("station name is longer than 100 bytes")
+    );
+    assert!(check_record(&long[1..], &format).is_ok());
+
+    let general: InputFormat = "general,sep=tab,key=1,value=0,crlf".parse().unwrap();
+    let (key, temp) = check_record(b"101.25\tOslo\r", &general).unwrap();
+    assert_eq!((key, temp), (7..11, 1013));
+    assert_eq!(check_record(b"\r", &general), Err("empty line"));
+    assert_eq!(check_record(b"1.0\r", &general), Err("missing separator"));
+    assert_eq!(check_record(b"1.0\tOslo\tx\r", &general), Ok((4..8, 10)));
+}
+
+#[test]
+fn reports_and_skips_invalid_lines() {
+    let good = sample(30_000, 1);
+    let expected = aggregate_with::<StationStats>(&good, Options::default());
+
+    // Put the bad lines among the good ones, one of them last without a '\n'.
+    let mut data = Vec::new();
+    let mut inserted = Vec::new();
+    let lines: Vec<_> = good.split_inclusive(|&b| b == b'\n').collect();
+    let every = lines.len() / BAD.len();
+    for (i, line) in lines.iter().enumerate() {
+        if i % every == 1 && inserted.len() < BAD.len() - 1 {
+            let (bad, reason) = BAD[inserted.len()];
+            let line_number = (i + inserted.len() + 1) as u64;
+            inserted.push((line_number, data.len() as u64, reason));
+            data.extend_from_slice(bad);
+            data.push(b'\n');
+        }
+        data.extend_from_slice(line);
+    }
+    let (bad, reason) = BAD[BAD.len() - 1];
+    inserted.push(((lines.len() + BAD.len()) as u64, data.len() as u64, reason));
+    data.extend_from_slice(bad);
+
+    let all: Vec<_> = inserted
+        .iter()
+        .map(|&(line, offset, reason)| Rejection {
+            line,
+            offset,
+            reason,
+        })
+        .collect();
+    for (threads, chunk_size) in [(1, 1 << 20), (4, 4096), (3, 100)] {
+        let options = Options {
+            threads,
+            chunk_size,
+            ..Options::default()
+        };
+        let validated = validate::<StationStats>(&data, options, 100);
+        assert_eq!(validated.report, expected, "chunks of {chunk_size}");
+        assert_eq!(validated.rejected, BAD.len() as u64);
+        assert_eq!(validated.first, all);
+
+        let validated = validate::<ExtendedStats>(&data, options, 3);
+        assert_eq!(validated.report, aggregate_with(&good, options));
+        assert_eq!(validated.rejected, BAD.len() as u64);
+        assert_eq!(validated.first, all[..3]);
+    }
+}
+
+#[test]
+fn candidate_22_validate() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(&input, b"Oslo;7.0\nOslo 7.0\n\nLima;1.25\nLima;-1.5").unwrap();
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(&input)
+            .args(["--format", "csv", "--no-fork"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let stderr = |out: &std::process::Output| {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        // Leaves out `total threads: N` and, with `--features metrics`, the
+        // `thread N: ...` lines.
+        let lines = stderr
+            .lines()
+            .filter(|l| !l.starts_with("total threads") && !l.starts_with("thread "));
+        lines.map(|l| format!("{l}\n")).collect::<String>()
+    };
+    let out = run(&["--validate"]);
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty());
+    assert_eq!(
+        stderr(&out),
+        "line 2 (byte 9): missing separator\n\
+         line 3 (byte 18): empty line\n\
+         line 4 (byte 19): temperature is not formatted like -12.3\n\
+         Error: Custom { kind: InvalidData, error: \"3 invalid lines\" }\n"
+    );
+
+    let out = run(&["--validate", "--max-errors", "1", "--skip-invalid"]);
+    assert!(out.status.success());
+    assert_eq!(
+        stderr(&out),
+        "line 2 (byte 9): missing separator\n\
+         ... and 2 more invalid lines\n\
+         skipped 3 invalid lines\n"
+    );
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "station,min,mean,max,count,sum\n\
+         Lima,-1.5,-1.5,-1.5,1,-1.5\n\
+         Oslo,7.0,7.0,7.0,1,7.0\n"
+    );
+
+    let out = run(&["--skip-invalid"]);
+    assert!(out.status.success());
+    assert_eq!(stderr(&out), "skipped 3 invalid lines\n");
+
+    std::fs::write(&input, b"Oslo;7.0\n").unwrap();
+    assert!(run(&["--validate"]).status.success());
+}
+
+#[test]
+fn candidate_22_fails_when_forked() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(&input, b"Oslo;7.0\nOslo 7.0\n").unwrap();
+
+    let run = |input: &std::path::Path, args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_candidate_22"))
+            .arg(input)
+            .args(args)
+            .output()
+            .unwrap();
+        (out.status.code(), out.stdout)
+    };
+    for format in ["challenge", "csv"] {
+        let args = ["--format", format];
+        assert_eq!(
+            run(&input, &[&args[..], &["--validate"]].concat()),
+            (Some(1), vec![])
+        );
+        let general = [&args[..], &["--input-format", "general"]].concat();
+        assert_eq!(run(&input, &general), (Some(1), vec![]));
+        let missing = dir.path().join("missing.txt");
+        assert_eq!(run(&missing, &args), (Some(1), vec![]));
+    }
+
+    std::fs::write(&input, b"Oslo;7.0\n").unwrap();
+    let (code, stdout) = run(&input, &["--validate"]);
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, b"{Oslo=7.0/7.0/7.0}\n");
+}